@@ -0,0 +1,388 @@
+//! Moving the working tree and index from one commit's tree to another's, as `checkout` does.
+//!
+//! Only files that differ between the two trees are touched, so changes to other files are
+//! carried over. Changes that checking out would overwrite, in tracked files or untracked ones
+//! in the way, are found before anything is written.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ffi::OsStr,
+    fs::{self, Metadata},
+    os::unix::{ffi::OsStrExt, fs::PermissionsExt},
+    path::Path,
+};
+
+use crate::{
+    error::{Error, Result},
+    ignore::Ignore,
+    index::{self, Index, IndexEntry},
+    object::{read_tree_recursive, Object, TreeEntry},
+    repository::Repository,
+    util::is_missing,
+    worktree::{self, FileModes},
+};
+
+/// The changes needed to check out a tree, worked out before any are made.
+///
+/// ```no_run
+/// use git_starter_rust::{checkout::Checkout, index::Index, Object, Repository};
+///
+/// let repo = Repository::discover()?;
+/// let mut index = Index::read(&repo)?;
+/// let head = Object::read_commit(&repo, &repo.read_ref("HEAD")?.unwrap())?;
+/// let target = Object::read_commit(&repo, &repo.read_ref("refs/heads/main")?.unwrap())?;
+/// let checkout = Checkout::new(&repo, &index, Some(&head.tree_hash), &target.tree_hash, false)?;
+/// if checkout.is_blocked() {
+///     println!("local changes to {:?} would be lost", checkout.modified);
+/// } else {
+///     checkout.apply(&repo, &mut index)?;
+///     index.write(&repo)?;
+/// }
+/// # Ok::<(), git_starter_rust::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct Checkout {
+    /// Files to write, with the tree entries to write them from
+    updates: Vec<(String, TreeEntry)>,
+    /// Files to delete
+    removals: Vec<String>,
+    /// How to write files' modes
+    modes: FileModes,
+    /// Tracked files with staged or unstaged changes that checking out would lose
+    pub modified: Vec<String>,
+    /// Untracked files (that aren't ignored) that checking out would overwrite
+    pub untracked: Vec<String>,
+}
+
+impl Checkout {
+    /// Work out how to move from the tree `from` (or nothing, for an unborn branch) to the tree
+    /// `to`, given the index.
+    ///
+    /// With `force`, local changes are thrown away rather than blocking the checkout: every file
+    /// ends up as it is in `to`, and any others in the index are removed.
+    pub fn new(
+        repo: &Repository,
+        index: &Index,
+        from: Option<&str>,
+        to: &str,
+        force: bool,
+    ) -> Result<Self> {
+        let work_tree = repo.work_tree()?;
+        let index_mtime = index::index_mtime(&repo.index_path());
+        let modes = FileModes::load(repo)?;
+        let ignore = Ignore::new(repo)?;
+        let old_files = match from {
+            Some(from) => read_tree_recursive(repo, from)?,
+            None => BTreeMap::new(),
+        };
+        let new_files = read_tree_recursive(repo, to)?;
+
+        let mut paths: BTreeSet<&str> = old_files
+            .keys()
+            .chain(new_files.keys())
+            .map(String::as_str)
+            .collect();
+        // Files that are only in the index are kept, unless everything is being reset
+        if force {
+            paths.extend(index.entries().iter().map(|e| e.path.as_str()));
+        }
+
+        let mut checkout = Checkout {
+            updates: Vec::new(),
+            removals: Vec::new(),
+            modes,
+            modified: Vec::new(),
+            untracked: Vec::new(),
+        };
+        for path in paths {
+            let old = old_files.get(path);
+            let new = new_files.get(path);
+            let staged = index.find(path, 0);
+            let conflicted = index
+                .entries()
+                .iter()
+                .any(|e| e.path == path && e.stage != 0);
+            let is_clean = |entry: &IndexEntry| -> Result<bool> {
+                Ok(!worktree::is_modified(
+                    work_tree,
+                    entry,
+                    index_mtime,
+                    &modes,
+                )?)
+            };
+
+            if force {
+                // Files that are already as they should be needn't be rewritten
+                let up_to_date = match (staged, new) {
+                    (Some(staged), Some(new)) => {
+                        !conflicted && same_as(staged, new) && is_clean(staged)?
+                    }
+                    _ => false,
+                };
+                match new {
+                    Some(new) if !up_to_date => {
+                        checkout.updates.push((path.to_owned(), new.clone()))
+                    }
+                    Some(_) => (),
+                    None => checkout.removals.push(path.to_owned()),
+                }
+                continue;
+            }
+
+            // Files that are the same in both trees keep any local changes
+            if old == new {
+                continue;
+            }
+            if conflicted {
+                checkout.modified.push(path.to_owned());
+                continue;
+            }
+            match (staged, new) {
+                // Already staged as it is in the new tree
+                (Some(staged), Some(new)) if same_as(staged, new) => continue,
+                (None, None) => continue,
+                _ => (),
+            }
+            let staged_matches_old = match (staged, old) {
+                (Some(staged), Some(old)) => same_as(staged, old),
+                (None, None) => true,
+                _ => false,
+            };
+            if !staged_matches_old {
+                checkout.modified.push(path.to_owned());
+                continue;
+            }
+            match staged {
+                // A file that's been deleted has no changes to lose
+                Some(staged) if exists(&work_tree.join(path))? && !is_clean(staged)? => {
+                    checkout.modified.push(path.to_owned());
+                    continue;
+                }
+                Some(_) => (),
+                None => {
+                    if is_in_the_way(work_tree, index, &ignore, path)? {
+                        checkout.untracked.push(path.to_owned());
+                        continue;
+                    }
+                }
+            }
+            match new {
+                Some(new) => checkout.updates.push((path.to_owned(), new.clone())),
+                None => checkout.removals.push(path.to_owned()),
+            }
+        }
+
+        // An untracked file can also be in the way of a directory that needs creating, and is
+        // removed if it's ignored
+        let mut blocking = BTreeSet::new();
+        for (path, _) in checkout.updates.iter() {
+            for (i, _) in path.match_indices('/') {
+                let dir = &path[..i];
+                if old_files.contains_key(dir) || blocking.contains(dir) {
+                    continue;
+                }
+                let metadata = match fs::symlink_metadata(work_tree.join(dir)) {
+                    Ok(metadata) => metadata,
+                    Err(err) if is_missing(&err) => break,
+                    Err(err) => return Err(err.into()),
+                };
+                if !metadata.is_dir() {
+                    blocking.insert(dir.to_owned());
+                }
+            }
+        }
+        for path in blocking {
+            if force || ignore.is_ignored(&path, false)? {
+                checkout.removals.push(path);
+            } else {
+                checkout.untracked.push(path);
+            }
+        }
+        checkout.untracked.sort();
+
+        Ok(checkout)
+    }
+
+    /// Whether checking out would lose local changes, so shouldn't go ahead.
+    pub fn is_blocked(&self) -> bool {
+        !self.modified.is_empty() || !self.untracked.is_empty()
+    }
+
+    /// Make the changes to the working tree and index. Files are deleted first, so that files
+    /// can replace directories and the other way round.
+    pub fn apply(self, repo: &Repository, index: &mut Index) -> Result<()> {
+        let work_tree = repo.work_tree()?;
+        for path in self.removals.iter() {
+            worktree::remove_file(work_tree, path)?;
+            index.remove(path);
+        }
+        for (path, entry) in self.updates.iter() {
+            let mode = parse_mode(&entry.mode)?;
+            let metadata = checkout_file(repo, work_tree, path, &entry.hash, mode, &self.modes)?;
+            index.add(IndexEntry::new(path, &entry.hash, mode, &metadata));
+        }
+        Ok(())
+    }
+}
+
+/// Write a file in the working tree from the store, creating any directories it needs, and
+/// return its metadata for the index.
+///
+/// Executable files (mode `100755`) are made executable, symlinks (`120000`) are created as
+/// symlinks and gitlinks (`160000`) as empty directories, as far as `modes` allows.
+pub fn checkout_file(
+    repo: &Repository,
+    work_tree: &Path,
+    path: &str,
+    hash: &str,
+    mode: u32,
+    modes: &FileModes,
+) -> Result<Metadata> {
+    let file_path = work_tree.join(path);
+    if let Some(parent) = file_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // Clear the way, so as not to write through an old symlink. An empty directory might have
+    // been left where the file goes, too.
+    match fs::symlink_metadata(&file_path) {
+        Ok(metadata) if metadata.is_dir() && mode != 0o160000 => fs::remove_dir(&file_path)?,
+        Ok(metadata) if metadata.is_dir() => (),
+        Ok(metadata) if metadata.file_type().is_symlink() || mode & 0o170000 != 0o100000 => {
+            fs::remove_file(&file_path)?;
+        }
+        _ => (),
+    }
+
+    // The commit a gitlink points to is in another repository
+    if mode == 0o160000 {
+        fs::create_dir_all(&file_path)?;
+        return Ok(fs::symlink_metadata(&file_path)?);
+    }
+
+    let content = match Object::parse_from_hash(repo, hash)? {
+        Object::Blob(content) => content,
+        _ => {
+            return Err(Error::CorruptObject(format!(
+                "unexpected object in tree for '{}' (mode {:o}): {}",
+                path, mode, hash
+            )))
+        }
+    };
+    if mode == 0o120000 && modes.symlinks {
+        std::os::unix::fs::symlink(OsStr::from_bytes(&content), &file_path)?;
+        return Ok(fs::symlink_metadata(&file_path)?);
+    }
+
+    fs::write(&file_path, content)?;
+    let metadata = fs::symlink_metadata(&file_path)?;
+    if modes.executable_bit {
+        // Files can be executed by whoever can read them, as in git
+        let permissions = metadata.permissions().mode();
+        let wanted = match mode {
+            0o100755 => permissions | (permissions & 0o444) >> 2,
+            _ => permissions & !0o111,
+        };
+        if wanted != permissions {
+            fs::set_permissions(&file_path, fs::Permissions::from_mode(wanted))?;
+            return Ok(fs::symlink_metadata(&file_path)?);
+        }
+    }
+    Ok(metadata)
+}
+
+/// Whether an index entry has the same contents and mode as a tree entry.
+fn same_as(staged: &IndexEntry, entry: &TreeEntry) -> bool {
+    staged.hash == entry.hash && parse_mode(&entry.mode).is_ok_and(|mode| mode == staged.mode)
+}
+
+fn parse_mode(mode: &str) -> Result<u32> {
+    u32::from_str_radix(mode, 8).map_err(|_| Error::CorruptObject(format!("invalid mode {}", mode)))
+}
+
+/// Whether there's an untracked file, or a directory with untracked files in it, where a new
+/// file needs to go. Ignored files don't count, as they can be recreated.
+fn is_in_the_way(work_tree: &Path, index: &Index, ignore: &Ignore, path: &str) -> Result<bool> {
+    let file_path = work_tree.join(path);
+    let metadata = match fs::symlink_metadata(&file_path) {
+        Ok(metadata) => metadata,
+        Err(err) if is_missing(&err) => return Ok(false),
+        Err(err) => return Err(err.into()),
+    };
+    if !metadata.is_dir() {
+        return Ok(!ignore.is_ignored(path, false)?);
+    }
+    // Tracked files in the directory are removed like any others
+    for file in worktree::walk(&file_path, None)? {
+        let file = format!("{}/{}", path, file);
+        if index.find(&file, 0).is_none() && !ignore.is_ignored(&file, false)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn exists(path: &Path) -> Result<bool> {
+    match fs::symlink_metadata(path) {
+        Ok(_) => Ok(true),
+        Err(err) if is_missing(&err) => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Checkout;
+    use crate::{
+        index::Index,
+        object::{Object, TreeEntry},
+        test_util::TempRepo,
+    };
+
+    #[test]
+    fn test_checkout() {
+        let repo = TempRepo::new("checkout");
+        let dir = repo.work_tree().unwrap();
+        let tree = |files: &[(&str, &str)]| {
+            let entries = files
+                .iter()
+                .map(|(name, content)| {
+                    let blob = Object::Blob(content.as_bytes().to_vec());
+                    blob.add(&repo).unwrap();
+                    TreeEntry {
+                        mode: "100644".to_owned(),
+                        name: name.to_string(),
+                        hash: blob.hash(),
+                    }
+                })
+                .collect();
+            let tree = Object::Tree(entries);
+            tree.add(&repo).unwrap();
+            tree.hash()
+        };
+        let one = tree(&[("a", "a\n"), ("b", "b\n")]);
+        let two = tree(&[("a", "a2\n"), ("c", "c\n")]);
+
+        let mut index = Index::new();
+        let checkout = Checkout::new(&repo, &index, None, &one, false).unwrap();
+        checkout.apply(&repo, &mut index).unwrap();
+        assert_eq!(std::fs::read_to_string(dir.join("b")).unwrap(), "b\n");
+        assert_eq!(index.entries().len(), 2);
+
+        // Changes to files that differ between the trees block the checkout
+        std::fs::write(dir.join("a"), "local\n").unwrap();
+        std::fs::write(dir.join("c"), "untracked\n").unwrap();
+        let checkout = Checkout::new(&repo, &index, Some(&one), &two, false).unwrap();
+        assert!(checkout.is_blocked());
+        assert_eq!(checkout.modified, ["a"]);
+        assert_eq!(checkout.untracked, ["c"]);
+
+        // Unless they're thrown away
+        let checkout = Checkout::new(&repo, &index, Some(&one), &two, true).unwrap();
+        checkout.apply(&repo, &mut index).unwrap();
+        assert_eq!(std::fs::read_to_string(dir.join("a")).unwrap(), "a2\n");
+        assert_eq!(std::fs::read_to_string(dir.join("c")).unwrap(), "c\n");
+        assert!(!dir.join("b").exists());
+        let paths = index.entries().iter().map(|e| e.path.as_str());
+        assert_eq!(paths.collect::<Vec<_>>(), ["a", "c"]);
+    }
+}
@@ -0,0 +1,731 @@
+//! Reading and editing git's configuration files.
+//!
+//! Variables are read from the system, global and repository (local) files in that order, with
+//! later values taking precedence, and `include.path` and `includeIf.<condition>.path` pulling in
+//! other files in place. Edits are made to the text of a single file, so that comments and layout
+//! survive them.
+
+use std::{
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::{Error, Result},
+    lockfile::write_locked,
+    repository::Repository,
+    wildmatch::wildmatch,
+};
+
+/// How deeply included files may themselves include other files.
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+/// Which config file a variable came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// `/etc/gitconfig`, or `GIT_CONFIG_SYSTEM`
+    System,
+    /// `~/.gitconfig` and `~/.config/git/config`, or `GIT_CONFIG_GLOBAL`
+    Global,
+    /// The repository's own `config` file
+    Local,
+}
+
+impl Scope {
+    /// The scope's name, as shown by `config list --show-scope`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Scope::System => "system",
+            Scope::Global => "global",
+            Scope::Local => "local",
+        }
+    }
+
+    /// The files variables in this scope are read from, in order. The last one is the file that
+    /// gets edited.
+    pub fn paths(&self, repo: Option<&Repository>) -> Vec<PathBuf> {
+        match self {
+            Scope::System => {
+                if std::env::var_os("GIT_CONFIG_NOSYSTEM").is_some() {
+                    return Vec::new();
+                }
+                let path = std::env::var_os("GIT_CONFIG_SYSTEM")
+                    .map_or_else(|| PathBuf::from("/etc/gitconfig"), PathBuf::from);
+                vec![path]
+            }
+            Scope::Global => {
+                if let Some(path) = std::env::var_os("GIT_CONFIG_GLOBAL") {
+                    return vec![PathBuf::from(path)];
+                }
+                let home = std::env::var_os("HOME").map(PathBuf::from);
+                let xdg_config_home = std::env::var_os("XDG_CONFIG_HOME")
+                    .map(PathBuf::from)
+                    .or_else(|| home.as_ref().map(|h| h.join(".config")));
+
+                let mut paths = Vec::new();
+                paths.extend(xdg_config_home.map(|d| d.join("git/config")));
+                paths.extend(home.map(|h| h.join(".gitconfig")));
+                paths
+            }
+            Scope::Local => repo
+                .map(|repo| vec![repo.git_dir().join("config")])
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// The combined configuration from some config files.
+#[derive(Debug, Default)]
+pub struct Config {
+    /// Variables in the order they were read, with later ones taking precedence
+    entries: Vec<Entry>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    /// Normalized key, i.e., `section[.subsection].name` with a lowercase section and name
+    key: String,
+    /// `None` for a variable given without `=`, which means true
+    value: Option<String>,
+    scope: Scope,
+}
+
+impl Config {
+    /// Read the system and global configuration, followed by the repository's if there is one.
+    pub fn load(repo: Option<&Repository>) -> Result<Self> {
+        let mut config = Config::default();
+        for scope in [Scope::System, Scope::Global, Scope::Local] {
+            for path in scope.paths(repo) {
+                config.read_file(&path, scope, repo, 0)?;
+            }
+        }
+        Ok(config)
+    }
+
+    /// Read just the files in one scope.
+    pub fn load_scope(scope: Scope, repo: Option<&Repository>) -> Result<Self> {
+        let mut config = Config::default();
+        for path in scope.paths(repo) {
+            config.read_file(&path, scope, repo, 0)?;
+        }
+        Ok(config)
+    }
+
+    /// Read a single file, attributing its variables to `scope`.
+    pub fn load_file(path: &Path, scope: Scope, repo: Option<&Repository>) -> Result<Self> {
+        let mut config = Config::default();
+        config.read_file(path, scope, repo, 0)?;
+        Ok(config)
+    }
+
+    /// Read a config file if it exists, along with any files it includes.
+    fn read_file(
+        &mut self,
+        path: &Path,
+        scope: Scope,
+        repo: Option<&Repository>,
+        depth: usize,
+    ) -> Result<()> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+        let (_, variables) = parse(&content).map_err(|line| {
+            Error::InvalidConfig(format!("bad config line {} in {}", line, path.display()))
+        })?;
+
+        for variable in variables {
+            let include = match (variable.key.as_str(), variable.value.as_deref()) {
+                ("include.path", Some(include)) => Some(include.to_owned()),
+                (key, Some(include)) if key.starts_with("includeif.") && key.ends_with(".path") => {
+                    let condition = &key["includeif.".len()..key.len() - ".path".len()];
+                    include_condition_holds(condition, path, repo).then(|| include.to_owned())
+                }
+                _ => None,
+            };
+
+            self.entries.push(Entry {
+                key: variable.key,
+                value: variable.value,
+                scope,
+            });
+
+            if let Some(include) = include {
+                if depth == MAX_INCLUDE_DEPTH {
+                    return Err(Error::InvalidConfig(format!(
+                        "exceeded maximum include depth in {}",
+                        path.display()
+                    )));
+                }
+                let include = resolve_include_path(&include, path);
+                self.read_file(&include, scope, repo, depth + 1)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the value of a variable, e.g., `user.name`. A variable given without a value (meaning
+    /// true) is returned as an empty string.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.get_all(key).last().copied()
+    }
+
+    /// Get all the values of a multivar, e.g., `remote.origin.fetch`, in order.
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        let key = normalize_key(key);
+        self.entries
+            .iter()
+            .filter(|e| e.key == key)
+            .map(|e| e.value.as_deref().unwrap_or(""))
+            .collect()
+    }
+
+    /// Get the value of a boolean variable, e.g., `core.bare`.
+    pub fn get_bool(&self, key: &str) -> Result<Option<bool>> {
+        let key = normalize_key(key);
+        let entry = match self.entries.iter().rev().find(|e| e.key == key) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        match entry
+            .value
+            .as_deref()
+            .map(str::to_ascii_lowercase)
+            .as_deref()
+        {
+            None | Some("true" | "yes" | "on" | "1") => Ok(Some(true)),
+            Some("false" | "no" | "off" | "0" | "") => Ok(Some(false)),
+            Some(value) => Err(Error::InvalidConfig(format!(
+                "bad boolean config value '{}' for '{}'",
+                value, key
+            ))),
+        }
+    }
+
+    /// All of the variables, in the order they were read, with their scopes.
+    pub fn entries(&self) -> impl Iterator<Item = (&str, Option<&str>, Scope)> {
+        self.entries
+            .iter()
+            .map(|e| (e.key.as_str(), e.value.as_deref(), e.scope))
+    }
+}
+
+/// Whether the condition of an `includeIf` section holds, e.g., `gitdir:~/work/` or
+/// `onbranch:release/*`.
+fn include_condition_holds(condition: &str, config_path: &Path, repo: Option<&Repository>) -> bool {
+    let repo = match repo {
+        Some(repo) => repo,
+        None => return false,
+    };
+
+    if let Some(pattern) = condition.strip_prefix("onbranch:") {
+        let head = fs::read_to_string(repo.git_dir().join("HEAD")).unwrap_or_default();
+        let branch = match head.trim_end().strip_prefix("ref: refs/heads/") {
+            Some(branch) => branch,
+            None => return false,
+        };
+        let mut pattern = pattern.to_owned();
+        if pattern.ends_with('/') {
+            pattern.push_str("**");
+        }
+        return wildmatch(&pattern, branch, false);
+    }
+
+    let (pattern, ignore_case) = if let Some(pattern) = condition.strip_prefix("gitdir:") {
+        (pattern, false)
+    } else if let Some(pattern) = condition.strip_prefix("gitdir/i:") {
+        (pattern, true)
+    } else {
+        return false;
+    };
+
+    let mut pattern = if let Some(rest) = pattern.strip_prefix("~/") {
+        match std::env::var_os("HOME") {
+            Some(home) => format!("{}/{}", Path::new(&home).display(), rest),
+            None => return false,
+        }
+    } else if let Some(rest) = pattern.strip_prefix("./") {
+        let dir = config_path.parent().unwrap_or(Path::new(""));
+        format!("{}/{}", dir.display(), rest)
+    } else if !pattern.starts_with('/') {
+        format!("**/{}", pattern)
+    } else {
+        pattern.to_owned()
+    };
+    if pattern.ends_with('/') {
+        pattern.push_str("**");
+    }
+
+    // Both the git directory as given and its real path can match
+    let git_dir = repo.git_dir();
+    let real_git_dir = fs::canonicalize(git_dir).unwrap_or_else(|_| git_dir.to_owned());
+    [git_dir, real_git_dir.as_path()]
+        .iter()
+        .any(|dir| wildmatch(&pattern, &dir.to_string_lossy(), ignore_case))
+}
+
+/// Resolve an included path, which may start with `~/` or be relative to the including file.
+fn resolve_include_path(include: &str, config_path: &Path) -> PathBuf {
+    if let Some(rest) = include.strip_prefix("~/") {
+        if let Some(home) = std::env::var_os("HOME") {
+            return Path::new(&home).join(rest);
+        }
+    }
+    config_path.parent().unwrap_or(Path::new("")).join(include)
+}
+
+/// A single config file, edited as text so that its comments and layout are kept.
+pub struct ConfigFile {
+    path: PathBuf,
+    content: String,
+}
+
+impl ConfigFile {
+    /// Open a config file for editing, which needn't exist yet.
+    pub fn open<P>(path: P) -> Result<Self>
+    where
+        P: Into<PathBuf>,
+    {
+        let path = path.into();
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err.into()),
+        };
+        Ok(ConfigFile { path, content })
+    }
+
+    /// Set a variable, replacing its value if it is already set. If it is a multivar, all of its
+    /// values are replaced with this one if `all` is set, or it is an error otherwise.
+    pub fn set(&mut self, key: &str, value: &str, all: bool) -> Result<()> {
+        let (_, name) = split_key(key)?;
+        let (_, variables) = self.parse()?;
+        let normalized_key = normalize_key(key);
+        let spans = variables
+            .into_iter()
+            .filter(|v| v.key == normalized_key)
+            .map(|v| v.span)
+            .collect::<Vec<_>>();
+
+        match spans.split_last() {
+            None => self.append(key, value),
+            Some((_, others)) if !others.is_empty() && !all => Err(Error::InvalidConfig(format!(
+                "cannot overwrite multiple values of '{}' with a single value",
+                normalized_key
+            ))),
+            Some((last, others)) => {
+                self.content
+                    .replace_range(last.clone(), &format_variable(name, value));
+                for span in others.iter().rev() {
+                    self.content.replace_range(span.clone(), "");
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Add a value to a variable, keeping any it already has.
+    pub fn append(&mut self, key: &str, value: &str) -> Result<()> {
+        let (section, name) = split_key(key)?;
+        let (sections, _) = self.parse()?;
+        let section_key = match section.split_once('.') {
+            Some((name, subsection)) => format!("{}.{}", name.to_ascii_lowercase(), subsection),
+            None => section.to_ascii_lowercase(),
+        };
+
+        let line = format_variable(name, value);
+        match sections.iter().rev().find(|s| s.name == section_key) {
+            Some(existing) => {
+                let mut insertion = line;
+                if existing.end > 0 && !self.content[..existing.end].ends_with('\n') {
+                    insertion.insert(0, '\n');
+                }
+                self.content.insert_str(existing.end, &insertion);
+            }
+            None => {
+                if !self.content.is_empty() && !self.content.ends_with('\n') {
+                    self.content.push('\n');
+                }
+                self.content.push_str(&format_section_header(section));
+                self.content.push_str(&line);
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove a variable, returning how many values were removed. If it is a multivar, all of its
+    /// values are removed if `all` is set, or it is an error otherwise.
+    pub fn unset(&mut self, key: &str, all: bool) -> Result<usize> {
+        split_key(key)?;
+        let (_, variables) = self.parse()?;
+        let key = normalize_key(key);
+        let spans = variables
+            .into_iter()
+            .filter(|v| v.key == key)
+            .map(|v| v.span)
+            .collect::<Vec<_>>();
+
+        if spans.len() > 1 && !all {
+            return Err(Error::InvalidConfig(format!(
+                "'{}' has multiple values",
+                key
+            )));
+        }
+        for span in spans.iter().rev() {
+            self.content.replace_range(span.clone(), "");
+        }
+        Ok(spans.len())
+    }
+
+    /// Write the file back, via a lock file so that concurrent edits can't be lost.
+    pub fn save(&self) -> Result<()> {
+        write_locked(&self.path, self.content.as_bytes())
+    }
+
+    fn parse(&self) -> Result<(Vec<Section>, Vec<Variable>)> {
+        parse(&self.content).map_err(|line| {
+            Error::InvalidConfig(format!(
+                "bad config line {} in {}",
+                line,
+                self.path.display()
+            ))
+        })
+    }
+}
+
+/// Split a key into its section (with any subsection) and variable name, checking that they
+/// are valid.
+fn split_key(key: &str) -> Result<(&str, &str)> {
+    let invalid = || Error::InvalidConfig(format!("invalid key: {}", key));
+    let (section, name) = key.rsplit_once('.').ok_or_else(invalid)?;
+    let section_name = section.split('.').next().unwrap();
+    let valid_section = !section_name.is_empty()
+        && section_name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-');
+    let valid_name = name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-');
+    if !valid_section || !valid_name || section.contains('\n') {
+        return Err(invalid());
+    }
+    Ok((section, name))
+}
+
+/// Section and variable names are case-insensitive, but subsection names aren't.
+fn normalize_key(key: &str) -> String {
+    match (key.find('.'), key.rfind('.')) {
+        (Some(first), Some(last)) => format!(
+            "{}{}{}",
+            key[0..first].to_ascii_lowercase(),
+            &key[first..last],
+            key[last..].to_ascii_lowercase()
+        ),
+        _ => key.to_ascii_lowercase(),
+    }
+}
+
+fn format_section_header(section: &str) -> String {
+    match section.split_once('.') {
+        Some((name, subsection)) => {
+            let subsection = subsection.replace('\\', "\\\\").replace('"', "\\\"");
+            format!("[{} \"{}\"]\n", name, subsection)
+        }
+        None => format!("[{}]\n", section),
+    }
+}
+
+/// Format a variable as a line of a config file, quoting and escaping its value if necessary.
+fn format_variable(name: &str, value: &str) -> String {
+    let needs_quotes = value.starts_with(char::is_whitespace)
+        || value.ends_with(char::is_whitespace)
+        || value.contains(['#', ';']);
+    let mut escaped = String::new();
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            c => escaped.push(c),
+        }
+    }
+    if needs_quotes {
+        format!("\t{} = \"{}\"\n", name, escaped)
+    } else {
+        format!("\t{} = {}\n", name, escaped)
+    }
+}
+
+/// A section header in a config file.
+#[derive(Debug)]
+struct Section {
+    /// Normalized name, i.e., `section[.subsection]` with a lowercase section
+    name: String,
+    /// Where new variables in the section should go, after its header or last variable
+    end: usize,
+}
+
+/// A variable in a config file, and the text that defines it.
+#[derive(Debug, PartialEq)]
+struct Variable {
+    key: String,
+    value: Option<String>,
+    /// From the start of its name to the end of its (last) line
+    span: Range<usize>,
+}
+
+/// Parse the contents of a config file into its sections and variables, returning the number of
+/// the first line that can't be parsed on failure.
+fn parse(content: &str) -> std::result::Result<(Vec<Section>, Vec<Variable>), usize> {
+    let mut sections: Vec<Section> = Vec::new();
+    let mut variables = Vec::new();
+
+    let mut lines = content
+        .split_inclusive('\n')
+        .scan(0, |offset, line| {
+            let start = *offset;
+            *offset += line.len();
+            Some((start, line))
+        })
+        .enumerate()
+        .peekable();
+    while let Some((i, (start, line))) = lines.next() {
+        let line_end = start + line.len();
+        // Variables span whole lines, unless they follow a section header on the same line
+        let mut span_start = start;
+        let mut rest = line.trim();
+        if rest.is_empty() || rest.starts_with(['#', ';']) {
+            continue;
+        }
+
+        if let Some(header) = rest.strip_prefix('[') {
+            let (name, after) = parse_section_header(header).ok_or(i + 1)?;
+            sections.push(Section {
+                name,
+                end: line_end,
+            });
+            rest = after.trim_start();
+            span_start = start + line.trim_end().len() - rest.len();
+            if rest.is_empty() || rest.starts_with(['#', ';']) {
+                continue;
+            }
+        }
+
+        let section = sections.last_mut().ok_or(i + 1)?;
+        let name_len = rest
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '-')
+            .unwrap_or(rest.len());
+        let name = &rest[0..name_len];
+        if !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            return Err(i + 1);
+        }
+        let key = format!("{}.{}", section.name, name.to_ascii_lowercase());
+
+        let after_name = rest[name_len..].trim_start();
+        let mut span_end = line_end;
+        let value = if after_name.is_empty() || after_name.starts_with(['#', ';']) {
+            None
+        } else if let Some(value) = after_name.strip_prefix('=') {
+            let next_line = || {
+                lines.next().map(|(_, (start, line))| {
+                    span_end = start + line.len();
+                    line.trim_end_matches(['\n', '\r'])
+                })
+            };
+            Some(parse_value(value, next_line).ok_or(i + 1)?)
+        } else {
+            return Err(i + 1);
+        };
+
+        section.end = span_end;
+        variables.push(Variable {
+            key,
+            value,
+            span: span_start..span_end,
+        });
+    }
+
+    Ok((sections, variables))
+}
+
+/// Parse a section header (after its opening `[`), e.g., `core]` or `remote "origin"]`, into
+/// a lowercase section name followed by any subsection, and the rest of the line.
+fn parse_section_header(header: &str) -> Option<(String, &str)> {
+    let name_len = header
+        .find(|c: char| !c.is_ascii_alphanumeric() && c != '-' && c != '.')
+        .unwrap_or(header.len());
+    let name = header[0..name_len].to_ascii_lowercase();
+    if name.is_empty() {
+        return None;
+    }
+
+    let rest = &header[name_len..];
+    if let Some(rest) = rest.strip_prefix(']') {
+        return Some((name, rest));
+    }
+
+    let mut subsection = String::new();
+    let mut chars = rest.strip_prefix(" \"")?.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => {
+                let rest = rest[2 + i + 1..].strip_prefix(']')?;
+                return Some((format!("{}.{}", name, subsection), rest));
+            }
+            '\\' => subsection.push(chars.next()?.1),
+            c => subsection.push(c),
+        }
+    }
+    None
+}
+
+/// Parse a value, handling quotes, escapes, comments and trailing backslashes that continue it
+/// onto the next line.
+fn parse_value<'a, F>(input: &'a str, mut next_line: F) -> Option<String>
+where
+    F: FnMut() -> Option<&'a str>,
+{
+    let mut output = String::new();
+    let mut in_quotes = false;
+    // Whitespace is only kept if something other than whitespace or a comment follows it
+    let mut pending_space = String::new();
+    let mut chars = input.trim_end_matches(['\n', '\r']).chars();
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() && !in_quotes {
+            if !output.is_empty() {
+                pending_space.push(c);
+            }
+            continue;
+        } else if matches!(c, '#' | ';') && !in_quotes {
+            break;
+        } else if c == '\\' && chars.as_str().is_empty() {
+            chars = next_line()?.chars();
+            continue;
+        }
+
+        output.push_str(&std::mem::take(&mut pending_space));
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '\\' => match chars.next()? {
+                'n' => output.push('\n'),
+                't' => output.push('\t'),
+                'b' => {
+                    output.pop();
+                }
+                c @ ('"' | '\\') => output.push(c),
+                _ => return None,
+            },
+            c => output.push(c),
+        }
+    }
+
+    (!in_quotes).then_some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize_key, parse, Config, ConfigFile, Scope};
+    use crate::test_util::TempDir;
+
+    fn parse_values(content: &str) -> Vec<(String, Option<String>)> {
+        let (_, variables) = parse(content).unwrap();
+        variables.into_iter().map(|v| (v.key, v.value)).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let content = "# comment\n\
+[user]\n\
+\tname = A U Thor  ; the author\n\
+\temail=\"author@example.com\"\n\
+[Remote \"Origin\"] url = https://example.com/\\\n\
+repo.git\n\
+[core]\n\
+\tbare\n\
+\tmessage = \"a # b\" \\t c\\\\\n\
+[remote \"origin\"]\n\
+\tfetch = a\n\
+\tfetch = b";
+        assert_eq!(
+            parse_values(content),
+            [
+                ("user.name".into(), Some("A U Thor".into())),
+                ("user.email".into(), Some("author@example.com".into())),
+                (
+                    "remote.Origin.url".into(),
+                    Some("https://example.com/repo.git".into())
+                ),
+                ("core.bare".into(), None),
+                ("core.message".into(), Some("a # b \t c\\".into())),
+                ("remote.origin.fetch".into(), Some("a".into())),
+                ("remote.origin.fetch".into(), Some("b".into())),
+            ]
+        );
+
+        assert!(matches!(parse("name = value"), Err(1)));
+        assert!(matches!(parse("[user]\n\tname = \"unterminated"), Err(2)));
+        assert!(matches!(parse("[user\n"), Err(1)));
+    }
+
+    #[test]
+    fn test_normalize_key() {
+        assert_eq!(normalize_key("User.Name"), "user.name");
+        assert_eq!(normalize_key("Remote.Origin.URL"), "remote.Origin.url");
+    }
+
+    #[test]
+    fn test_edit_keeps_comments() {
+        let mut file = ConfigFile {
+            path: "config".into(),
+            content: "# Settings\n[core]\n\tbare = false ; not bare\n\n# Who I am\n\
+[user]\n\tname = Old\n"
+                .into(),
+        };
+
+        file.set("core.bare", "true", false).unwrap();
+        file.set("user.email", "a@example.com", false).unwrap();
+        file.set("user.name", " New ", false).unwrap();
+        file.append("remote.origin.fetch", "+refs/heads/*").unwrap();
+        file.append("remote.origin.fetch", "+refs/tags/*").unwrap();
+        assert_eq!(
+            file.content,
+            "# Settings\n[core]\n\tbare = true\n\n# Who I am\n[user]\n\tname = \" New \"\n\
+\temail = a@example.com\n[remote \"origin\"]\n\tfetch = +refs/heads/*\n\tfetch = +refs/tags/*\n"
+        );
+
+        assert!(file.set("remote.origin.fetch", "x", false).is_err());
+        assert!(file.unset("remote.origin.fetch", false).is_err());
+        assert_eq!(file.unset("remote.origin.fetch", true).unwrap(), 2);
+        assert_eq!(file.unset("user.missing", false).unwrap(), 0);
+        assert_eq!(
+            file.content,
+            "# Settings\n[core]\n\tbare = true\n\n# Who I am\n[user]\n\tname = \" New \"\n\
+\temail = a@example.com\n[remote \"origin\"]\n"
+        );
+        assert!(file.set("bad key.name", "x", false).is_err());
+    }
+
+    #[test]
+    fn test_includes() {
+        let dir = TempDir::new("config-includes");
+        let dir = dir.path();
+        std::fs::write(
+            dir.join("config"),
+            "[user]\n\tname = First\n[include]\n\tpath = included\n[user]\n\temail = e\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("included"), "[user]\n\tname = Second\n").unwrap();
+
+        let config = Config::load_file(&dir.join("config"), Scope::Local, None).unwrap();
+        assert_eq!(config.get("user.name"), Some("Second"));
+        assert_eq!(config.get_all("user.name"), ["First", "Second"]);
+        assert_eq!(config.get("USER.EMAIL"), Some("e"));
+
+        // Including itself should hit the depth limit rather than looping forever
+        std::fs::write(dir.join("included"), "[include]\n\tpath = included\n").unwrap();
+        assert!(Config::load_file(&dir.join("config"), Scope::Local, None).is_err());
+    }
+}
@@ -0,0 +1,558 @@
+//! Dates and timezones: parsing the date formats git accepts, and finding the local timezone's
+//! offset from UTC without any help from the C library.
+
+use std::{path::Path, sync::OnceLock, time::SystemTime};
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+/// The current time, in seconds since the Unix epoch.
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// The offset of the local timezone from UTC at the given time, in minutes.
+///
+/// The timezone comes from `TZ` if it is set, or `/etc/localtime` otherwise. Anything that can't
+/// be understood is treated as UTC.
+pub fn local_offset(time: i64) -> i32 {
+    static LOCAL: OnceLock<Option<TimeZone>> = OnceLock::new();
+    LOCAL
+        .get_or_init(TimeZone::local)
+        .as_ref()
+        .map_or(0, |tz| tz.offset_at(time) / 60)
+}
+
+/// Parse a date in any of the formats git accepts for `GIT_AUTHOR_DATE` and
+/// `GIT_COMMITTER_DATE`, returning seconds since the epoch and the timezone offset in minutes:
+///
+/// - git's internal format, e.g., `1112911993 -0700` or `@1112911993`
+/// - RFC 2822, e.g., `Thu, 07 Apr 2005 22:13:13 +0200`
+/// - ISO 8601, e.g., `2005-04-07T22:13:13+02:00` or `2005-04-07 22:13:13`
+///
+/// Dates without a timezone are taken to be in the local timezone.
+pub fn parse_date(input: &str) -> Option<(i64, i32)> {
+    let input = input.trim();
+    if let Some(date) = parse_raw_date(input) {
+        return Some(date);
+    }
+
+    let (mut year, mut month, mut day) = (None, None, None);
+    let mut time = None;
+    let mut tz_offset = None;
+
+    let tokens = input
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|t| !t.is_empty())
+        .flat_map(split_iso_date_time);
+    for token in tokens {
+        let lower = token.to_ascii_lowercase();
+        if let Some((y, m, d)) = parse_iso_date(token) {
+            (year, month, day) = (Some(y), Some(m), Some(d));
+        } else if token.contains(':') && token.as_bytes()[0].is_ascii_digit() {
+            // A time, possibly with a timezone stuck onto it
+            let tz_start = token.find(['+', '-', 'Z', 'z']).unwrap_or(token.len());
+            time = Some(parse_time(&token[0..tz_start])?);
+            if tz_start < token.len() {
+                tz_offset = Some(parse_loose_offset(&token[tz_start..])?);
+            }
+        } else if let Some(offset) = parse_loose_offset(token) {
+            tz_offset = Some(offset);
+        } else if let Some(m) = MONTHS.iter().position(|m| lower.starts_with(m)) {
+            month = Some(m as u32 + 1);
+        } else if WEEKDAYS.iter().any(|d| lower.starts_with(d)) {
+            // The weekday is implied by the date
+        } else if token.bytes().all(|b| b.is_ascii_digit()) {
+            let n = token.parse::<i64>().ok()?;
+            match (day, token.len()) {
+                (None, 1 | 2) => day = Some(n as u32),
+                (_, 4) => year = Some(n),
+                _ => return None,
+            }
+        } else {
+            return None;
+        }
+    }
+
+    let (year, month, day) = (year?, month?, day?);
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+    let local = days_from_civil(year, month, day) * 86400 + time.unwrap_or(0);
+    let tz_offset = tz_offset.unwrap_or_else(|| {
+        // Find the offset in effect at that local time
+        let guess = local_offset(local);
+        local_offset(local - guess as i64 * 60)
+    });
+    Some((local - tz_offset as i64 * 60, tz_offset))
+}
+
+/// Parse git's internal date format, i.e., seconds since the epoch with an optional `@` and
+/// timezone offset.
+fn parse_raw_date(input: &str) -> Option<(i64, i32)> {
+    let input = input.strip_prefix('@').unwrap_or(input);
+    let (time, tz_offset) = match input.split_once(' ') {
+        Some((time, tz_offset)) => (time, Some(parse_offset(tz_offset.trim())?)),
+        None => (input, None),
+    };
+    // Eight or more digits, so that `20050407` isn't mistaken for a timestamp
+    if time.len() < 8 || !time.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let time = time.parse::<i64>().ok()?;
+    Some((time, tz_offset.unwrap_or_else(|| local_offset(time))))
+}
+
+/// Split ISO 8601's `2005-04-07T22:13:13` into its date and time.
+fn split_iso_date_time(token: &str) -> Vec<&str> {
+    match token.split_once(['T', 't']) {
+        Some((date, time)) if parse_iso_date(date).is_some() => vec![date, time],
+        _ => vec![token],
+    }
+}
+
+fn parse_iso_date(token: &str) -> Option<(i64, u32, u32)> {
+    let mut parts = token.split('-');
+    let year = parts.next().filter(|y| y.len() == 4)?.parse().ok()?;
+    let month = parts.next().filter(|m| m.len() == 2)?.parse().ok()?;
+    let day = parts.next().filter(|d| d.len() == 2)?.parse().ok()?;
+    match parts.next() {
+        Some(_) => None,
+        None => Some((year, month, day)),
+    }
+}
+
+/// Parse `hh:mm[:ss[.fraction]]` into seconds since midnight.
+fn parse_time(token: &str) -> Option<i64> {
+    let mut parts = token.split(':');
+    let hours = parts.next()?.parse::<i64>().ok()?;
+    let minutes = parts.next()?.parse::<i64>().ok()?;
+    let seconds = match parts.next() {
+        Some(seconds) => seconds.split('.').next()?.parse::<i64>().ok()?,
+        None => 0,
+    };
+    if parts.next().is_some() || hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+    Some(hours * 3600 + minutes * 60 + seconds)
+}
+
+/// Parse a timezone offset in the strict form used in signatures, e.g., `+0130`, into minutes.
+pub(crate) fn parse_offset(input: &str) -> Option<i32> {
+    let (sign, digits) = match input.as_bytes().first()? {
+        b'+' => (1, &input[1..]),
+        b'-' => (-1, &input[1..]),
+        _ => return None,
+    };
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let hours = digits[0..2].parse::<i32>().ok()?;
+    let minutes = digits[2..4].parse::<i32>().ok()?;
+    Some(sign * (hours * 60 + minutes))
+}
+
+/// Parse a timezone as it might appear in a date, e.g., `Z`, `UTC`, `+02:00`, `-07` or `+0530`.
+fn parse_loose_offset(input: &str) -> Option<i32> {
+    if ["z", "utc", "gmt"].contains(&input.to_ascii_lowercase().as_str()) {
+        return Some(0);
+    }
+    let sign = match input.as_bytes().first()? {
+        b'+' => "+",
+        b'-' => "-",
+        _ => return None,
+    };
+    match input[1..].replace(':', "") {
+        digits if digits.len() == 2 => parse_offset(&format!("{}{}00", sign, digits)),
+        digits => parse_offset(&format!("{}{}", sign, digits)),
+    }
+}
+
+/// Format a timezone offset in minutes as it appears in signatures, e.g., `-0700`.
+pub(crate) fn format_offset(tz_offset: i32) -> String {
+    let sign = if tz_offset < 0 { '-' } else { '+' };
+    let minutes = tz_offset.abs();
+    format!("{}{:02}{:02}", sign, minutes / 60, minutes % 60)
+}
+
+/// Format a time in git's default style, e.g., `Thu Apr 7 15:13:13 2005 -0700`, in the
+/// timezone given by its offset in minutes.
+pub(crate) fn format_date(time: i64, tz_offset: i32) -> String {
+    let local_time = time + tz_offset as i64 * 60;
+    let days = local_time.div_euclid(86400);
+    let seconds = local_time.rem_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+    // The epoch was a Thursday
+    let weekday = WEEKDAYS[(days + 4).rem_euclid(7) as usize];
+    let capitalize = |name: &str| name[0..1].to_uppercase() + &name[1..];
+    format!(
+        "{} {} {} {:02}:{:02}:{:02} {} {}",
+        capitalize(weekday),
+        capitalize(MONTHS[month as usize - 1]),
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        year,
+        format_offset(tz_offset)
+    )
+}
+
+/// Days since the epoch of a date in the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_from_march = (month as i64 + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The year, month and day of a number of days since the epoch.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = (month_from_march + 2) % 12 + 1;
+    let year = era * 400 + year_of_era + if month <= 2 { 1 } else { 0 };
+    (year, month as u32, day as u32)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// A timezone, as described by a TZif file (see `tzfile(5)`).
+struct TimeZone {
+    /// Times at which the offset changes, with the index of the new offset in `offsets`
+    transitions: Vec<(i64, usize)>,
+    /// Offsets from UTC in seconds, and whether they are daylight saving time
+    offsets: Vec<(i32, bool)>,
+    /// Rule for times after the last transition
+    rule: Option<PosixTimeZone>,
+}
+
+impl TimeZone {
+    fn local() -> Option<Self> {
+        match std::env::var("TZ") {
+            Ok(tz) if tz.is_empty() => None,
+            Ok(tz) => {
+                let tz = tz.strip_prefix(':').unwrap_or(&tz);
+                let path = Path::new("/usr/share/zoneinfo").join(tz);
+                match std::fs::read(path) {
+                    Ok(data) => TimeZone::parse(&data),
+                    Err(_) => PosixTimeZone::parse(tz).map(|rule| TimeZone {
+                        transitions: Vec::new(),
+                        offsets: Vec::new(),
+                        rule: Some(rule),
+                    }),
+                }
+            }
+            Err(_) => TimeZone::parse(&std::fs::read("/etc/localtime").ok()?),
+        }
+    }
+
+    fn parse(data: &[u8]) -> Option<Self> {
+        if data.get(0..4)? != b"TZif" {
+            return None;
+        }
+        let version = *data.get(4)?;
+        let counts = |data: &[u8]| -> Option<[usize; 6]> {
+            let mut counts = [0; 6];
+            for (i, count) in counts.iter_mut().enumerate() {
+                let bytes = data.get(20 + i * 4..24 + i * 4)?;
+                *count = u32::from_be_bytes(bytes.try_into().unwrap()) as usize;
+            }
+            Some(counts)
+        };
+        let block_len = |counts: [usize; 6], time_len: usize| {
+            let [is_ut, is_std, leap, time, types, chars] = counts;
+            44 + time * (time_len + 1) + types * 6 + chars + leap * (time_len + 4) + is_std + is_ut
+        };
+
+        // Version 2 files repeat everything with 64-bit times, followed by a rule
+        let (data, time_len) = if version >= b'2' {
+            (data.get(block_len(counts(data)?, 4)..)?, 8)
+        } else {
+            (data, 4)
+        };
+        let counts @ [_, _, _, time_count, type_count, _] = counts(data)?;
+
+        let times = data.get(44..44 + time_count * time_len)?;
+        let indices = data.get(44 + time_count * time_len..44 + time_count * (time_len + 1))?;
+        let mut transitions = Vec::with_capacity(time_count);
+        for (i, index) in indices.iter().enumerate() {
+            let time = &times[i * time_len..(i + 1) * time_len];
+            let time = match time_len {
+                8 => i64::from_be_bytes(time.try_into().unwrap()),
+                _ => i32::from_be_bytes(time.try_into().unwrap()) as i64,
+            };
+            if *index as usize >= type_count {
+                return None;
+            }
+            transitions.push((time, *index as usize));
+        }
+
+        let types_start = 44 + time_count * (time_len + 1);
+        let types = data.get(types_start..types_start + type_count * 6)?;
+        let offsets = types
+            .chunks(6)
+            .map(|t| (i32::from_be_bytes(t[0..4].try_into().unwrap()), t[4] != 0))
+            .collect();
+
+        let rule = match data.get(block_len(counts, time_len)..) {
+            Some(footer) if version >= b'2' => std::str::from_utf8(footer)
+                .ok()
+                .and_then(|f| PosixTimeZone::parse(f.trim_matches('\n'))),
+            _ => None,
+        };
+
+        Some(TimeZone {
+            transitions,
+            offsets,
+            rule,
+        })
+    }
+
+    /// The offset from UTC at the given time, in seconds.
+    fn offset_at(&self, time: i64) -> i32 {
+        let after_last = self.transitions.last().map_or(true, |(t, _)| time >= *t);
+        if let (true, Some(rule)) = (after_last, self.rule.as_ref()) {
+            return rule.offset_at(time);
+        }
+
+        match self.transitions.partition_point(|(t, _)| *t <= time) {
+            // Before the first transition, use the first standard time offset
+            0 => self
+                .offsets
+                .iter()
+                .find(|(_, is_dst)| !is_dst)
+                .or(self.offsets.first())
+                .map_or(0, |(offset, _)| *offset),
+            i => self.offsets[self.transitions[i - 1].1].0,
+        }
+    }
+}
+
+/// A POSIX `TZ` string, e.g., `EST5EDT,M3.2.0,M11.1.0`.
+struct PosixTimeZone {
+    /// Offset from UTC of standard time, in seconds
+    std_offset: i32,
+    /// Offset from UTC of daylight saving time, and when it starts and ends
+    dst: Option<(i32, DstRule, DstRule)>,
+}
+
+/// The day and time daylight saving time starts or ends, in the form `Mm.w.d/time`: the `w`th
+/// (or last, if 5) weekday `d` of month `m`.
+struct DstRule {
+    month: u32,
+    week: u32,
+    weekday: u32,
+    /// Local time of day, in seconds
+    time: i64,
+}
+
+impl PosixTimeZone {
+    fn parse(input: &str) -> Option<Self> {
+        let rest = skip_tz_name(input)?;
+        let (std_offset, rest) = parse_posix_offset(rest)?;
+        if rest.is_empty() {
+            return Some(PosixTimeZone {
+                std_offset,
+                dst: None,
+            });
+        }
+
+        let rest = skip_tz_name(rest)?;
+        let (dst_offset, rest) = match rest.starts_with(',') {
+            true => (std_offset + 3600, rest),
+            false => parse_posix_offset(rest)?,
+        };
+        let mut rules = rest.strip_prefix(',')?.split(',');
+        let start = DstRule::parse(rules.next()?)?;
+        let end = DstRule::parse(rules.next()?)?;
+
+        Some(PosixTimeZone {
+            std_offset,
+            dst: Some((dst_offset, start, end)),
+        })
+    }
+
+    fn offset_at(&self, time: i64) -> i32 {
+        let (dst_offset, start, end) = match self.dst.as_ref() {
+            Some(dst) => dst,
+            None => return self.std_offset,
+        };
+
+        let (year, _, _) = civil_from_days((time + self.std_offset as i64).div_euclid(86400));
+        let start = start.local_time(year) - self.std_offset as i64;
+        let end = end.local_time(year) - *dst_offset as i64;
+        let is_dst = if start < end {
+            start <= time && time < end
+        } else {
+            // Southern hemisphere, where daylight saving time spans the new year
+            !(end <= time && time < start)
+        };
+
+        if is_dst {
+            *dst_offset
+        } else {
+            self.std_offset
+        }
+    }
+}
+
+impl DstRule {
+    fn parse(input: &str) -> Option<Self> {
+        let (date, time) = match input.split_once('/') {
+            Some((date, time)) => (date, parse_posix_time(time)?),
+            None => (input, 2 * 3600),
+        };
+        // Julian day rules are rare enough not to bother with
+        let mut parts = date.strip_prefix('M')?.split('.');
+        let month = parts
+            .next()?
+            .parse()
+            .ok()
+            .filter(|m| (1..=12).contains(m))?;
+        let week = parts.next()?.parse().ok().filter(|w| (1..=5).contains(w))?;
+        let weekday = parts.next()?.parse().ok().filter(|d| *d <= 6)?;
+
+        Some(DstRule {
+            month,
+            week,
+            weekday,
+            time,
+        })
+    }
+
+    /// Seconds since the epoch, in local time, at which this rule takes effect in `year`.
+    fn local_time(&self, year: i64) -> i64 {
+        let first = days_from_civil(year, self.month, 1);
+        // The epoch was a Thursday
+        let first_weekday = (first + 4).rem_euclid(7) as u32;
+        let mut day = 1 + (self.weekday + 7 - first_weekday) % 7 + (self.week - 1) * 7;
+        while day > days_in_month(year, self.month) {
+            day -= 7;
+        }
+        (first + day as i64 - 1) * 86400 + self.time
+    }
+}
+
+/// Skip a timezone abbreviation, e.g., `EST` or `<+0530>`.
+fn skip_tz_name(input: &str) -> Option<&str> {
+    if let Some(rest) = input.strip_prefix('<') {
+        return rest.split_once('>').map(|(_, rest)| rest);
+    }
+    let len = input
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(input.len());
+    (len >= 3).then(|| &input[len..])
+}
+
+/// Parse a POSIX offset, which counts hours west of UTC, into seconds east of UTC.
+fn parse_posix_offset(input: &str) -> Option<(i32, &str)> {
+    let len = input
+        .find(|c: char| !matches!(c, '+' | '-' | ':' | '0'..='9'))
+        .unwrap_or(input.len());
+    let (sign, time) = match input[0..len].strip_prefix('-') {
+        Some(time) => (1, time),
+        None => (-1, input[0..len].trim_start_matches('+')),
+    };
+    Some((sign * parse_posix_time(time)? as i32, &input[len..]))
+}
+
+/// Parse `hh[:mm[:ss]]` into seconds.
+fn parse_posix_time(input: &str) -> Option<i64> {
+    let mut seconds = 0;
+    for (i, part) in input.split(':').enumerate() {
+        if i > 2 {
+            return None;
+        }
+        seconds += part.parse::<i64>().ok()? * [3600, 60, 1][i];
+    }
+    Some(seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{civil_from_days, days_from_civil, format_date, parse_date, PosixTimeZone};
+
+    #[test]
+    fn test_parse_date() {
+        let expected = Some((1112911993, -7 * 60));
+        assert_eq!(parse_date("1112911993 -0700"), expected);
+        assert_eq!(parse_date("@1112911993 -0700"), expected);
+        assert_eq!(parse_date("Thu, 07 Apr 2005 15:13:13 -0700"), expected);
+        assert_eq!(parse_date("Thu Apr 7 15:13:13 2005 -0700"), expected);
+        assert_eq!(parse_date("2005-04-07T15:13:13-07:00"), expected);
+        assert_eq!(parse_date("2005-04-07 15:13:13 -0700"), expected);
+        assert_eq!(parse_date("2005-04-07T22:13:13Z"), Some((1112911993, 0)));
+
+        assert_eq!(parse_date("2005-02-30 15:13:13 -0700"), None);
+        assert_eq!(parse_date("yesterday"), None);
+        assert_eq!(parse_date("1112911993 0700"), None);
+    }
+
+    #[test]
+    fn test_civil_days() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+        assert_eq!(civil_from_days(11017), (2000, 3, 1));
+        assert_eq!(
+            civil_from_days(days_from_civil(2024, 12, 31)),
+            (2024, 12, 31)
+        );
+        assert_eq!(civil_from_days(days_from_civil(2024, 2, 29)), (2024, 2, 29));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+    }
+
+    #[test]
+    fn test_format_date() {
+        assert_eq!(
+            format_date(1700000000, 60),
+            "Tue Nov 14 23:13:20 2023 +0100"
+        );
+        assert_eq!(format_date(0, -330), "Wed Dec 31 18:30:00 1969 -0530");
+        assert_eq!(
+            format_date(1112911993, -420),
+            "Thu Apr 7 15:13:13 2005 -0700"
+        );
+    }
+
+    #[test]
+    fn test_posix_time_zone() {
+        let new_york = PosixTimeZone::parse("EST5EDT,M3.2.0,M11.1.0").unwrap();
+        // 2023-03-12 06:59:59 and 07:00:00 UTC, either side of the start of DST
+        assert_eq!(new_york.offset_at(1678604399), -5 * 3600);
+        assert_eq!(new_york.offset_at(1678604400), -4 * 3600);
+        // 2023-11-05 05:59:59 and 06:00:00 UTC, either side of the end of DST
+        assert_eq!(new_york.offset_at(1699163999), -4 * 3600);
+        assert_eq!(new_york.offset_at(1699164000), -5 * 3600);
+
+        // DST over the new year
+        let sydney = PosixTimeZone::parse("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap();
+        assert_eq!(sydney.offset_at(1704067200), 11 * 3600);
+        assert_eq!(sydney.offset_at(1719792000), 10 * 3600);
+
+        let india = PosixTimeZone::parse("<+0530>-5:30").unwrap();
+        assert_eq!(india.offset_at(0), 5 * 3600 + 30 * 60);
+    }
+}
@@ -0,0 +1,93 @@
+//! The error type shared by the whole crate.
+
+use std::path::PathBuf;
+
+/// Errors that can occur while working with a repository or talking to a remote.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Reading or writing a file failed.
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// A request to a remote failed.
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    /// A remote responded with an unsuccessful HTTP status.
+    #[error("unexpected HTTP status {0}")]
+    HttpStatus(reqwest::StatusCode),
+    /// An object in the store, or parsed from a pack, is malformed.
+    #[error("corrupt object: {0}")]
+    CorruptObject(String),
+    /// A pack or pack index file is malformed.
+    #[error("corrupt pack: {0}")]
+    CorruptPack(String),
+    /// A pack file's trailing checksum doesn't match its contents.
+    #[error("pack file checksum mismatch")]
+    BadPackChecksum,
+    /// The remote sent something that doesn't follow the git protocol.
+    #[error("protocol error: {0}")]
+    Protocol(String),
+    /// The object with the given hash isn't in the store.
+    #[error("object {0} not found")]
+    MissingObject(String),
+    /// An object isn't of the type it needs to be, e.g., a commit's tree is a blob.
+    #[error("object {hash} is a {actual}, not a {expected}")]
+    WrongObjectType {
+        /// Hash of the object
+        hash: String,
+        /// The type the object needed to be
+        expected: &'static str,
+        /// The type the object actually is
+        actual: &'static str,
+    },
+    /// A ref or object name doesn't refer to anything valid.
+    #[error("invalid ref: {0}")]
+    InvalidRef(String),
+    /// A revision (e.g., `main~2`) doesn't refer to any object.
+    #[error("bad revision '{0}'")]
+    UnknownRevision(String),
+    /// An abbreviated hash matches more than one object.
+    #[error("short object ID {0} is ambiguous")]
+    AmbiguousObject(String),
+    /// A ref couldn't be changed, e.g., because it didn't have the value it was expected to.
+    #[error("{0}")]
+    RefUpdateFailed(String),
+    /// Another process is changing the same file.
+    #[error("unable to create '{}': file exists; is another git process running?", .0.display())]
+    Locked(PathBuf),
+    /// The index file is malformed.
+    #[error("corrupt index: {0}")]
+    CorruptIndex(String),
+    /// A tree can't be written from an index with unresolved merge conflicts.
+    #[error("{0}: unmerged; cannot write a tree")]
+    Unmerged(String),
+    /// A path given on the command line is outside the working tree.
+    #[error("'{}' is outside repository", .0.display())]
+    OutsideRepository(PathBuf),
+    /// A `for-each-ref` format or sort key is malformed, or names an unknown field.
+    #[error("{0}")]
+    InvalidFormat(String),
+    /// A config file couldn't be parsed.
+    #[error("{0}")]
+    InvalidConfig(String),
+    /// No name or email address could be found for the author, committer or tagger.
+    #[error("unable to determine {0} identity; set user.name and user.email")]
+    UnknownIdentity(&'static str),
+    /// A date in the environment couldn't be parsed.
+    #[error("invalid date format: {0}")]
+    InvalidDate(String),
+    /// No repository could be found at, or above, the given path.
+    #[error("not a git repository: '{}'", .0.display())]
+    NotARepository(PathBuf),
+    /// A nested repository can't be recorded as a gitlink, as it has no commits.
+    #[error("'{}' does not have a commit checked out", .0.display())]
+    NoCommitCheckedOut(PathBuf),
+    /// A file's name can't be recorded in a tree, e.g., because it isn't UTF-8.
+    #[error("'{}' is not a valid file name", .0.display())]
+    InvalidFileName(PathBuf),
+    /// The repository has no working tree.
+    #[error("this operation must be run in a work tree")]
+    BareRepository,
+}
+
+/// Result type for all fallible operations in this crate.
+pub type Result<T> = std::result::Result<T, Error>;
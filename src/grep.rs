@@ -0,0 +1,128 @@
+//! Matching text against basic regular expressions, as `log --grep` and `--author` do.
+//!
+//! The commonly used parts are supported: `.`, `*`, `^` and `$` anchors, bracket expressions
+//! like `[a-z]` and `[^0-9]`, and `\` to escape a special character. As in POSIX basic regular
+//! expressions, `+`, `?`, `|` and parentheses match themselves.
+
+/// Whether any line of `text` contains a match for `pattern`.
+///
+/// ```
+/// use git_starter_rust::grep::is_match;
+///
+/// assert!(is_match("^Fix", "Fix typo\n\nIn the README"));
+/// assert!(is_match("READ.*$", "Fix typo\n\nIn the README"));
+/// assert!(!is_match("^typo", "Fix typo"));
+/// ```
+pub fn is_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    text.lines().any(|line| {
+        let line: Vec<char> = line.chars().collect();
+        match pattern.split_first() {
+            Some(('^', pattern)) => match_here(pattern, &line),
+            _ => (0..=line.len()).any(|start| match_here(&pattern, &line[start..])),
+        }
+    })
+}
+
+/// Whether `pattern` matches at the start of `text`.
+fn match_here(pattern: &[char], text: &[char]) -> bool {
+    match pattern {
+        [] => true,
+        ['$'] => text.is_empty(),
+        _ => {
+            let (atom, rest) = pattern.split_at(atom_len(pattern));
+            match rest.split_first() {
+                // Take as many as possible, backing off until the rest matches
+                Some(('*', rest)) => {
+                    let count = text.iter().take_while(|c| atom_matches(atom, **c)).count();
+                    (0..=count).rev().any(|n| match_here(rest, &text[n..]))
+                }
+                _ => match text.split_first() {
+                    Some((c, text)) => atom_matches(atom, *c) && match_here(rest, text),
+                    None => false,
+                },
+            }
+        }
+    }
+}
+
+/// The length of the atom at the start of a pattern: a character, an escaped character, or a
+/// bracket expression. A `[` that's never closed matches itself.
+fn atom_len(pattern: &[char]) -> usize {
+    match pattern {
+        ['\\', _, ..] => 2,
+        ['[', rest @ ..] => {
+            // A `]` straight after the `[` (or `[^`) is part of the set
+            let start = if rest.first() == Some(&'^') { 2 } else { 1 };
+            pattern
+                .get(start + 1..)
+                .and_then(|rest| rest.iter().position(|c| *c == ']'))
+                .map_or(1, |end| start + 1 + end + 1)
+        }
+        _ => 1,
+    }
+}
+
+fn atom_matches(atom: &[char], c: char) -> bool {
+    match atom {
+        ['.'] => true,
+        ['\\', escaped] => *escaped == c,
+        ['[', '^', set @ .., ']'] => !set_contains(set, c),
+        ['[', set @ .., ']'] => set_contains(set, c),
+        [literal] => *literal == c,
+        _ => false,
+    }
+}
+
+/// Whether the characters of a bracket expression, e.g., `a-z_` for `[a-z_]`, include `c`.
+fn set_contains(set: &[char], c: char) -> bool {
+    let mut i = 0;
+    while i < set.len() {
+        match set.get(i..i + 3) {
+            Some([start, '-', end]) => {
+                if (*start..=*end).contains(&c) {
+                    return true;
+                }
+                i += 3;
+            }
+            _ => {
+                if set[i] == c {
+                    return true;
+                }
+                i += 1;
+            }
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_match;
+
+    #[test]
+    fn test_is_match() {
+        assert!(is_match("typo", "Fix typo"));
+        assert!(is_match("^Fix", "Fix typo"));
+        assert!(!is_match("^typo", "Fix typo"));
+        assert!(is_match("typo$", "Fix typo"));
+        assert!(!is_match("Fix$", "Fix typo"));
+        // Anchors apply to each line
+        assert!(is_match("^body$", "subject\n\nbody\n"));
+        assert!(is_match("F.x t*y", "Fix ty"));
+        assert!(is_match(
+            "^A.*<a@example\\.com>$",
+            "A U Thor <a@example.com>"
+        ));
+        assert!(!is_match("a@example\\.com", "a@exampleXcom"));
+        assert!(is_match("v[0-9][0-9]*", "Release v12"));
+        assert!(!is_match("v[^0-9]", "Release v12"));
+        assert!(is_match("[]x]", "a]b"));
+        assert!(is_match("caf.$", "café"));
+        // Characters that are only special in extended regular expressions
+        assert!(is_match("a+b", "a+b"));
+        assert!(!is_match("a+b", "aab"));
+        assert!(is_match("[", "a[b"));
+        assert!(is_match("*", "a*b"));
+    }
+}
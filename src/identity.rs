@@ -0,0 +1,52 @@
+//! Working out who is making a commit or tag, and when.
+
+use crate::{
+    config::Config,
+    date,
+    error::{Error, Result},
+    object::Signature,
+    repository::Repository,
+};
+
+/// The author of a new commit, from `GIT_AUTHOR_NAME`, `GIT_AUTHOR_EMAIL` and `GIT_AUTHOR_DATE`
+/// or the `author.*` and `user.*` config variables.
+pub fn author(repo: &Repository) -> Result<Signature> {
+    signature(repo, "author", "GIT_AUTHOR")
+}
+
+/// The committer of a new commit (or tagger of a new tag), from `GIT_COMMITTER_NAME`,
+/// `GIT_COMMITTER_EMAIL` and `GIT_COMMITTER_DATE` or the `committer.*` and `user.*` config
+/// variables.
+pub fn committer(repo: &Repository) -> Result<Signature> {
+    signature(repo, "committer", "GIT_COMMITTER")
+}
+
+fn signature(repo: &Repository, role: &'static str, env_prefix: &str) -> Result<Signature> {
+    let config = Config::load(Some(repo))?;
+    let lookup = |var: &str, fallback_env: Option<&str>| {
+        std::env::var(format!("{}_{}", env_prefix, var.to_ascii_uppercase()))
+            .ok()
+            .or_else(|| config.get(&format!("{}.{}", role, var)).map(str::to_owned))
+            .or_else(|| config.get(&format!("user.{}", var)).map(str::to_owned))
+            .or_else(|| fallback_env.and_then(|var| std::env::var(var).ok()))
+            .filter(|value| !value.is_empty())
+    };
+
+    let name = lookup("name", None).ok_or(Error::UnknownIdentity(role))?;
+    let email = lookup("email", Some("EMAIL")).ok_or(Error::UnknownIdentity(role))?;
+    let (time, tz_offset) = match std::env::var(format!("{}_DATE", env_prefix)) {
+        Ok(value) => date::parse_date(&value).ok_or(Error::InvalidDate(value))?,
+        Err(_) => {
+            let now = date::now();
+            (now, date::local_offset(now))
+        }
+    };
+
+    Ok(Signature {
+        name: name.into_bytes(),
+        email: email.into_bytes(),
+        time,
+        tz_offset,
+        negative_utc: false,
+    })
+}
@@ -0,0 +1,321 @@
+//! Deciding which untracked files to ignore, from `.gitignore` files, `info/exclude` and the
+//! file named by `core.excludesFile`.
+//!
+//! Patterns in a `.gitignore` file apply to the directory it's in and everything below it, and
+//! take precedence over those in `.gitignore` files further up, which take precedence over
+//! `info/exclude`, which takes precedence over `core.excludesFile`. Within a file, the last
+//! pattern that matches wins. Nothing inside an ignored directory can be un-ignored.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    config::Config, error::Result, repository::Repository, util::is_missing, wildmatch::wildmatch,
+};
+
+/// The ignore rules for a working tree, with each directory's `.gitignore` read the first time
+/// it's needed.
+///
+/// ```no_run
+/// use git_starter_rust::{ignore::Ignore, Repository};
+///
+/// let repo = Repository::discover()?;
+/// let ignore = Ignore::new(&repo)?;
+/// if ignore.is_ignored("target", true)? {
+///     println!("target/ is ignored");
+/// }
+/// # Ok::<(), git_starter_rust::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct Ignore {
+    work_tree: PathBuf,
+    ignore_case: bool,
+    /// `info/exclude` then `core.excludesFile`, in order of precedence
+    global: Vec<PatternList>,
+    /// The patterns from each directory's `.gitignore`, by the directory's path from the root
+    per_directory: RefCell<HashMap<String, PatternList>>,
+}
+
+/// The pattern that decided whether a path is ignored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IgnoreMatch {
+    /// The file the pattern is in, e.g., `src/.gitignore`
+    pub source: PathBuf,
+    /// The pattern's line number in the file, from 1
+    pub line: usize,
+    /// The pattern as written, e.g., `!/build/`
+    pub pattern: String,
+    /// Whether the pattern starts with `!`, in which case matching it means the path is *not*
+    /// ignored
+    pub negated: bool,
+}
+
+/// The patterns from one file.
+#[derive(Debug)]
+struct PatternList {
+    source: PathBuf,
+    /// The directory the patterns are relative to, e.g., `src/`, or empty for the root
+    base: String,
+    patterns: Vec<Pattern>,
+}
+
+#[derive(Debug)]
+struct Pattern {
+    /// The glob to match, without any `!`, leading `/` or trailing `/`
+    glob: String,
+    /// The line as written, for reporting which pattern matched
+    original: String,
+    line: usize,
+    negated: bool,
+    /// Whether the pattern only matches directories, as it ended in `/`
+    dir_only: bool,
+    /// Whether the pattern matches paths relative to the base, rather than any path's last
+    /// component, as it contained a `/`
+    anchored: bool,
+}
+
+impl Ignore {
+    /// Read a repository's `info/exclude` and `core.excludesFile` (`~/.config/git/ignore` by
+    /// default). `.gitignore` files are read as they are needed.
+    pub fn new(repo: &Repository) -> Result<Self> {
+        let work_tree = repo.work_tree()?.to_owned();
+        let config = Config::load(Some(repo))?;
+
+        let exclude = repo.git_dir().join("info/exclude");
+        // Show the path from the root of the working tree if possible, e.g., `.git/info/exclude`
+        let source = exclude
+            .strip_prefix(&work_tree)
+            .map_or_else(|_| exclude.clone(), Path::to_path_buf);
+        let mut global = vec![PatternList::read(&exclude, source, "")?];
+        if let Some(path) = excludes_file(&config) {
+            global.push(PatternList::read(&path, path.clone(), "")?);
+        }
+
+        Ok(Ignore {
+            work_tree,
+            ignore_case: config.get_bool("core.ignoreCase")?.unwrap_or(false),
+            global,
+            per_directory: RefCell::new(HashMap::new()),
+        })
+    }
+
+    /// Whether a path (from the root of the working tree) is ignored, either by a pattern that
+    /// matches it or because it's inside an ignored directory.
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> Result<bool> {
+        Ok(self.find_match(path, is_dir)?.is_some_and(|m| !m.negated))
+    }
+
+    /// Find the pattern that decides whether a path is ignored, which is the one that matched
+    /// the directory if the path is inside an ignored directory. A negated match means the path
+    /// isn't ignored.
+    pub fn find_match(&self, path: &str, is_dir: bool) -> Result<Option<IgnoreMatch>> {
+        for (i, _) in path.match_indices('/') {
+            if let Some(found) = self.match_path(&path[..i], true)? {
+                if !found.negated {
+                    return Ok(Some(found));
+                }
+            }
+        }
+        self.match_path(path, is_dir)
+    }
+
+    /// Find the pattern matching a path itself, without looking at the directories it's in.
+    fn match_path(&self, path: &str, is_dir: bool) -> Result<Option<IgnoreMatch>> {
+        let mut dirs = vec![""];
+        dirs.extend(path.match_indices('/').map(|(i, _)| &path[..i]));
+        // Deeper `.gitignore` files take precedence
+        for dir in dirs.into_iter().rev() {
+            self.load_directory(dir)?;
+            let per_directory = self.per_directory.borrow();
+            if let Some(found) = per_directory[dir].find_match(path, is_dir, self.ignore_case) {
+                return Ok(Some(found));
+            }
+        }
+        Ok(self
+            .global
+            .iter()
+            .find_map(|list| list.find_match(path, is_dir, self.ignore_case)))
+    }
+
+    /// Read a directory's `.gitignore`, unless it's been read already.
+    fn load_directory(&self, dir: &str) -> Result<()> {
+        if self.per_directory.borrow().contains_key(dir) {
+            return Ok(());
+        }
+        let (source, base) = match dir {
+            "" => (PathBuf::from(".gitignore"), String::new()),
+            _ => (
+                PathBuf::from(format!("{}/.gitignore", dir)),
+                format!("{}/", dir),
+            ),
+        };
+        let list = PatternList::read(&self.work_tree.join(&source), source, &base)?;
+        self.per_directory.borrow_mut().insert(dir.to_owned(), list);
+        Ok(())
+    }
+}
+
+/// The file named by `core.excludesFile`, or `$XDG_CONFIG_HOME/git/ignore` by default.
+fn excludes_file(config: &Config) -> Option<PathBuf> {
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    match config.get("core.excludesFile") {
+        Some(path) => match (path.strip_prefix("~/"), home) {
+            (Some(rest), Some(home)) => Some(home.join(rest)),
+            _ => Some(PathBuf::from(path)),
+        },
+        None => std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| home.map(|h| h.join(".config")))
+            .map(|dir| dir.join("git/ignore")),
+    }
+}
+
+impl PatternList {
+    /// Read the patterns in a file, which needn't exist.
+    fn read(path: &Path, source: PathBuf, base: &str) -> Result<Self> {
+        match fs::read(path) {
+            Ok(content) => Ok(PatternList::parse(
+                &String::from_utf8_lossy(&content),
+                source,
+                base,
+            )),
+            Err(err) if is_missing(&err) => Ok(PatternList {
+                source,
+                base: base.to_owned(),
+                patterns: Vec::new(),
+            }),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn parse(content: &str, source: PathBuf, base: &str) -> Self {
+        let patterns = content
+            .lines()
+            .enumerate()
+            .filter_map(|(i, line)| Pattern::parse(line, i + 1))
+            .collect();
+        PatternList {
+            source,
+            base: base.to_owned(),
+            patterns,
+        }
+    }
+
+    /// Find the last pattern that matches a path, which must be inside the base directory.
+    fn find_match(&self, path: &str, is_dir: bool, ignore_case: bool) -> Option<IgnoreMatch> {
+        let relative = path.strip_prefix(self.base.as_str())?;
+        let name = relative.rsplit('/').next().unwrap_or(relative);
+        self.patterns
+            .iter()
+            .rev()
+            .find(|pattern| {
+                let text = if pattern.anchored { relative } else { name };
+                (is_dir || !pattern.dir_only) && wildmatch(&pattern.glob, text, ignore_case)
+            })
+            .map(|pattern| IgnoreMatch {
+                source: self.source.clone(),
+                line: pattern.line,
+                pattern: pattern.original.clone(),
+                negated: pattern.negated,
+            })
+    }
+}
+
+impl Pattern {
+    /// Parse a line of a gitignore file, or return `None` for blank lines and comments.
+    fn parse(line: &str, line_number: usize) -> Option<Self> {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if line.starts_with('#') {
+            return None;
+        }
+        let original = trim_trailing_spaces(line);
+        if original.is_empty() {
+            return None;
+        }
+
+        let (negated, glob) = match original.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, original),
+        };
+        let (dir_only, glob) = match glob.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, glob),
+        };
+        let anchored = glob.contains('/');
+        let glob = glob.strip_prefix('/').unwrap_or(glob);
+        if glob.is_empty() {
+            return None;
+        }
+
+        Some(Pattern {
+            glob: glob.to_owned(),
+            original: original.to_owned(),
+            line: line_number,
+            negated,
+            dir_only,
+            anchored,
+        })
+    }
+}
+
+/// Remove trailing spaces from a line, except one escaped with a backslash.
+fn trim_trailing_spaces(line: &str) -> &str {
+    let trimmed = line.trim_end_matches(' ');
+    if trimmed.len() < line.len() && trimmed.ends_with('\\') && !trimmed.ends_with("\\\\") {
+        &line[..trimmed.len() + 1]
+    } else {
+        trimmed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{trim_trailing_spaces, PatternList};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_pattern_list() {
+        let content =
+            "# build output\n/target\n*.log\n!keep.log\ndocs/**/*.html\ncache/\n\\#hash\n";
+        let list = PatternList::parse(content, PathBuf::from(".gitignore"), "");
+        let ignored = |path: &str, is_dir: bool| {
+            list.find_match(path, is_dir, false)
+                .map(|found| (found.line, found.negated))
+        };
+        assert_eq!(ignored("target", true), Some((2, false)));
+        // Anchored patterns only match relative to the file's directory
+        assert_eq!(ignored("src/target", true), None);
+        assert_eq!(ignored("a/b/debug.log", false), Some((3, false)));
+        assert_eq!(ignored("a/keep.log", false), Some((4, true)));
+        assert_eq!(ignored("docs/a/b/page.html", false), Some((5, false)));
+        assert_eq!(ignored("docs/page.html", false), Some((5, false)));
+        assert_eq!(ignored("page.html", false), None);
+        // Directory-only patterns
+        assert_eq!(ignored("src/cache", true), Some((6, false)));
+        assert_eq!(ignored("src/cache", false), None);
+        assert_eq!(ignored("#hash", false), Some((7, false)));
+    }
+
+    #[test]
+    fn test_nested_pattern_list() {
+        let list = PatternList::parse("/out\nlib/*.o\n", PathBuf::from("src/.gitignore"), "src/");
+        let ignored = |path: &str| list.find_match(path, false, false).is_some();
+        assert!(ignored("src/out"));
+        assert!(!ignored("out"));
+        assert!(!ignored("src/a/out"));
+        assert!(ignored("src/lib/a.o"));
+        assert!(!ignored("lib/a.o"));
+    }
+
+    #[test]
+    fn test_trim_trailing_spaces() {
+        assert_eq!(trim_trailing_spaces("a  "), "a");
+        assert_eq!(trim_trailing_spaces("a\\  "), "a\\ ");
+        assert_eq!(trim_trailing_spaces("a\\\\ "), "a\\\\");
+        assert_eq!(trim_trailing_spaces(" a"), " a");
+    }
+}
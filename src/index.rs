@@ -0,0 +1,906 @@
+//! Reading and writing the index (`.git/index`, also known as the dircache or staging area).
+//!
+//! Versions 2, 3 (which adds extended flags) and 4 (which compresses paths against the previous
+//! entry's) are supported, along with the cached tree (`TREE`) extension. Other optional
+//! extensions are dropped when the index is rewritten, as git itself allows.
+
+use sha1::{Digest, Sha1};
+use std::{fs::Metadata, os::unix::fs::MetadataExt, path::Path};
+
+use crate::{
+    error::{Error, Result},
+    lockfile::write_locked,
+    object::{Object, TreeEntry},
+    pathspec::{Pathspec, PathspecMatch},
+    repository::Repository,
+    util::{encode_offset, parse_offset, read_u32},
+};
+
+const SIGNATURE: &[u8; 4] = b"DIRC";
+/// Length of an entry before its path, without extended flags
+const ENTRY_HEADER_LEN: usize = 62;
+
+const FLAG_ASSUME_VALID: u16 = 0x8000;
+const FLAG_EXTENDED: u16 = 0x4000;
+const FLAG_STAGE_MASK: u16 = 0x3000;
+const FLAG_NAME_MASK: u16 = 0x0fff;
+const EXTENDED_FLAG_SKIP_WORKTREE: u16 = 0x4000;
+const EXTENDED_FLAG_INTENT_TO_ADD: u16 = 0x2000;
+
+/// The index: the contents of the next commit, along with enough information about the files in
+/// the working tree to tell cheaply whether they have changed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Index {
+    /// Format version, from 2 to 4
+    pub version: u32,
+    /// Entries sorted by path and then stage
+    entries: Vec<IndexEntry>,
+    /// Tree hashes of directories whose entries haven't changed since they were last written
+    pub cache_tree: Option<CacheTree>,
+}
+
+/// A file in the index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexEntry {
+    /// Time the file's metadata last changed, in seconds and nanoseconds
+    pub ctime: (u32, u32),
+    /// Time the file's contents last changed, in seconds and nanoseconds
+    pub mtime: (u32, u32),
+    /// Device the file is on
+    pub dev: u32,
+    /// Inode number of the file
+    pub ino: u32,
+    /// File mode: 100644, 100755, 120000 (symlink) or 160000 (gitlink), as a number
+    pub mode: u32,
+    /// Owner's user ID
+    pub uid: u32,
+    /// Owner's group ID
+    pub gid: u32,
+    /// Size of the file in bytes, truncated to 32 bits
+    pub size: u32,
+    /// Hash of the blob (or commit, for gitlinks)
+    pub hash: String,
+    /// Merge stage: 0 normally, or 1 (base), 2 (ours) or 3 (theirs) during a conflict
+    pub stage: u8,
+    /// Assume the file is unchanged without checking it
+    pub assume_valid: bool,
+    /// The file is outside the sparse checkout
+    pub skip_worktree: bool,
+    /// The file was added with `add -N`, so only its path is staged
+    pub intent_to_add: bool,
+    /// Path of the file from the root of the working tree, with `/` separators
+    pub path: String,
+}
+
+/// A node of the cached tree extension, recording the tree hash of a directory in the index.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CacheTree {
+    /// Name of the directory (empty for the root)
+    pub name: String,
+    /// Number of index entries under this directory, or `None` if it has been invalidated
+    pub entry_count: Option<usize>,
+    /// Hash of the directory's tree, if it is still valid
+    pub hash: Option<String>,
+    /// Subdirectories
+    pub children: Vec<CacheTree>,
+}
+
+impl Default for Index {
+    fn default() -> Self {
+        Index::new()
+    }
+}
+
+impl Index {
+    /// An empty index.
+    pub fn new() -> Self {
+        Index {
+            version: 2,
+            entries: Vec::new(),
+            cache_tree: None,
+        }
+    }
+
+    /// Read a repository's index, which is empty if it doesn't exist yet.
+    pub fn read(repo: &Repository) -> Result<Self> {
+        match std::fs::read(repo.index_path()) {
+            Ok(data) => Index::parse(&data),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Index::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Write the index to a repository, atomically.
+    pub fn write(&self, repo: &Repository) -> Result<()> {
+        write_locked(&repo.index_path(), &self.encode())
+    }
+
+    /// Parse an index file.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let corrupt = |message: &str| Error::CorruptIndex(message.to_owned());
+        if data.len() < 12 + 20 {
+            return Err(corrupt("index file is too short"));
+        }
+
+        let (content, checksum) = data.split_at(data.len() - 20);
+        // With `index.skipHash`, the checksum is left as zeros
+        if checksum != [0; 20] && Sha1::digest(content).as_slice() != checksum {
+            return Err(corrupt("index checksum mismatch"));
+        }
+        if &content[0..4] != SIGNATURE {
+            return Err(corrupt("bad index signature"));
+        }
+        let version = read_u32(&content[4..]);
+        if !(2..=4).contains(&version) {
+            return Err(Error::CorruptIndex(format!(
+                "unsupported index version {}",
+                version
+            )));
+        }
+        let entry_count = read_u32(&content[8..]) as usize;
+
+        let mut rest = &content[12..];
+        let mut entries = Vec::with_capacity(entry_count);
+        let mut previous_path = "";
+        for _ in 0..entry_count {
+            let (remainder, entry) = IndexEntry::parse(rest, version, previous_path)?;
+            rest = remainder;
+            entries.push(entry);
+            previous_path = &entries.last().unwrap().path;
+        }
+
+        let mut cache_tree = None;
+        while !rest.is_empty() {
+            if rest.len() < 8 {
+                return Err(corrupt("truncated extension"));
+            }
+            let signature = &rest[0..4];
+            let len = read_u32(&rest[4..]) as usize;
+            let extension = rest
+                .get(8..8 + len)
+                .ok_or_else(|| corrupt("truncated extension"))?;
+            match signature {
+                b"TREE" => cache_tree = Some(CacheTree::parse(extension)?),
+                // Extensions starting with a capital letter are optional
+                [b'A'..=b'Z', ..] => (),
+                _ => {
+                    return Err(Error::CorruptIndex(format!(
+                        "unsupported index extension '{}'",
+                        String::from_utf8_lossy(signature)
+                    )))
+                }
+            }
+            rest = &rest[8 + len..];
+        }
+
+        let index = Index {
+            version,
+            entries,
+            cache_tree,
+        };
+        if !index.entries.windows(2).all(|w| w[0].key() < w[1].key()) {
+            return Err(corrupt("index entries are not sorted"));
+        }
+        Ok(index)
+    }
+
+    /// Encode this index in the index file format.
+    pub fn encode(&self) -> Vec<u8> {
+        // Extended flags need at least version 3
+        let version = match self.version {
+            2 if self.entries.iter().any(|e| e.has_extended_flags()) => 3,
+            version => version,
+        };
+
+        let mut output = Vec::new();
+        output.extend(SIGNATURE);
+        output.extend(version.to_be_bytes());
+        output.extend((self.entries.len() as u32).to_be_bytes());
+
+        let mut previous_path = "";
+        for entry in self.entries.iter() {
+            entry.encode(&mut output, version, previous_path);
+            previous_path = &entry.path;
+        }
+
+        if let Some(cache_tree) = self.cache_tree.as_ref() {
+            let mut extension = Vec::new();
+            cache_tree.encode(&mut extension);
+            output.extend(b"TREE");
+            output.extend((extension.len() as u32).to_be_bytes());
+            output.extend(extension);
+        }
+
+        let checksum = Sha1::digest(&output);
+        output.extend(checksum);
+        output
+    }
+
+    /// All of the entries, sorted by path and then stage.
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+
+    /// Find the entry for a path at the given stage.
+    pub fn find(&self, path: &str, stage: u8) -> Option<&IndexEntry> {
+        self.entries
+            .binary_search_by(|e| e.key().cmp(&(path.as_bytes(), stage)))
+            .ok()
+            .map(|i| &self.entries[i])
+    }
+
+    /// Record new stat data for a file whose contents turned out to be unchanged, so it won't
+    /// need hashing again. Unlike [`add`](Index::add), this leaves the cached tree valid.
+    pub fn refresh(&mut self, path: &str, metadata: &Metadata) {
+        if let Ok(i) = self
+            .entries
+            .binary_search_by(|e| e.key().cmp(&(path.as_bytes(), 0)))
+        {
+            self.entries[i].update_stat(metadata);
+        }
+    }
+
+    /// The entries whose paths a pathspec matches, and how they matched. Paths with merge
+    /// conflicts are only included once, by their first stage.
+    pub fn matching(&self, pathspec: &Pathspec) -> Vec<(&IndexEntry, PathspecMatch)> {
+        let mut matching: Vec<(&IndexEntry, PathspecMatch)> = Vec::new();
+        for entry in self.entries.iter() {
+            if matching.last().is_some_and(|(e, _)| e.path == entry.path) {
+                continue;
+            }
+            if let Some(pathspec_match) = pathspec.matches(&entry.path) {
+                matching.push((entry, pathspec_match));
+            }
+        }
+        matching
+    }
+
+    /// Whether any paths have unresolved merge conflicts.
+    pub fn has_conflicts(&self) -> bool {
+        self.entries.iter().any(|e| e.stage != 0)
+    }
+
+    /// Add an entry, replacing any existing one for the same path. Adding a path at stage 0
+    /// resolves any conflict it had.
+    pub fn add(&mut self, entry: IndexEntry) {
+        if let Some(cache_tree) = self.cache_tree.as_mut() {
+            cache_tree.invalidate(&entry.path);
+        }
+        if entry.stage == 0 {
+            self.entries
+                .retain(|e| e.path != entry.path || e.stage == 0);
+        }
+        match self.entries.binary_search_by(|e| e.key().cmp(&entry.key())) {
+            Ok(i) => self.entries[i] = entry,
+            Err(i) => self.entries.insert(i, entry),
+        }
+    }
+
+    /// Write the tree objects for the staged files (including every subtree), returning the hash
+    /// of the root tree. Directories whose cached tree is still valid aren't rebuilt, and the
+    /// cached tree is brought up to date for next time.
+    pub fn write_tree(&mut self, repo: &Repository) -> Result<String> {
+        if let Some(entry) = self.entries.iter().find(|e| e.stage != 0) {
+            return Err(Error::Unmerged(entry.path.clone()));
+        }
+
+        let (cache_tree, hash, _) =
+            build_tree(repo, &self.entries, "", 0, self.cache_tree.as_ref())?;
+        self.cache_tree = Some(cache_tree);
+        Ok(hash)
+    }
+
+    /// Remove all entries for a path, returning whether there were any.
+    pub fn remove(&mut self, path: &str) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|e| e.path != path);
+        if self.entries.len() == len {
+            return false;
+        }
+        if let Some(cache_tree) = self.cache_tree.as_mut() {
+            cache_tree.invalidate(path);
+        }
+        true
+    }
+}
+
+impl IndexEntry {
+    /// An entry for a file with the given metadata, as returned by `symlink_metadata`.
+    pub fn new(path: &str, hash: &str, mode: u32, metadata: &Metadata) -> Self {
+        let mut entry = IndexEntry {
+            ctime: (0, 0),
+            mtime: (0, 0),
+            dev: 0,
+            ino: 0,
+            mode,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            hash: hash.to_owned(),
+            stage: 0,
+            assume_valid: false,
+            skip_worktree: false,
+            intent_to_add: false,
+            path: path.to_owned(),
+        };
+        entry.update_stat(metadata);
+        entry
+    }
+
+    /// Record the file's current stat data, so that later changes to it can be noticed.
+    pub fn update_stat(&mut self, metadata: &Metadata) {
+        // Everything is truncated to 32 bits, as in git
+        self.ctime = (metadata.ctime() as u32, metadata.ctime_nsec() as u32);
+        self.mtime = (metadata.mtime() as u32, metadata.mtime_nsec() as u32);
+        self.dev = metadata.dev() as u32;
+        self.ino = metadata.ino() as u32;
+        self.uid = metadata.uid();
+        self.gid = metadata.gid();
+        self.size = metadata.size() as u32;
+    }
+
+    /// Whether the file's stat data differs from when the entry was made, meaning that it may
+    /// have changed (though its contents might still turn out to be the same).
+    pub fn stat_changed(&self, metadata: &Metadata) -> bool {
+        self.mtime != (metadata.mtime() as u32, metadata.mtime_nsec() as u32)
+            || self.ctime != (metadata.ctime() as u32, metadata.ctime_nsec() as u32)
+            || self.ino != metadata.ino() as u32
+            || self.dev != metadata.dev() as u32
+            || self.uid != metadata.uid()
+            || self.gid != metadata.gid()
+            || self.size != metadata.size() as u32
+    }
+
+    /// Whether the file was modified so soon after the index was written that a later change in
+    /// the same second wouldn't alter its stat data, so its contents need checking.
+    pub fn is_racily_clean(&self, index_mtime: (u32, u32)) -> bool {
+        index_mtime <= self.mtime
+    }
+
+    /// Whether the entry has changes staged to commit, given the file's entry in `HEAD`'s tree, or
+    /// `None` if it isn't there.
+    pub fn is_staged(&self, head: Option<&TreeEntry>) -> bool {
+        head.map_or(true, |head| {
+            head.hash != self.hash || u32::from_str_radix(&head.mode, 8) != Ok(self.mode)
+        })
+    }
+
+    /// Sort key: path, then stage.
+    fn key(&self) -> (&[u8], u8) {
+        (self.path.as_bytes(), self.stage)
+    }
+
+    fn has_extended_flags(&self) -> bool {
+        self.skip_worktree || self.intent_to_add
+    }
+
+    fn parse<'a>(input: &'a [u8], version: u32, previous_path: &str) -> Result<(&'a [u8], Self)> {
+        let truncated = || Error::CorruptIndex("truncated index entry".into());
+        if input.len() < ENTRY_HEADER_LEN {
+            return Err(truncated());
+        }
+
+        let flags = read_u16(&input[60..]);
+        let mut len = ENTRY_HEADER_LEN;
+        let extended_flags = if flags & FLAG_EXTENDED != 0 {
+            if version < 3 {
+                return Err(Error::CorruptIndex(
+                    "extended flags in a version 2 index".into(),
+                ));
+            }
+            len += 2;
+            read_u16(input.get(62..64).ok_or_else(truncated)?)
+        } else {
+            0
+        };
+
+        let (path, rest) = if version == 4 {
+            // The path is given as how much of the previous path to drop, and what to add
+            // This uses the same variable-length encoding as offset deltas in packs
+            let (rest, strip) = parse_offset(&input[len..]).map_err(|_| truncated())?;
+            let nul = rest.iter().position(|b| *b == 0).ok_or_else(truncated)?;
+            let keep = previous_path
+                .len()
+                .checked_sub(strip)
+                .ok_or_else(|| Error::CorruptIndex("bad path compression".into()))?;
+            let mut path = previous_path.as_bytes()[0..keep].to_vec();
+            path.extend(&rest[0..nul]);
+            (path, &rest[nul + 1..])
+        } else {
+            let nul = input[len..]
+                .iter()
+                .position(|b| *b == 0)
+                .ok_or_else(truncated)?;
+            // Entries are padded with 1-8 NULs to a multiple of 8 bytes
+            let padded_len = (len + nul + 8) & !7;
+            let rest = input.get(padded_len..).ok_or_else(truncated)?;
+            (input[len..len + nul].to_vec(), rest)
+        };
+        let path = String::from_utf8(path)
+            .map_err(|_| Error::CorruptIndex("path is not valid UTF-8".into()))?;
+
+        let entry = IndexEntry {
+            ctime: (read_u32(&input[0..]), read_u32(&input[4..])),
+            mtime: (read_u32(&input[8..]), read_u32(&input[12..])),
+            dev: read_u32(&input[16..]),
+            ino: read_u32(&input[20..]),
+            mode: read_u32(&input[24..]),
+            uid: read_u32(&input[28..]),
+            gid: read_u32(&input[32..]),
+            size: read_u32(&input[36..]),
+            hash: hex::encode(&input[40..60]),
+            stage: ((flags & FLAG_STAGE_MASK) >> 12) as u8,
+            assume_valid: flags & FLAG_ASSUME_VALID != 0,
+            skip_worktree: extended_flags & EXTENDED_FLAG_SKIP_WORKTREE != 0,
+            intent_to_add: extended_flags & EXTENDED_FLAG_INTENT_TO_ADD != 0,
+            path,
+        };
+        Ok((rest, entry))
+    }
+
+    fn encode(&self, output: &mut Vec<u8>, version: u32, previous_path: &str) {
+        let start = output.len();
+        for value in [
+            self.ctime.0,
+            self.ctime.1,
+            self.mtime.0,
+            self.mtime.1,
+            self.dev,
+            self.ino,
+            self.mode,
+            self.uid,
+            self.gid,
+            self.size,
+        ] {
+            output.extend(value.to_be_bytes());
+        }
+        output.extend(hex::decode(&self.hash).unwrap());
+
+        let mut flags = (self.stage as u16) << 12 | self.path.len().min(0xfff) as u16;
+        flags &= FLAG_STAGE_MASK | FLAG_NAME_MASK;
+        if self.assume_valid {
+            flags |= FLAG_ASSUME_VALID;
+        }
+        if self.has_extended_flags() {
+            flags |= FLAG_EXTENDED;
+        }
+        output.extend(flags.to_be_bytes());
+        if self.has_extended_flags() {
+            let mut extended_flags = 0;
+            if self.skip_worktree {
+                extended_flags |= EXTENDED_FLAG_SKIP_WORKTREE;
+            }
+            if self.intent_to_add {
+                extended_flags |= EXTENDED_FLAG_INTENT_TO_ADD;
+            }
+            output.extend(extended_flags.to_be_bytes());
+        }
+
+        if version == 4 {
+            let common = previous_path
+                .bytes()
+                .zip(self.path.bytes())
+                .take_while(|(a, b)| a == b)
+                .count();
+            encode_offset(output, previous_path.len() - common);
+            output.extend(&self.path.as_bytes()[common..]);
+            output.push(0);
+        } else {
+            output.extend(self.path.as_bytes());
+            let len = output.len() - start;
+            let padded_len = (len + 8) & !7;
+            output.resize(start + padded_len, 0);
+        }
+    }
+}
+
+impl CacheTree {
+    /// Mark the directories containing `path` as changed.
+    pub fn invalidate(&mut self, path: &str) {
+        self.entry_count = None;
+        self.hash = None;
+        if let Some((dir, rest)) = path.split_once('/') {
+            if let Some(child) = self.children.iter_mut().find(|c| c.name == dir) {
+                child.invalidate(rest);
+            }
+        }
+    }
+
+    /// Find the node for a directory, given its path from the root.
+    pub fn find(&self, path: &str) -> Option<&CacheTree> {
+        if path.is_empty() {
+            return Some(self);
+        }
+        let (dir, rest) = path.split_once('/').unwrap_or((path, ""));
+        self.children
+            .iter()
+            .find(|c| c.name == dir)
+            .and_then(|c| c.find(rest))
+    }
+
+    fn parse(input: &[u8]) -> Result<Self> {
+        let (rest, tree) = CacheTree::parse_node(input)?;
+        if !rest.is_empty() {
+            return Err(Error::CorruptIndex("trailing data in cached tree".into()));
+        }
+        Ok(tree)
+    }
+
+    /// Parse a node and all of its children, which follow it directly.
+    fn parse_node(input: &[u8]) -> Result<(&[u8], Self)> {
+        let corrupt = || Error::CorruptIndex("invalid cached tree".into());
+
+        let nul = input.iter().position(|b| *b == 0).ok_or_else(corrupt)?;
+        let name = std::str::from_utf8(&input[0..nul]).map_err(|_| corrupt())?;
+        let rest = &input[nul + 1..];
+        let newline = rest.iter().position(|b| *b == b'\n').ok_or_else(corrupt)?;
+        let counts = std::str::from_utf8(&rest[0..newline]).map_err(|_| corrupt())?;
+        let (entry_count, child_count) = counts.split_once(' ').ok_or_else(corrupt)?;
+        let entry_count = entry_count.parse::<isize>().map_err(|_| corrupt())?;
+        let child_count = child_count.parse::<usize>().map_err(|_| corrupt())?;
+        let mut rest = &rest[newline + 1..];
+
+        // Invalidated nodes have a count of -1 and no hash
+        let (entry_count, hash) = if entry_count >= 0 {
+            let hash = rest.get(0..20).ok_or_else(corrupt)?;
+            let hash = hex::encode(hash);
+            rest = &rest[20..];
+            (Some(entry_count as usize), Some(hash))
+        } else {
+            (None, None)
+        };
+
+        let mut children = Vec::with_capacity(child_count);
+        for _ in 0..child_count {
+            let (remainder, child) = CacheTree::parse_node(rest)?;
+            rest = remainder;
+            children.push(child);
+        }
+
+        Ok((
+            rest,
+            CacheTree {
+                name: name.to_owned(),
+                entry_count,
+                hash,
+                children,
+            },
+        ))
+    }
+
+    fn encode(&self, output: &mut Vec<u8>) {
+        output.extend(self.name.as_bytes());
+        output.push(0);
+        match (self.entry_count, self.hash.as_ref()) {
+            (Some(entry_count), Some(hash)) => {
+                output.extend(format!("{} {}\n", entry_count, self.children.len()).as_bytes());
+                output.extend(hex::decode(hash).unwrap());
+            }
+            _ => output.extend(format!("-1 {}\n", self.children.len()).as_bytes()),
+        }
+        for child in self.children.iter() {
+            child.encode(output);
+        }
+    }
+}
+
+/// Build the tree for a directory, given the index entries under it (whose paths start with the
+/// directory's path, `prefix_len` bytes long including the trailing slash) and any cached tree
+/// for it. Returns the directory's new cached tree, the hash of its tree, and whether the tree
+/// is empty.
+///
+/// As in git, a cached tree that leaves out intent-to-add entries is marked invalid, since its
+/// hash doesn't account for every entry.
+fn build_tree(
+    repo: &Repository,
+    entries: &[IndexEntry],
+    name: &str,
+    prefix_len: usize,
+    cached: Option<&CacheTree>,
+) -> Result<(CacheTree, String, bool)> {
+    if let Some(cached) = cached {
+        if let (Some(entry_count), Some(hash)) = (cached.entry_count, cached.hash.as_ref()) {
+            if entry_count == entries.len() && repo.has_object(hash)? {
+                return Ok((cached.clone(), hash.clone(), entry_count == 0));
+            }
+        }
+    }
+
+    let mut tree_entries = Vec::new();
+    let mut children = Vec::new();
+    let mut valid = true;
+    let mut i = 0;
+    while i < entries.len() {
+        let entry = &entries[i];
+        let relative_path = &entry.path[prefix_len..];
+        match relative_path.split_once('/') {
+            None => {
+                // Files that are only intended to be added aren't part of the tree yet
+                if entry.intent_to_add {
+                    valid = false;
+                } else {
+                    tree_entries.push(TreeEntry {
+                        mode: format!("{:o}", entry.mode),
+                        name: relative_path.to_owned(),
+                        hash: entry.hash.clone(),
+                    });
+                }
+                i += 1;
+            }
+            Some((dir, _)) => {
+                let dir_prefix = &entry.path[..prefix_len + dir.len() + 1];
+                let len = entries[i..]
+                    .iter()
+                    .take_while(|e| e.path.starts_with(dir_prefix))
+                    .count();
+                let cached_child = cached.and_then(|c| c.children.iter().find(|c| c.name == dir));
+                let (child, hash, empty) = build_tree(
+                    repo,
+                    &entries[i..i + len],
+                    dir,
+                    dir_prefix.len(),
+                    cached_child,
+                )?;
+                valid &= child.entry_count.is_some();
+                // Directories with nothing but intended additions are left out
+                if !empty {
+                    tree_entries.push(TreeEntry {
+                        mode: "40000".to_owned(),
+                        name: dir.to_owned(),
+                        hash,
+                    });
+                }
+                children.push(child);
+                i += len;
+            }
+        }
+    }
+    // Git orders the cached tree's children by length first, rather than as in the tree
+    children.sort_by(|a, b| (a.name.len(), &a.name).cmp(&(b.name.len(), &b.name)));
+
+    let empty = tree_entries.is_empty();
+    let tree = Object::Tree(tree_entries);
+    let hash = tree.hash();
+    if !repo.has_object(&hash)? {
+        tree.add(repo)?;
+    }
+
+    let cache_tree = CacheTree {
+        name: name.to_owned(),
+        entry_count: valid.then_some(entries.len()),
+        hash: valid.then(|| hash.clone()),
+        children,
+    };
+    Ok((cache_tree, hash, empty))
+}
+
+fn read_u16(input: &[u8]) -> u16 {
+    u16::from_be_bytes([input[0], input[1]])
+}
+
+/// The mode to record for a file in the index, given its metadata.
+pub fn mode_from_metadata(metadata: &Metadata) -> u32 {
+    if metadata.file_type().is_symlink() {
+        0o120000
+    } else if metadata.is_dir() {
+        // A nested repository
+        0o160000
+    } else if metadata.mode() & 0o100 != 0 {
+        0o100755
+    } else {
+        0o100644
+    }
+}
+
+/// The time an index file was last written, for detecting racily clean entries.
+pub fn index_mtime(path: &Path) -> (u32, u32) {
+    std::fs::metadata(path)
+        .map(|m| (m.mtime() as u32, m.mtime_nsec() as u32))
+        .unwrap_or((0, 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CacheTree, Index, IndexEntry};
+    use crate::{
+        error::Error,
+        object::{Object, TreeEntry},
+        pathspec::Pathspec,
+        test_util::TempRepo,
+    };
+
+    fn entry(path: &str, stage: u8) -> IndexEntry {
+        IndexEntry {
+            ctime: (1, 2),
+            mtime: (3, 4),
+            dev: 5,
+            ino: 6,
+            mode: 0o100644,
+            uid: 7,
+            gid: 8,
+            size: 9,
+            hash: "ab".repeat(20),
+            stage,
+            assume_valid: false,
+            skip_worktree: false,
+            intent_to_add: false,
+            path: path.to_owned(),
+        }
+    }
+
+    fn sample_index(version: u32) -> Index {
+        let mut index = Index::new();
+        index.version = version;
+        for path in ["dir/file", "dir/sub/file", "a", "dir/file2"] {
+            index.add(entry(path, 0));
+        }
+        index.add(entry("conflict", 2));
+        index.add(entry("conflict", 1));
+        index.cache_tree = Some(CacheTree {
+            name: "".into(),
+            entry_count: None,
+            hash: None,
+            children: vec![CacheTree {
+                name: "dir".into(),
+                entry_count: Some(3),
+                hash: Some("cd".repeat(20)),
+                children: Vec::new(),
+            }],
+        });
+        index
+    }
+
+    #[test]
+    fn test_encode_and_parse() {
+        for version in 2..=4 {
+            let index = sample_index(version);
+            let encoded = index.encode();
+            assert_eq!(
+                Index::parse(&encoded).unwrap(),
+                index,
+                "version {}",
+                version
+            );
+        }
+
+        let paths = sample_index(2)
+            .entries()
+            .iter()
+            .map(|e| (e.path.clone(), e.stage))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                ("a".to_owned(), 0),
+                ("conflict".to_owned(), 1),
+                ("conflict".to_owned(), 2),
+                ("dir/file".to_owned(), 0),
+                ("dir/file2".to_owned(), 0),
+                ("dir/sub/file".to_owned(), 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extended_flags_upgrade_version() {
+        let mut index = sample_index(2);
+        let mut intent = entry("new", 0);
+        intent.intent_to_add = true;
+        index.add(intent);
+
+        let parsed = Index::parse(&index.encode()).unwrap();
+        assert_eq!(parsed.version, 3);
+        assert!(parsed.find("new", 0).unwrap().intent_to_add);
+    }
+
+    #[test]
+    fn test_add_and_remove() {
+        let mut index = sample_index(2);
+        assert!(index.has_conflicts());
+
+        // Staging a resolution removes the conflict stages
+        index.add(entry("conflict", 0));
+        assert!(!index.has_conflicts());
+        assert!(index.find("conflict", 0).is_some());
+
+        // Changes under a directory invalidate its cached tree
+        index.add(entry("dir/new", 0));
+        let cache_tree = index.cache_tree.as_ref().unwrap();
+        assert_eq!(cache_tree.find("dir").unwrap().hash, None);
+
+        assert!(index.remove("a"));
+        assert!(!index.remove("a"));
+        assert!(index.find("a", 0).is_none());
+    }
+
+    #[test]
+    fn test_matching() {
+        let index = sample_index(2);
+        let pathspec = Pathspec::from_patterns(&["dir", "conflict"]);
+        let matching = index
+            .matching(&pathspec)
+            .into_iter()
+            .map(|(e, m)| (e.path.as_str(), e.stage, m.pattern, m.in_directory))
+            .collect::<Vec<_>>();
+        // The conflicted path is only included once
+        assert_eq!(
+            matching,
+            [
+                ("conflict", 1, 1, false),
+                ("dir/file", 0, 0, true),
+                ("dir/file2", 0, 0, true),
+                ("dir/sub/file", 0, 0, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_is_staged() {
+        let entry = entry("a", 0);
+        let head = |mode: &str, hash: String| TreeEntry {
+            mode: mode.to_owned(),
+            name: "a".to_owned(),
+            hash,
+        };
+        assert!(!entry.is_staged(Some(&head("100644", "ab".repeat(20)))));
+        assert!(entry.is_staged(Some(&head("100755", "ab".repeat(20)))));
+        assert!(entry.is_staged(Some(&head("100644", "cd".repeat(20)))));
+        assert!(entry.is_staged(None));
+    }
+
+    #[test]
+    fn test_write_tree() {
+        let repo = TempRepo::new("index-write-tree");
+        let empty_blob = "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391";
+
+        let mut index = Index::new();
+        for path in ["a.txt", "a/b/f", "a-b/x", "run"] {
+            let mut entry = entry(path, 0);
+            entry.hash = empty_blob.to_owned();
+            if path == "run" {
+                entry.mode = 0o100755;
+            }
+            index.add(entry);
+        }
+        // Hashes as given by git for the same index
+        let root = "9474fe7404ac84299e3c5847c9b89fa52b35d505";
+        assert_eq!(index.write_tree(&repo).unwrap(), root);
+        assert_eq!(Object::read_tree(&repo, root).unwrap().len(), 4);
+        let cache_tree = index.cache_tree.clone().unwrap();
+        assert_eq!(cache_tree.entry_count, Some(4));
+        let names: Vec<_> = cache_tree
+            .children
+            .iter()
+            .map(|c| c.name.as_str())
+            .collect();
+        assert_eq!(names, ["a", "a-b"]);
+        let a = cache_tree.find("a").unwrap();
+        assert_eq!(
+            a.hash.as_deref(),
+            Some("4fc42e9019f701143c3cc95a81c1ba92e19ebca3")
+        );
+
+        // Intended additions are left out of the tree, and leave its cached tree invalid
+        let mut intent = entry("a/new", 0);
+        intent.intent_to_add = true;
+        index.add(intent);
+        assert_eq!(index.write_tree(&repo).unwrap(), root);
+        assert_eq!(
+            index.cache_tree.as_ref().unwrap().find("a").unwrap().hash,
+            None
+        );
+        assert!(index.remove("a/new"));
+
+        index.add(entry("a.txt", 1));
+        assert!(matches!(index.write_tree(&repo), Err(Error::Unmerged(path)) if path == "a.txt"));
+    }
+
+    #[test]
+    fn test_corrupt_index() {
+        let mut encoded = sample_index(2).encode();
+        let len = encoded.len();
+        encoded[len - 1] ^= 1;
+        assert!(Index::parse(&encoded).is_err());
+        assert!(Index::parse(b"DIRC").is_err());
+    }
+}
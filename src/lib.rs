@@ -0,0 +1,51 @@
+//! A small implementation of git: reading and writing objects in a repository's store,
+//! parsing and indexing pack files, and cloning over the smart HTTP protocol.
+//!
+//! ```no_run
+//! use git_starter_rust::{Object, Repository};
+//!
+//! let repo = Repository::discover()?;
+//! let object = Object::new_from_path("README.md")?;
+//! object.add(&repo)?;
+//! println!("{}", object.hash());
+//! # Ok::<(), git_starter_rust::Error>(())
+//! ```
+
+#![warn(missing_docs)]
+
+pub mod checkout;
+pub mod config;
+mod date;
+pub mod error;
+pub mod grep;
+pub mod identity;
+pub mod ignore;
+pub mod index;
+mod lockfile;
+pub mod object;
+pub mod pack;
+pub mod pack_index;
+mod pack_store;
+pub mod pathspec;
+pub mod ref_format;
+pub mod refs;
+pub mod repository;
+pub mod revision;
+pub mod revwalk;
+pub mod status;
+#[cfg(test)]
+mod test_util;
+pub mod transfer;
+mod util;
+mod wildmatch;
+pub mod worktree;
+
+pub use config::{Config, ConfigFile, Scope};
+pub use error::{Error, Result};
+pub use object::{Commit, Object, Signature, TreeEntry};
+pub use pack::{parse_pack_file, PackedObject};
+pub use pack_index::PackIndex;
+pub use refs::RefTransaction;
+pub use repository::Repository;
+pub use revwalk::RevWalk;
+pub use transfer::{clone, get_refs, Ref};
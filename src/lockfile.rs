@@ -0,0 +1,73 @@
+//! Lock files, which are how git makes changes to files atomically: the new contents are written
+//! to `<path>.lock`, which can only be created by one process at a time, and then renamed over
+//! the original.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::error::{Error, Result};
+
+/// A held lock on a file. The lock is released without changing the file if it is dropped
+/// without being committed.
+pub(crate) struct LockFile {
+    path: PathBuf,
+    lock_path: PathBuf,
+    file: Option<File>,
+}
+
+impl LockFile {
+    /// Take the lock on `path`, failing if someone else holds it.
+    pub fn acquire(path: &Path) -> Result<Self> {
+        let mut lock_path = path.as_os_str().to_owned();
+        lock_path.push(".lock");
+        let lock_path = PathBuf::from(lock_path);
+
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+            .map_err(|err| match err.kind() {
+                std::io::ErrorKind::AlreadyExists => Error::Locked(lock_path.clone()),
+                _ => err.into(),
+            })?;
+
+        Ok(LockFile {
+            path: path.to_owned(),
+            lock_path,
+            file: Some(file),
+        })
+    }
+
+    /// Write to the new contents of the file.
+    pub fn write_all(&mut self, data: &[u8]) -> Result<()> {
+        self.file.as_mut().unwrap().write_all(data)?;
+        Ok(())
+    }
+
+    /// Replace the file with what has been written, releasing the lock.
+    pub fn commit(mut self) -> Result<()> {
+        let file = self.file.take().unwrap();
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&self.lock_path, &self.path)?;
+        Ok(())
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.lock_path);
+        }
+    }
+}
+
+/// Replace the contents of a file atomically.
+pub(crate) fn write_locked(path: &Path, data: &[u8]) -> Result<()> {
+    let mut lock = LockFile::acquire(path)?;
+    lock.write_all(data)?;
+    lock.commit()
+}
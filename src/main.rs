@@ -1,42 +1,128 @@
 use anyhow::Result;
 use clap::{Parser, Subcommand};
-use std::{fs, path::PathBuf};
+use std::{
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
 
-use object::Object;
-
-mod object;
-mod pack;
-mod transfer;
-mod util;
+use git_starter_rust::{
+    checkout::Checkout,
+    grep, identity,
+    ignore::Ignore,
+    index::{self, Index},
+    object::read_tree_recursive,
+    pathspec::{self, Pathspec},
+    ref_format::{self, RefDetails},
+    refs::{self, NULL_HASH},
+    revision::{self, DEFAULT_ABBREV},
+    status::{BranchStatus, Status, UntrackedFiles},
+    worktree::{self, FileModes},
+    Commit, Config, ConfigFile, Object, RefTransaction, Repository, RevWalk, Scope,
+};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
+    /// Run as if started in this directory
+    #[arg(short = 'C')]
+    directory: Option<PathBuf>,
     #[command(subcommand)]
     command: Commands,
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    Add,
+    Add {
+        #[arg(short = 'A', long, conflicts_with = "update")]
+        all: bool,
+        #[arg(short, long)]
+        update: bool,
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+        /// Add files even if they're ignored
+        #[arg(short, long)]
+        force: bool,
+        pathspec: Vec<String>,
+    },
     CatFile {
         #[arg(short)]
         pretty_print: bool,
         object_name: String,
     },
-    CheckIgnore,
-    Checkout,
+    CheckIgnore {
+        /// Show the pattern that matched each path
+        #[arg(short, long)]
+        verbose: bool,
+        /// Show paths that don't match any pattern too
+        #[arg(short, long, requires = "verbose")]
+        non_matching: bool,
+        /// Check tracked files as well, rather than treating them as never ignored
+        #[arg(long)]
+        no_index: bool,
+        /// Read paths from standard input, one per line
+        #[arg(long, conflicts_with = "paths")]
+        stdin: bool,
+        paths: Vec<String>,
+    },
+    Checkout {
+        /// Create a branch with this name, and switch to it
+        #[arg(short = 'b')]
+        new_branch: Option<String>,
+        /// Throw away local changes
+        #[arg(short, long)]
+        force: bool,
+        /// Detach HEAD at the commit, even if it's a branch
+        #[arg(long)]
+        detach: bool,
+        /// The branch or commit to check out
+        target: Option<String>,
+    },
     Clone {
         repo_url: reqwest::Url,
         directory: PathBuf,
     },
-    Commit,
+    Commit {
+        #[arg(short, long)]
+        message: Vec<String>,
+        #[arg(short = 'F', long, conflicts_with = "message")]
+        file: Option<PathBuf>,
+        #[arg(long)]
+        amend: bool,
+        #[arg(long)]
+        allow_empty: bool,
+    },
+    Config {
+        #[arg(long, global = true, group = "scope")]
+        global: bool,
+        #[arg(long, global = true, group = "scope")]
+        system: bool,
+        #[arg(long, global = true, group = "scope")]
+        local: bool,
+        #[arg(long, short, global = true, group = "scope")]
+        file: Option<PathBuf>,
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
     CommitTree {
         tree_hash: String,
         #[arg(short)]
         parents: Vec<String>,
         #[arg(short)]
-        message: String,
+        message: Vec<String>,
+        #[arg(short = 'F')]
+        file: Vec<PathBuf>,
+    },
+    ForEachRef {
+        /// Format for each ref, with `%(atom)` placeholders like `%(refname)`
+        #[arg(long)]
+        format: Option<String>,
+        /// Sort by this atom (descending with a leading `-`); the last given is the primary key
+        #[arg(long)]
+        sort: Vec<String>,
+        #[arg(long)]
+        count: Option<usize>,
+        patterns: Vec<String>,
     },
     HashObject {
         #[arg(short)]
@@ -44,8 +130,29 @@ enum Commands {
         path: PathBuf,
     },
     Init,
-    Log,
-    LsFiles,
+    Log {
+        #[arg(long)]
+        oneline: bool,
+        #[arg(short = 'n', long)]
+        max_count: Option<usize>,
+        #[arg(long)]
+        first_parent: bool,
+        #[arg(long)]
+        reverse: bool,
+        /// Show only commits whose author (`Name <email>`) matches this basic regular expression
+        #[arg(long)]
+        author: Vec<String>,
+        /// Show only commits with a line of their message matching this basic regular expression
+        #[arg(long)]
+        grep: Vec<String>,
+        /// Commits to show the history of (`HEAD` by default), `^commit` to leave out a commit's
+        /// history, or `from..to`
+        revisions: Vec<String>,
+    },
+    LsFiles {
+        #[arg(short, long)]
+        stage: bool,
+    },
     LsRemote {
         repo_url: reqwest::Url,
     },
@@ -54,135 +161,1154 @@ enum Commands {
         name_only: bool,
         tree_hash: String,
     },
-    RevParse,
-    Rm,
-    ShowRef,
-    Status,
-    Tag,
+    RevParse {
+        #[arg(long)]
+        verify: bool,
+        /// Abbreviate hashes, to at least this many digits
+        #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "7")]
+        short: Option<usize>,
+        revisions: Vec<String>,
+    },
+    Rm {
+        #[arg(long)]
+        cached: bool,
+        #[arg(short)]
+        recursive: bool,
+        #[arg(short, long)]
+        force: bool,
+        #[arg(required = true)]
+        pathspec: Vec<String>,
+    },
+    ShowRef {
+        #[arg(long)]
+        heads: bool,
+        #[arg(long)]
+        tags: bool,
+        #[arg(long)]
+        verify: bool,
+        #[arg(short, long)]
+        dereference: bool,
+        #[arg(short = 's', long)]
+        hash: bool,
+        patterns: Vec<String>,
+    },
+    Status {
+        #[arg(short, long)]
+        short: bool,
+        /// Output in a stable format for scripts: `v1` (the default) or `v2`
+        #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "v1")]
+        porcelain: Option<String>,
+        /// Show the branch and how it compares with its upstream, in short and porcelain output
+        #[arg(short, long)]
+        branch: bool,
+        /// Which untracked files to show: `no`, `normal` or `all`
+        #[arg(
+            short = 'u',
+            long,
+            num_args = 0..=1,
+            default_value = "normal",
+            default_missing_value = "all"
+        )]
+        untracked_files: String,
+        pathspec: Vec<String>,
+    },
+    Switch {
+        /// Create a branch with this name, and switch to it
+        #[arg(short, long)]
+        create: Option<String>,
+        /// Throw away local changes
+        #[arg(short, long, visible_alias = "discard-changes")]
+        force: bool,
+        /// Detach HEAD at a commit, rather than switching to a branch
+        #[arg(short, long)]
+        detach: bool,
+        /// The branch to switch to, or commit to start a new branch at or detach HEAD at
+        target: Option<String>,
+    },
+    Tag {
+        #[arg(short)]
+        annotate: bool,
+        #[arg(short)]
+        message: Option<String>,
+        #[arg(short)]
+        force: bool,
+        #[arg(short, conflicts_with_all = ["annotate", "message", "force", "object"])]
+        delete: bool,
+        name: Option<String>,
+        object: Option<String>,
+    },
+    UpdateRef {
+        /// Reason for the update, for the reflog
+        #[arg(short)]
+        message: Option<String>,
+        #[arg(short)]
+        delete: bool,
+        /// Update symbolic refs themselves, rather than the refs they point to
+        #[arg(long)]
+        no_deref: bool,
+        /// Read commands from standard input, and carry out all of them or none
+        #[arg(long, conflicts_with_all = ["delete", "args"])]
+        stdin: bool,
+        /// The ref, its new value (unless deleting) and, optionally, its expected old value
+        args: Vec<String>,
+    },
     WriteTree,
 }
 
-fn main() -> Result<()> {
+#[derive(Subcommand)]
+enum ConfigAction {
+    Get {
+        #[arg(long)]
+        all: bool,
+        name: String,
+    },
+    Set {
+        #[arg(long, conflicts_with = "append")]
+        all: bool,
+        #[arg(long)]
+        append: bool,
+        name: String,
+        value: String,
+    },
+    Unset {
+        #[arg(long)]
+        all: bool,
+        name: String,
+    },
+    List {
+        #[arg(long)]
+        show_scope: bool,
+    },
+}
+
+fn main() -> ExitCode {
     let cli = Cli::parse();
 
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("fatal: {}", err);
+            ExitCode::from(128)
+        }
+    }
+}
+
+fn run(cli: Cli) -> Result<()> {
+    if let Some(directory) = cli.directory {
+        std::env::set_current_dir(directory)?;
+    }
+
     match cli.command {
-        Commands::Add => add(),
+        Commands::Add {
+            all,
+            update,
+            dry_run,
+            force,
+            pathspec,
+        } => add(&pathspec, all, update, dry_run, force),
         Commands::CatFile {
             pretty_print,
             object_name,
         } => cat_file(&object_name, pretty_print),
-        Commands::CheckIgnore => check_ignore(),
-        Commands::Checkout => checkout(),
+        Commands::CheckIgnore {
+            verbose,
+            non_matching,
+            no_index,
+            stdin,
+            paths,
+        } => {
+            let paths = match stdin {
+                true => std::io::stdin().lines().collect::<std::io::Result<_>>()?,
+                false => paths,
+            };
+            check_ignore(&paths, verbose, non_matching, no_index)
+        }
+        Commands::Checkout {
+            new_branch,
+            force,
+            detach,
+            target,
+        } => checkout(
+            target.as_deref(),
+            new_branch.as_deref(),
+            &CheckoutOptions {
+                force,
+                detach,
+                branch_only: false,
+            },
+        ),
         Commands::Clone {
             repo_url,
             directory,
         } => clone(repo_url, directory),
-        Commands::Commit => commit(),
+        Commands::Commit {
+            message,
+            file,
+            amend,
+            allow_empty,
+        } => commit(
+            &message,
+            file.as_ref().map(std::slice::from_ref).unwrap_or_default(),
+            amend,
+            allow_empty,
+        ),
+        Commands::Config {
+            global,
+            system,
+            local,
+            file,
+            action,
+        } => {
+            let scope = match (global, system, local) {
+                (true, _, _) => Some(Scope::Global),
+                (_, true, _) => Some(Scope::System),
+                (_, _, true) => Some(Scope::Local),
+                _ => None,
+            };
+            config(scope, file, action)
+        }
         Commands::CommitTree {
             tree_hash,
             parents,
             message,
-        } => commit_tree(&tree_hash, &parents, &message),
+            file,
+        } => commit_tree(&tree_hash, &parents, &message, &file),
+        Commands::ForEachRef {
+            format,
+            sort,
+            count,
+            patterns,
+        } => for_each_ref(&patterns, format.as_deref(), &sort, count),
         Commands::HashObject { write, path } => hash_object(path, write),
         Commands::Init => init(),
-        Commands::Log => log(),
-        Commands::LsFiles => ls_files(),
+        Commands::Log {
+            oneline,
+            max_count,
+            first_parent,
+            reverse,
+            author,
+            grep,
+            revisions,
+        } => log(
+            &revisions,
+            &LogOptions {
+                oneline,
+                max_count,
+                first_parent,
+                reverse,
+                authors: author,
+                greps: grep,
+            },
+        ),
+        Commands::LsFiles { stage } => ls_files(stage),
         Commands::LsRemote { repo_url } => ls_remote(repo_url),
         Commands::LsTree {
             name_only,
             tree_hash,
         } => ls_tree(&tree_hash, name_only),
-        Commands::RevParse => rev_parse(),
-        Commands::Rm => rm(),
-        Commands::ShowRef => show_ref(),
-        Commands::Status => status(),
-        Commands::Tag => tag(),
+        Commands::RevParse {
+            verify,
+            short,
+            revisions,
+        } => rev_parse(&revisions, verify, short),
+        Commands::Rm {
+            cached,
+            recursive,
+            force,
+            pathspec,
+        } => rm(&pathspec, cached, recursive, force),
+        Commands::ShowRef {
+            heads,
+            tags,
+            verify,
+            dereference,
+            hash,
+            patterns,
+        } => show_ref(
+            &patterns,
+            &ShowRefOptions {
+                heads,
+                tags,
+                verify,
+                dereference,
+                hash_only: hash,
+            },
+        ),
+        Commands::Status {
+            short,
+            porcelain,
+            branch,
+            untracked_files,
+            pathspec,
+        } => {
+            let format = match (porcelain.as_deref(), short) {
+                (Some("v1" | "1"), _) => StatusFormat::Porcelain,
+                (Some("v2" | "2"), _) => StatusFormat::PorcelainV2,
+                (Some(version), _) => anyhow::bail!("unsupported porcelain version '{}'", version),
+                (None, true) => StatusFormat::Short,
+                (None, false) => StatusFormat::Long,
+            };
+            let untracked_files = match untracked_files.as_str() {
+                "no" => UntrackedFiles::No,
+                "normal" => UntrackedFiles::Normal,
+                "all" => UntrackedFiles::All,
+                other => anyhow::bail!("invalid untracked files mode '{}'", other),
+            };
+            status(&pathspec, format, branch, untracked_files)
+        }
+        Commands::Switch {
+            create,
+            force,
+            detach,
+            target,
+        } => checkout(
+            target.as_deref(),
+            create.as_deref(),
+            &CheckoutOptions {
+                force,
+                detach,
+                branch_only: true,
+            },
+        ),
+        Commands::Tag {
+            annotate,
+            message,
+            force,
+            delete,
+            name,
+            object,
+        } => match name {
+            Some(name) if delete => delete_tag(&name),
+            Some(name) => tag(
+                &name,
+                object.as_deref(),
+                annotate,
+                message.as_deref(),
+                force,
+            ),
+            None if annotate || message.is_some() || delete => {
+                anyhow::bail!("a tag name is required")
+            }
+            None => list_tags(),
+        },
+        Commands::UpdateRef {
+            message,
+            delete,
+            no_deref,
+            stdin,
+            args,
+        } => update_ref(&args, message.as_deref(), delete, no_deref, stdin),
         Commands::WriteTree => write_tree(),
     }
 }
 
-fn add() -> Result<()> {
-    todo!("add")
+fn add(args: &[String], all: bool, update: bool, dry_run: bool, force: bool) -> Result<()> {
+    let repo = Repository::discover()?;
+    let work_tree = repo.work_tree()?;
+    let pathspec = match (args.is_empty(), all || update) {
+        (false, _) => Pathspec::new(&repo, args)?,
+        // -A and -u apply to the whole tree if no paths are given
+        (true, true) => Pathspec::all(),
+        (true, false) => anyhow::bail!("nothing specified, nothing added"),
+    };
+
+    let ignore = Ignore::new(&repo)?;
+    let mut index = Index::read(&repo)?;
+    let modes = FileModes::load(&repo)?;
+    let staging = worktree::changes_to_stage(
+        &repo,
+        &index,
+        &pathspec,
+        (!force).then_some(&ignore),
+        update,
+        &modes,
+    )?;
+
+    // Ignored paths that were named explicitly are reported, once everything else is added
+    let mut ignored = Vec::new();
+    for i in (0..staging.matched.len()).filter(|&i| !staging.matched[i]) {
+        let path = &pathspec.patterns()[i];
+        let exists = std::fs::symlink_metadata(work_tree.join(path)).is_ok();
+        if !force && exists && ignore.is_ignored(path, work_tree.join(path).is_dir())? {
+            ignored.push(&args[i]);
+            continue;
+        }
+        anyhow::bail!("pathspec '{}' did not match any files", args[i]);
+    }
+
+    for path in staging.additions {
+        if dry_run {
+            println!("add '{}'", path);
+            continue;
+        }
+        worktree::add_file(&repo, &mut index, &path, &modes)?;
+    }
+    for path in staging.removals {
+        if dry_run {
+            println!("remove '{}'", path);
+            continue;
+        }
+        index.remove(&path);
+    }
+
+    if !dry_run {
+        index.write(&repo)?;
+    }
+
+    if !ignored.is_empty() {
+        eprintln!("The following paths are ignored by one of your .gitignore files:");
+        for path in ignored {
+            eprintln!("{}", path);
+        }
+        eprintln!("hint: Use -f if you really want to add them.");
+        std::process::exit(1);
+    }
+    Ok(())
 }
 
 fn cat_file(object_name: &str, pretty_print: bool) -> Result<()> {
-    assert!(pretty_print);
+    if !pretty_print {
+        anyhow::bail!("only pretty-printing (-p) is supported");
+    }
 
-    // Should be a SHA1 hash
-    assert_eq!(object_name.len(), 40);
+    let repo = Repository::discover()?;
+    let object = Object::parse_from_hash(&repo, &revision::resolve(&repo, object_name)?)?;
+    object.print()?;
 
-    let mut path = PathBuf::from(".git/objects");
-    path.push(&object_name[0..2]);
-    path.push(&object_name[2..40]);
+    Ok(())
+}
 
-    let object = Object::parse_from_path(path)?;
-    assert!(matches!(object, Object::Blob(_)));
-    object.print();
+fn check_ignore(paths: &[String], verbose: bool, non_matching: bool, no_index: bool) -> Result<()> {
+    if paths.is_empty() {
+        anyhow::bail!("no path specified");
+    }
+
+    let repo = Repository::discover()?;
+    let work_tree = repo.work_tree()?;
+    let ignore = Ignore::new(&repo)?;
+    let index = if no_index {
+        Index::new()
+    } else {
+        Index::read(&repo)?
+    };
+
+    let mut any_ignored = false;
+    for (arg, path) in paths.iter().zip(Pathspec::new(&repo, paths)?.patterns()) {
+        // Tracked files are never ignored
+        let tracked = index.entries().iter().any(|e| &e.path == path);
+        let is_dir = arg.ends_with('/') || work_tree.join(path).is_dir();
+        let found = match tracked {
+            true => None,
+            false => ignore.find_match(path, is_dir)?,
+        };
+        match found.as_ref() {
+            Some(found) if verbose => {
+                println!(
+                    "{}:{}:{}\t{}",
+                    found.source.display(),
+                    found.line,
+                    found.pattern,
+                    arg
+                );
+            }
+            Some(found) if !found.negated => println!("{}", arg),
+            None if non_matching => println!("::\t{}", arg),
+            _ => (),
+        }
+        any_ignored |= found.is_some_and(|f| !f.negated);
+    }
+    if !any_ignored {
+        std::process::exit(1);
+    }
 
     Ok(())
 }
 
-fn check_ignore() -> Result<()> {
-    todo!("check_ignore")
+struct CheckoutOptions {
+    force: bool,
+    detach: bool,
+    /// Only switch to branches (unless detaching), as `switch` does
+    branch_only: bool,
 }
 
-fn checkout() -> Result<()> {
-    todo!("checkout")
+/// Switch `HEAD` to a branch or (detached) commit, optionally creating a new branch there, and
+/// update the working tree and index to match.
+fn checkout(
+    target: Option<&str>,
+    new_branch: Option<&str>,
+    options: &CheckoutOptions,
+) -> Result<()> {
+    let repo = Repository::discover()?;
+    let old_branch = repo.symbolic_ref("HEAD")?;
+    let old_head = repo.read_ref("HEAD")?;
+    let resolve = |name: &str| {
+        revision::resolve_commit(&repo, name).map_err(|_| match options.branch_only {
+            true => anyhow::anyhow!("invalid reference: {}", name),
+            false => anyhow::anyhow!("pathspec '{}' did not match any file(s) known to git", name),
+        })
+    };
+
+    // The branch HEAD will point to (or `None` if it'll be detached), and the commit
+    let (branch, hash) = match (new_branch, target) {
+        (Some(name), target) => {
+            let ref_name = format!("refs/heads/{}", name);
+            if repo.read_ref(&ref_name)?.is_some() {
+                anyhow::bail!("a branch named '{}' already exists", name);
+            }
+            // The working tree mustn't change if the branch can't be created
+            match refs::check_new_ref(&repo, &ref_name) {
+                Err(git_starter_rust::Error::InvalidRef(_)) => {
+                    anyhow::bail!("'{}' is not a valid branch name", name)
+                }
+                result => result?,
+            }
+            let hash = match (target, old_head.as_ref()) {
+                (Some(target), _) => resolve(target)?,
+                (None, Some(head)) => head.clone(),
+                (None, None) => anyhow::bail!("you are on a branch yet to be born"),
+            };
+            (Some(ref_name), hash)
+        }
+        (None, target) => {
+            let target = match (target, options.detach) {
+                (Some(target), _) => target,
+                (None, true) => "HEAD",
+                (None, false) => anyhow::bail!("missing branch or commit argument"),
+            };
+            let ref_name = format!("refs/heads/{}", target);
+            match repo.read_ref(&ref_name)? {
+                Some(hash) if !options.detach => (Some(ref_name), hash),
+                // Checking out HEAD stays on the current branch
+                _ if target == "HEAD" && !options.detach => {
+                    let head = resolve(target)?;
+                    (old_branch.clone(), head)
+                }
+                _ => {
+                    let hash = resolve(target)?;
+                    if options.branch_only && !options.detach {
+                        anyhow::bail!("a branch is expected, got commit '{}'", target);
+                    }
+                    (None, hash)
+                }
+            }
+        }
+    };
+
+    let mut index = Index::read(&repo)?;
+    if index.has_conflicts() && !options.force {
+        anyhow::bail!("you need to resolve your current index first");
+    }
+    let old_tree = match old_head.as_deref() {
+        Some(head) => Some(Object::read_commit(&repo, head)?.tree_hash),
+        None => None,
+    };
+    let new_commit = Object::read_commit(&repo, &hash)?;
+    let checkout = Checkout::new(
+        &repo,
+        &index,
+        old_tree.as_deref(),
+        &new_commit.tree_hash,
+        options.force,
+    )?;
+    if checkout.is_blocked() {
+        if !checkout.modified.is_empty() {
+            eprintln!("error: Your local changes to the following files would be overwritten by checkout:");
+            for path in checkout.modified.iter() {
+                eprintln!("\t{}", path);
+            }
+            eprintln!("Please commit your changes or stash them before you switch branches.");
+        }
+        if !checkout.untracked.is_empty() {
+            eprintln!("error: The following untracked working tree files would be overwritten by checkout:");
+            for path in checkout.untracked.iter() {
+                eprintln!("\t{}", path);
+            }
+            eprintln!("Please move or remove them before you switch branches.");
+        }
+        eprintln!("Aborting");
+        std::process::exit(1);
+    }
+    checkout.apply(&repo, &mut index)?;
+    index.write(&repo)?;
+
+    // Checking out shouldn't fail just because there's nobody to record in the reflogs
+    let committer = identity::committer(&repo).ok();
+    if let Some(name) = new_branch {
+        let mut transaction = RefTransaction::new(&repo);
+        transaction.update(&format!("refs/heads/{}", name), &hash, Some(NULL_HASH));
+        if let Some(committer) = committer.clone() {
+            let message = format!("branch: Created from {}", target.unwrap_or("HEAD"));
+            transaction.reflog(committer, &message);
+        }
+        transaction.commit()?;
+    }
+    let from = match old_branch.as_deref() {
+        Some(branch) => refs::shorten(branch),
+        None => old_head.as_deref().unwrap_or_default(),
+    };
+    let to = match (new_branch, branch.as_deref()) {
+        (Some(name), _) => name,
+        (None, Some(branch)) => refs::shorten(branch),
+        (None, None) => target.unwrap_or("HEAD"),
+    };
+    let message = format!("checkout: moving from {} to {}", from, to);
+    let unchanged = branch == old_branch && old_head.as_ref() == Some(&hash);
+    match branch.as_deref() {
+        Some(_) if unchanged => (),
+        Some(branch) => {
+            repo.write_symbolic_ref("HEAD", branch)?;
+            if let Some(committer) = committer.as_ref() {
+                repo.append_reflog("HEAD", old_head.as_deref(), &hash, committer, &message)?;
+            }
+        }
+        None => {
+            let mut transaction = RefTransaction::new(&repo);
+            transaction.no_deref(true);
+            transaction.update("HEAD", &hash, None);
+            if let Some(committer) = committer {
+                transaction.reflog(committer, &message);
+            }
+            transaction.commit()?;
+        }
+    }
+
+    let describe = |hash: &str, commit: &Commit| -> Result<String> {
+        let subject = String::from_utf8_lossy(&commit.message);
+        let subject = subject.lines().next().unwrap_or("").to_owned();
+        Ok(format!(
+            "{} {}",
+            revision::abbreviate(&repo, hash, DEFAULT_ABBREV)?,
+            subject
+        ))
+    };
+    if let (None, Some(old_head)) = (old_branch.as_ref(), old_head.as_ref()) {
+        if *old_head != hash || branch.is_some() {
+            let old_commit = Object::read_commit(&repo, old_head)?;
+            eprintln!(
+                "Previous HEAD position was {}",
+                describe(old_head, &old_commit)?
+            );
+        }
+    }
+    match (new_branch, branch.as_deref()) {
+        (Some(name), _) => eprintln!("Switched to a new branch '{}'", name),
+        (None, Some(_)) if unchanged => eprintln!("Already on '{}'", to),
+        (None, Some(_)) => eprintln!("Switched to branch '{}'", to),
+        (None, None) => {
+            if old_branch.is_some() {
+                eprintln!("Note: switching to '{}'.", to);
+                eprintln!();
+                eprintln!(
+                    "You are in 'detached HEAD' state. You can look around, make experimental"
+                );
+                eprintln!(
+                    "changes and commit them, and you can discard any commits you make in this"
+                );
+                eprintln!("state without impacting any branches by switching back to a branch.");
+                eprintln!();
+            }
+            eprintln!("HEAD is now at {}", describe(&hash, &new_commit)?);
+        }
+    }
+
+    Ok(())
 }
 
 fn clone(repo_url: reqwest::Url, directory: PathBuf) -> Result<()> {
-    transfer::clone(repo_url, directory)
+    Ok(git_starter_rust::clone(repo_url, directory)?)
 }
 
-fn commit() -> Result<()> {
-    todo!("commit")
+fn commit(messages: &[String], file: &[PathBuf], amend: bool, allow_empty: bool) -> Result<()> {
+    let repo = Repository::discover()?;
+    // The ref to advance, which is `HEAD` itself if it's detached
+    let ref_name = repo
+        .symbolic_ref("HEAD")?
+        .unwrap_or_else(|| "HEAD".to_owned());
+    let head = repo.read_ref("HEAD")?;
+
+    let amended = match (amend, head.as_deref()) {
+        (true, Some(head)) => Some(Object::read_commit(&repo, head)?),
+        (true, None) => anyhow::bail!("You have nothing to amend."),
+        (false, _) => None,
+    };
+    let parents = match amended.as_ref() {
+        Some(amended) => amended.parent_hashes.clone(),
+        None => head.iter().cloned().collect(),
+    };
+
+    let message = match (read_message(messages, file)?, amended.as_ref()) {
+        (Some(message), _) => message,
+        // A message in another encoding would be corrupted by reusing it as UTF-8
+        (None, Some(amended)) => String::from_utf8(amended.message.clone()).map_err(|_| {
+            anyhow::anyhow!(
+                "the commit being amended has a message that isn't UTF-8; give a new one with -m \
+                 or -F"
+            )
+        })?,
+        (None, None) => anyhow::bail!("no commit message given (use -m or -F)"),
+    };
+    let message = cleanup_message(&message);
+    if message.is_empty() {
+        eprintln!("Aborting commit due to empty commit message.");
+        std::process::exit(1);
+    }
+
+    let mut index = Index::read(&repo)?;
+    let tree_hash = index.write_tree(&repo)?;
+    index.write(&repo)?;
+
+    let parent_tree = match parents.first() {
+        Some(parent) => Object::read_commit(&repo, parent)?.tree_hash,
+        None => Object::Tree(Vec::new()).hash(),
+    };
+    if tree_hash == parent_tree && parents.len() < 2 && !allow_empty {
+        println!("nothing to commit (use --allow-empty to commit anyway)");
+        std::process::exit(1);
+    }
+
+    // Amending keeps the original author
+    let author = match amended {
+        Some(amended) => amended.author,
+        None => identity::author(&repo)?,
+    };
+    let committer = identity::committer(&repo)?;
+    let parent_hashes = parents.iter().map(String::as_str).collect::<Vec<_>>();
+    let object = Object::new_commit(
+        &tree_hash,
+        &parent_hashes,
+        author,
+        committer.clone(),
+        &message,
+    );
+    object.add(&repo)?;
+    let hash = object.hash();
+
+    let subject = message.lines().next().unwrap_or("");
+    let reflog_message = match (amend, parents.is_empty()) {
+        (true, _) => format!("commit (amend): {}", subject),
+        (false, true) => format!("commit (initial): {}", subject),
+        (false, false) => format!("commit: {}", subject),
+    };
+    // Updating HEAD advances the branch it points to, as long as nothing else has moved it
+    let mut transaction = RefTransaction::new(&repo);
+    transaction.update("HEAD", &hash, Some(head.as_deref().unwrap_or(NULL_HASH)));
+    transaction.reflog(committer, &reflog_message);
+    transaction.commit()?;
+
+    let branch = ref_name
+        .strip_prefix("refs/heads/")
+        .unwrap_or("detached HEAD");
+    let root = if parents.is_empty() {
+        " (root-commit)"
+    } else {
+        ""
+    };
+    println!("[{}{} {}] {}", branch, root, &hash[0..7], subject);
+
+    Ok(())
 }
 
-fn commit_tree(tree_hash: &str, parents: &[String], message: &str) -> Result<()> {
-    // Should be a SHA1 hash
-    assert_eq!(tree_hash.len(), 40);
+/// Read a commit message given as `-m` paragraphs and the contents of `-F` files (`-` for
+/// standard input), or `None` if there isn't one.
+fn read_message(messages: &[String], files: &[PathBuf]) -> Result<Option<String>> {
+    if messages.is_empty() && files.is_empty() {
+        return Ok(None);
+    }
 
-    assert_eq!(parents.len(), 1);
-    let parent_hash = parents.first().unwrap();
-    // Should be a SHA1 hash
-    assert_eq!(parent_hash.len(), 40);
+    let mut message = String::new();
+    for paragraph in messages {
+        if !message.is_empty() {
+            message.push('\n');
+        }
+        message.push_str(paragraph);
+        if !message.ends_with('\n') {
+            message.push('\n');
+        }
+    }
+    for path in files {
+        if !message.is_empty() {
+            message.push('\n');
+        }
+        if path == Path::new("-") {
+            std::io::stdin().read_to_string(&mut message)?;
+        } else {
+            message.push_str(&std::fs::read_to_string(path)?);
+        }
+    }
+    Ok(Some(message))
+}
+
+/// Tidy up whitespace in a commit message like git's default for messages that weren't
+/// edited: trailing whitespace and leading and trailing blank lines are removed, runs of blank
+/// lines are collapsed into one, and the last line ends with a newline.
+fn cleanup_message(message: &str) -> String {
+    let mut cleaned = String::new();
+    let mut blank_lines = false;
+    for line in message.lines().map(str::trim_end) {
+        if line.is_empty() {
+            blank_lines = true;
+            continue;
+        }
+        if blank_lines && !cleaned.is_empty() {
+            cleaned.push('\n');
+        }
+        blank_lines = false;
+        cleaned.push_str(line);
+        cleaned.push('\n');
+    }
+    cleaned
+}
+
+fn config(scope: Option<Scope>, file: Option<PathBuf>, action: ConfigAction) -> Result<()> {
+    // Only local config needs a repository, but it is used for includeIf if there is one
+    let repo = Repository::discover();
+    let repo = match (repo, scope) {
+        (Ok(repo), _) => Some(repo),
+        (Err(err), Some(Scope::Local)) => return Err(err.into()),
+        (Err(_), _) => None,
+    };
+    let repo = repo.as_ref();
+
+    match action {
+        ConfigAction::Get { all, name } => {
+            let config = load_config(scope, file.as_deref(), repo)?;
+            let values = match all {
+                true => config.get_all(&name),
+                false => config.get(&name).into_iter().collect(),
+            };
+            if values.is_empty() {
+                std::process::exit(1);
+            }
+            for value in values {
+                println!("{}", value);
+            }
+        }
+        ConfigAction::Set {
+            all,
+            append,
+            name,
+            value,
+        } => {
+            let mut config_file = ConfigFile::open(config_file_path(scope, file, repo)?)?;
+            if append {
+                config_file.append(&name, &value)?;
+            } else {
+                config_file.set(&name, &value, all)?;
+            }
+            config_file.save()?;
+        }
+        ConfigAction::Unset { all, name } => {
+            let mut config_file = ConfigFile::open(config_file_path(scope, file, repo)?)?;
+            if config_file.unset(&name, all)? == 0 {
+                std::process::exit(5);
+            }
+            config_file.save()?;
+        }
+        ConfigAction::List { show_scope } => {
+            let config = load_config(scope, file.as_deref(), repo)?;
+            for (key, value, scope) in config.entries() {
+                if show_scope {
+                    print!("{}\t", scope.name());
+                }
+                match value {
+                    Some(value) => println!("{}={}", key, value),
+                    None => println!("{}", key),
+                }
+            }
+        }
+    }
 
-    let object = Object::new_commit(tree_hash, Some(parent_hash), message);
-    object.add()?;
+    Ok(())
+}
+
+/// Read the config from the chosen scope or file, or from all scopes.
+fn load_config(
+    scope: Option<Scope>,
+    file: Option<&std::path::Path>,
+    repo: Option<&Repository>,
+) -> Result<Config> {
+    Ok(match (file, scope) {
+        (Some(file), _) => Config::load_file(file, Scope::Local, repo)?,
+        (None, Some(scope)) => Config::load_scope(scope, repo)?,
+        (None, None) => Config::load(repo)?,
+    })
+}
+
+/// The config file that edits should be made to: the local one unless another is chosen.
+fn config_file_path(
+    scope: Option<Scope>,
+    file: Option<PathBuf>,
+    repo: Option<&Repository>,
+) -> Result<PathBuf> {
+    if let Some(file) = file {
+        return Ok(file);
+    }
+    let scope = scope.unwrap_or(Scope::Local);
+    if scope == Scope::Local && repo.is_none() {
+        anyhow::bail!("not in a git directory");
+    }
+    // Edit ~/.gitconfig rather than the XDG file, unless only the XDG file exists
+    let paths = scope.paths(repo);
+    let path = match paths.last() {
+        Some(last) if last.exists() => Some(last),
+        last => paths.iter().find(|p| p.exists()).or(last),
+    };
+    let path = path.ok_or_else(|| anyhow::anyhow!("no config file for {} scope", scope.name()))?;
+    Ok(path.clone())
+}
+
+fn commit_tree(
+    tree_hash: &str,
+    parents: &[String],
+    messages: &[String],
+    files: &[PathBuf],
+) -> Result<()> {
+    let repo = Repository::discover()?;
+    let tree_hash = revision::resolve(&repo, tree_hash)?;
+    Object::read_tree(&repo, &tree_hash)?;
+
+    let mut parent_hashes = Vec::new();
+    for parent in parents {
+        let parent = revision::resolve(&repo, parent)?;
+        if parent_hashes.contains(&parent) {
+            eprintln!("error: duplicate parent {} ignored", parent);
+            continue;
+        }
+        Object::read_commit(&repo, &parent)?;
+        parent_hashes.push(parent);
+    }
+
+    // Without -m or -F, the message comes from standard input
+    let message = match read_message(messages, files)? {
+        Some(message) => message,
+        None => {
+            let mut message = String::new();
+            std::io::stdin().read_to_string(&mut message)?;
+            message
+        }
+    };
+
+    let author = identity::author(&repo)?;
+    let committer = identity::committer(&repo)?;
+    let parent_hashes = parent_hashes.iter().map(String::as_str).collect::<Vec<_>>();
+    let object = Object::new_commit(&tree_hash, &parent_hashes, author, committer, &message);
+    object.add(&repo)?;
     println!("{}", object.hash());
 
     Ok(())
 }
 
-fn hash_object(path: PathBuf, write: bool) -> Result<()> {
-    assert!(write);
+fn for_each_ref(
+    patterns: &[String],
+    format: Option<&str>,
+    sort: &[String],
+    count: Option<usize>,
+) -> Result<()> {
+    let repo = Repository::discover()?;
+    let format = format.unwrap_or("%(objectname) %(objecttype)\t%(refname)");
+    let head = repo.symbolic_ref("HEAD")?;
 
+    let mut refs = Vec::new();
+    for reference in repo.list_refs("refs/")? {
+        if patterns.is_empty()
+            || patterns
+                .iter()
+                .any(|p| refs::matches_pattern(&reference.name, p))
+        {
+            refs.push(RefDetails::read(&repo, reference, head.as_deref())?);
+        }
+    }
+
+    let sort = if sort.is_empty() {
+        vec!["refname".to_owned()]
+    } else {
+        sort.to_vec()
+    };
+    let refs = ref_format::sort(&repo, refs, &sort)?;
+    for details in refs.iter().take(count.unwrap_or(usize::MAX)) {
+        println!("{}", ref_format::format(&repo, details, format)?);
+    }
+
+    Ok(())
+}
+
+fn hash_object(path: PathBuf, write: bool) -> Result<()> {
     let object = Object::new_from_path(path)?;
-    object.add()?;
+    if write {
+        let repo = Repository::discover()?;
+        object.add(&repo)?;
+    }
     println!("{}", object.hash());
 
     Ok(())
 }
 
 fn init() -> Result<()> {
-    fs::create_dir(".git")?;
-    fs::create_dir(".git/objects")?;
-    fs::create_dir(".git/refs")?;
-    fs::write(".git/HEAD", "ref: refs/heads/master\n")?;
+    Repository::init(std::env::current_dir()?)?;
     println!("Initialized git directory");
     Ok(())
 }
 
-fn log() -> Result<()> {
-    todo!("log")
+struct LogOptions {
+    oneline: bool,
+    max_count: Option<usize>,
+    first_parent: bool,
+    reverse: bool,
+    authors: Vec<String>,
+    greps: Vec<String>,
 }
 
-fn ls_files() -> Result<()> {
-    todo!("ls_files")
+fn log(revisions: &[String], options: &LogOptions) -> Result<()> {
+    let repo = Repository::discover()?;
+    let mut walk = RevWalk::new(&repo);
+    walk.first_parent(options.first_parent);
+
+    for argument in revisions {
+        if let Some((from, to)) = split_range(argument) {
+            walk.hide(&revision::resolve_commit(&repo, from)?)?;
+            walk.push(&revision::resolve_commit(&repo, to)?)?;
+        } else if let Some(name) = argument.strip_prefix('^') {
+            walk.hide(&revision::resolve_commit(&repo, name)?)?;
+        } else {
+            walk.push(&revision::resolve_commit(&repo, argument)?)?;
+        }
+    }
+    // Any revision, even one that only leaves commits out, means HEAD isn't shown by default
+    if revisions.is_empty() {
+        match repo.read_ref("HEAD")? {
+            Some(head) => walk.push(&head)?,
+            None => {
+                let branch = repo.symbolic_ref("HEAD")?.unwrap_or_default();
+                anyhow::bail!(
+                    "your current branch '{}' does not have any commits yet",
+                    branch.strip_prefix("refs/heads/").unwrap_or(&branch)
+                );
+            }
+        }
+    }
+
+    let mut commits = Vec::new();
+    for commit in walk {
+        if options.max_count == Some(commits.len()) {
+            break;
+        }
+        let (hash, commit) = commit?;
+        let author = format!(
+            "{} <{}>",
+            commit.author.display_name(),
+            commit.author.display_email()
+        );
+        let message = String::from_utf8_lossy(&commit.message);
+        // Both kinds of filter have to match, if given, but any of each kind will do
+        if (options.authors.is_empty()
+            || options.authors.iter().any(|a| grep::is_match(a, &author)))
+            && (options.greps.is_empty()
+                || options.greps.iter().any(|g| grep::is_match(g, &message)))
+        {
+            commits.push((hash, commit));
+        }
+    }
+    if options.reverse {
+        commits.reverse();
+    }
+
+    let mut output = std::io::BufWriter::new(std::io::stdout().lock());
+    let result = commits
+        .iter()
+        .enumerate()
+        .try_for_each(|(i, (hash, commit))| {
+            if options.oneline {
+                print_oneline(&mut output, &repo, hash, commit)
+            } else {
+                if i > 0 {
+                    writeln!(output)?;
+                }
+                print_commit(&mut output, &repo, hash, commit)
+            }
+        });
+    match result.and_then(|()| Ok(output.flush()?)) {
+        // Stop quietly if output is piped to something like `head`
+        Err(err)
+            if err
+                .downcast_ref::<std::io::Error>()
+                .is_some_and(|err| err.kind() == std::io::ErrorKind::BrokenPipe) =>
+        {
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// Print a commit in git's default format for `log`.
+fn print_commit(
+    output: &mut impl Write,
+    repo: &Repository,
+    hash: &str,
+    commit: &Commit,
+) -> Result<()> {
+    writeln!(output, "commit {}", hash)?;
+    if commit.parent_hashes.len() > 1 {
+        let parents = commit
+            .parent_hashes
+            .iter()
+            .map(|p| revision::abbreviate(repo, p, DEFAULT_ABBREV))
+            .collect::<git_starter_rust::Result<Vec<_>>>()?;
+        writeln!(output, "Merge: {}", parents.join(" "))?;
+    }
+    writeln!(
+        output,
+        "Author: {} <{}>",
+        commit.author.display_name(),
+        commit.author.display_email()
+    )?;
+    writeln!(output, "Date:   {}", commit.author.format_date())?;
+    writeln!(output)?;
+    let message = String::from_utf8_lossy(&commit.message);
+    for line in message.trim_end().lines() {
+        writeln!(output, "    {}", line)?;
+    }
+    Ok(())
+}
+
+/// Print a commit as `log --oneline` does: its abbreviated hash and subject.
+fn print_oneline(
+    output: &mut impl Write,
+    repo: &Repository,
+    hash: &str,
+    commit: &Commit,
+) -> Result<()> {
+    let message = String::from_utf8_lossy(&commit.message);
+    // The subject is the first paragraph, even if it's over more than one line
+    let subject = message
+        .lines()
+        .skip_while(|line| line.trim().is_empty())
+        .take_while(|line| !line.trim().is_empty())
+        .map(str::trim)
+        .collect::<Vec<_>>();
+    let abbreviated = revision::abbreviate(repo, hash, DEFAULT_ABBREV)?;
+    writeln!(output, "{} {}", abbreviated, subject.join(" "))?;
+    Ok(())
+}
+
+fn ls_files(stage: bool) -> Result<()> {
+    let repo = Repository::discover()?;
+    let index = Index::read(&repo)?;
+    for entry in index.entries() {
+        if stage {
+            println!(
+                "{:06o} {} {}\t{}",
+                entry.mode, entry.hash, entry.stage, entry.path
+            );
+        } else if entry.stage <= 1 {
+            // Conflicted paths are only listed once
+            println!("{}", entry.path);
+        }
+    }
+    Ok(())
 }
 
 fn ls_remote(repo_url: reqwest::Url) -> Result<()> {
-    let (refs, _) = transfer::get_refs(&repo_url)?;
+    let (refs, _) = git_starter_rust::get_refs(&repo_url)?;
     for ref_ in refs.iter() {
         ref_.print();
     }
@@ -190,41 +1316,420 @@ fn ls_remote(repo_url: reqwest::Url) -> Result<()> {
 }
 
 fn ls_tree(tree_hash: &str, name_only: bool) -> Result<()> {
-    assert!(name_only);
+    if !name_only {
+        anyhow::bail!("only --name-only is supported");
+    }
+
+    let repo = Repository::discover()?;
+    let tree_hash = revision::peel(&repo, &revision::resolve(&repo, tree_hash)?, "tree")?;
+    Object::parse_from_hash(&repo, &tree_hash)?.print()?;
+
+    Ok(())
+}
+
+fn rev_parse(revisions: &[String], verify: bool, short: Option<usize>) -> Result<()> {
+    if verify && revisions.len() != 1 {
+        anyhow::bail!("Needed a single revision");
+    }
+
+    let repo = Repository::discover()?;
+    let format = |hash: String| match short {
+        Some(len) => revision::abbreviate(&repo, &hash, len),
+        None => Ok(hash),
+    };
+    for argument in revisions {
+        if verify {
+            println!("{}", format(revision::resolve(&repo, argument)?)?);
+        } else if let Some((from, to)) = split_range(argument) {
+            let to = revision::resolve(&repo, to)?;
+            let from = revision::resolve(&repo, from)?;
+            println!("{}", format(to)?);
+            println!("^{}", format(from)?);
+        } else if let Some(name) = argument.strip_prefix('^') {
+            println!("^{}", format(revision::resolve(&repo, name)?)?);
+        } else {
+            println!("{}", format(revision::resolve(&repo, argument)?)?);
+        }
+    }
+
+    Ok(())
+}
 
-    // Should be a SHA1 hash
-    assert_eq!(tree_hash.len(), 40);
+/// Split a range like `from..to` into its ends, either of which defaults to `HEAD` if left out.
+fn split_range<'a>(argument: &'a str) -> Option<(&'a str, &'a str)> {
+    let (from, to) = argument.split_once("..")?;
+    // `from...to` (the symmetric difference) isn't supported
+    if to.starts_with('.') {
+        return None;
+    }
+    let or_head = |name: &'a str| if name.is_empty() { "HEAD" } else { name };
+    Some((or_head(from), or_head(to)))
+}
+fn rm(args: &[String], cached: bool, recursive: bool, force: bool) -> Result<()> {
+    let repo = Repository::discover()?;
+    let work_tree = repo.work_tree()?;
+    let pathspec = Pathspec::new(&repo, args)?;
+
+    let mut index = Index::read(&repo)?;
+    let index_mtime = index::index_mtime(&repo.index_path());
+    let modes = FileModes::load(&repo)?;
+    let head_files = match repo.read_ref("HEAD")? {
+        Some(head) => {
+            let commit = Object::read_commit(&repo, &head)?;
+            read_tree_recursive(&repo, &commit.tree_hash)?
+        }
+        None => Default::default(),
+    };
+
+    let mut matched = vec![false; pathspec.patterns().len()];
+    let mut paths = Vec::new();
+    for (entry, pathspec_match) in index.matching(&pathspec) {
+        if pathspec_match.in_directory && !recursive {
+            anyhow::bail!(
+                "not removing '{}' recursively without -r",
+                args[pathspec_match.pattern]
+            );
+        }
+        matched[pathspec_match.pattern] = true;
+        paths.push(entry);
+    }
+    if let Some(i) = matched.iter().position(|m| !m) {
+        anyhow::bail!("pathspec '{}' did not match any files", args[i]);
+    }
+
+    if !force {
+        // Refuse to lose changes that aren't committed anywhere else
+        let mut both = Vec::new();
+        let mut staged = Vec::new();
+        let mut modified = Vec::new();
+        for entry in paths.iter() {
+            let exists = std::fs::symlink_metadata(work_tree.join(&entry.path)).is_ok();
+            let is_staged = entry.is_staged(head_files.get(&entry.path));
+            let is_modified =
+                exists && worktree::is_modified(work_tree, entry, index_mtime, &modes)?;
+            match (is_staged, is_modified) {
+                (true, true) => both.push(&entry.path),
+                (true, false) if !cached && exists => staged.push(&entry.path),
+                (false, true) if !cached => modified.push(&entry.path),
+                _ => (),
+            }
+        }
 
-    let mut path = PathBuf::from(".git/objects");
-    path.push(&tree_hash[0..2]);
-    path.push(&tree_hash[2..40]);
+        let mut refused = false;
+        for (paths, problem, advice) in [
+            (
+                both,
+                "staged content different from both the\nfile and the HEAD",
+                "use -f to force removal",
+            ),
+            (
+                staged,
+                "changes staged in the index",
+                "use --cached to keep the file, or -f to force removal",
+            ),
+            (
+                modified,
+                "local modifications",
+                "use --cached to keep the file, or -f to force removal",
+            ),
+        ] {
+            if paths.is_empty() {
+                continue;
+            }
+            let noun = if paths.len() == 1 {
+                "file has"
+            } else {
+                "files have"
+            };
+            eprintln!("error: the following {} {}:", noun, problem);
+            for path in paths {
+                eprintln!("    {}", path);
+            }
+            eprintln!("({})", advice);
+            refused = true;
+        }
+        if refused {
+            std::process::exit(1);
+        }
+    }
 
-    let object = Object::parse_from_path(path)?;
-    assert!(matches!(object, Object::Tree(_)));
-    object.print();
+    let paths = paths
+        .into_iter()
+        .map(|e| e.path.clone())
+        .collect::<Vec<_>>();
+    for path in paths {
+        println!("rm '{}'", path);
+        index.remove(&path);
+        if !cached {
+            worktree::remove_file(work_tree, &path)?;
+        }
+    }
+    index.write(&repo)?;
 
     Ok(())
 }
+struct ShowRefOptions {
+    heads: bool,
+    tags: bool,
+    verify: bool,
+    dereference: bool,
+    hash_only: bool,
+}
+
+fn show_ref(patterns: &[String], options: &ShowRefOptions) -> Result<()> {
+    let repo = Repository::discover()?;
+
+    let mut shown = Vec::new();
+    if options.verify {
+        // Each ref has to be given in full
+        for name in patterns {
+            let hash = match name == "HEAD" || name.starts_with("refs/") {
+                true => repo.read_ref(name)?,
+                false => None,
+            };
+            let hash = hash.ok_or_else(|| anyhow::anyhow!("'{}' - not a valid ref", name))?;
+            shown.push((name.clone(), hash));
+        }
+    } else {
+        for reference in repo.list_refs("refs/")? {
+            let name = reference.name.as_str();
+            let wanted_kind = (!options.heads && !options.tags)
+                || (options.heads && name.starts_with("refs/heads/"))
+                || (options.tags && name.starts_with("refs/tags/"));
+            // Patterns match whole trailing components of the name
+            let matches = patterns.is_empty()
+                || patterns.iter().any(|pattern| {
+                    name.strip_suffix(pattern.as_str())
+                        .is_some_and(|rest| rest.is_empty() || rest.ends_with('/'))
+                });
+            if wanted_kind && matches {
+                shown.push((reference.name, reference.hash));
+            }
+        }
+    }
+
+    for (name, hash) in shown.iter() {
+        if options.hash_only {
+            println!("{}", hash);
+        } else {
+            println!("{} {}", hash, name);
+        }
+        if options.dereference {
+            let peeled = revision::peel(&repo, hash, "")?;
+            if peeled != *hash {
+                match options.hash_only {
+                    true => println!("{}", peeled),
+                    false => println!("{} {}^{{}}", peeled, name),
+                }
+            }
+        }
+    }
+    if shown.is_empty() {
+        std::process::exit(1);
+    }
 
-fn rev_parse() -> Result<()> {
-    todo!("rev_parse")
+    Ok(())
 }
-fn rm() -> Result<()> {
-    todo!("rm")
+/// How `status` prints its output.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum StatusFormat {
+    Long,
+    Short,
+    /// Like `Short`, but with paths always from the root of the working tree
+    Porcelain,
+    PorcelainV2,
 }
-fn show_ref() -> Result<()> {
-    todo!("show_ref")
+
+fn status(
+    args: &[String],
+    format: StatusFormat,
+    show_branch: bool,
+    untracked_files: UntrackedFiles,
+) -> Result<()> {
+    let repo = Repository::discover()?;
+    let pathspec = match args.is_empty() {
+        true => Pathspec::all(),
+        false => Pathspec::new(&repo, args)?,
+    };
+
+    let mut index = Index::read(&repo)?;
+    let original = index.clone();
+    let status = Status::new(&repo, &mut index, &pathspec, untracked_files)?;
+    // Save any refreshed stat data if possible, but it doesn't matter if the index is locked
+    if index != original {
+        let _ = index.write(&repo);
+    }
+
+    let branch = BranchStatus::read(&repo)?;
+    let prefix = match format {
+        StatusFormat::Long | StatusFormat::Short => pathspec::current_prefix(&repo)?,
+        StatusFormat::Porcelain | StatusFormat::PorcelainV2 => String::new(),
+    };
+    let mut output = std::io::stdout().lock();
+    match format {
+        StatusFormat::Long => status.print_long(&mut output, &branch, &prefix, untracked_files)?,
+        StatusFormat::Short | StatusFormat::Porcelain => {
+            status.print_short(&mut output, show_branch.then_some(&branch), &prefix)?
+        }
+        StatusFormat::PorcelainV2 => {
+            status.print_porcelain_v2(&mut output, show_branch.then_some(&branch))?
+        }
+    }
+    Ok(())
 }
-fn status() -> Result<()> {
-    todo!("status")
+
+fn tag(
+    name: &str,
+    object_name: Option<&str>,
+    annotate: bool,
+    message: Option<&str>,
+    force: bool,
+) -> Result<()> {
+    let repo = Repository::discover()?;
+
+    let ref_name = format!("refs/tags/{}", name);
+    if repo.read_ref(&ref_name)?.is_some() && !force {
+        anyhow::bail!("tag '{}' already exists", name);
+    }
+
+    let object_hash = revision::resolve(&repo, object_name.unwrap_or("HEAD"))?;
+
+    let tag_hash = if annotate || message.is_some() {
+        let message =
+            message.ok_or_else(|| anyhow::anyhow!("annotated tags need a message (-m)"))?;
+        let object = Object::parse_from_hash(&repo, &object_hash)?;
+        let tagger = identity::committer(&repo)?;
+        let tag = Object::new_tag(&object_hash, object.type_name(), name, tagger, message);
+        tag.add(&repo)?;
+        tag.hash()
+    } else {
+        object_hash
+    };
+    repo.write_ref(&ref_name, &tag_hash)?;
+
+    Ok(())
+}
+
+fn delete_tag(name: &str) -> Result<()> {
+    let repo = Repository::discover()?;
+
+    let ref_name = format!("refs/tags/{}", name);
+    let hash = repo
+        .read_ref(&ref_name)?
+        .ok_or_else(|| anyhow::anyhow!("tag '{}' not found", name))?;
+    repo.delete_ref(&ref_name)?;
+    println!("Deleted tag '{}' (was {})", name, &hash[0..7]);
+
+    Ok(())
 }
-fn tag() -> Result<()> {
-    todo!("tag")
+
+fn list_tags() -> Result<()> {
+    let repo = Repository::discover()?;
+    for reference in repo.list_refs("refs/tags/")? {
+        println!("{}", refs::shorten(&reference.name));
+    }
+    Ok(())
+}
+
+fn update_ref(
+    args: &[String],
+    message: Option<&str>,
+    delete: bool,
+    no_deref: bool,
+    stdin: bool,
+) -> Result<()> {
+    let repo = Repository::discover()?;
+    let mut transaction = RefTransaction::new(&repo);
+    transaction.no_deref(no_deref);
+
+    // An empty old value means the ref mustn't exist
+    let value = |value: &str, which: &str| match value {
+        "" => Ok(NULL_HASH.to_owned()),
+        _ => revision::resolve(&repo, value)
+            .map_err(|_| anyhow::anyhow!("{}: not a valid {}SHA1", value, which)),
+    };
+    if stdin {
+        let mut input = String::new();
+        std::io::stdin().read_to_string(&mut input)?;
+        for line in input.lines() {
+            read_ref_update(&repo, &mut transaction, line)?;
+        }
+    } else {
+        match (delete, args) {
+            (true, [name]) => transaction.delete(name, None),
+            (true, [name, old]) => transaction.delete(name, Some(&value(old, "old ")?)),
+            (false, [name, new]) => transaction.update(name, &value(new, "")?, None),
+            (false, [name, new, old]) => {
+                transaction.update(name, &value(new, "")?, Some(&value(old, "old ")?))
+            }
+            _ => anyhow::bail!(
+                "usage: update-ref [-m <reason>] (-d <ref> [<old-value>] | <ref> <new-value> \
+                 [<old-value>] | --stdin)"
+            ),
+        }
+    }
+
+    // As in git, the refs are still updated if there's nobody to record in the reflogs
+    if let Ok(committer) = identity::committer(&repo) {
+        transaction.reflog(committer, message.unwrap_or(""));
+    }
+    transaction.commit()?;
+
+    Ok(())
+}
+
+/// Add one line of `update-ref --stdin` input to the transaction: `update <ref> <new> [<old>]`,
+/// `create <ref> <new>`, `delete <ref> [<old>]` or `verify <ref> [<old>]`.
+fn read_ref_update(repo: &Repository, transaction: &mut RefTransaction, line: &str) -> Result<()> {
+    let (command, args) = line.split_once(' ').unwrap_or((line, ""));
+    if !["update", "create", "delete", "verify"].contains(&command) {
+        anyhow::bail!("unknown command: {}", line);
+    }
+    let mut args = args.split(' ');
+    let name = match args.next() {
+        Some(name) if !name.is_empty() => name,
+        _ => anyhow::bail!("{}: missing <ref>", command),
+    };
+    // An empty old value means the ref mustn't exist
+    let mut value = |which: &str| match args.next() {
+        None => Ok(None),
+        Some("") => Ok(Some(NULL_HASH.to_owned())),
+        Some(value) => match revision::resolve(repo, value) {
+            Ok(hash) => Ok(Some(hash)),
+            Err(_) => anyhow::bail!("{} {}: invalid <{}>: {}", command, name, which, value),
+        },
+    };
+
+    let missing_new = || anyhow::anyhow!("{} {}: missing <newvalue>", command, name);
+    match command {
+        "update" => {
+            let new_hash = value("newvalue")?.ok_or_else(missing_new)?;
+            let old_hash = value("oldvalue")?;
+            transaction.update(name, &new_hash, old_hash.as_deref());
+        }
+        "create" => {
+            let new_hash = value("newvalue")?.ok_or_else(missing_new)?;
+            if new_hash == NULL_HASH {
+                anyhow::bail!("{} {}: zero <newvalue>", command, name);
+            }
+            transaction.update(name, &new_hash, Some(NULL_HASH));
+        }
+        "delete" => transaction.delete(name, value("oldvalue")?.as_deref()),
+        _ => transaction.verify(name, value("oldvalue")?.as_deref()),
+    }
+
+    let extra = args.collect::<Vec<_>>();
+    if !extra.is_empty() {
+        anyhow::bail!("{} {}: extra input: {}", command, name, extra.join(" "));
+    }
+    Ok(())
 }
 
 fn write_tree() -> Result<()> {
-    let object = Object::new_from_path(PathBuf::from("./"))?;
-    object.add()?;
-    println!("{}", object.hash());
+    let repo = Repository::discover()?;
+    let mut index = Index::read(&repo)?;
+    let hash = index.write_tree(&repo)?;
+    index.write(&repo)?;
+    println!("{}", hash);
     Ok(())
 }
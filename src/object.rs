@@ -1,77 +1,339 @@
-use anyhow::Result;
+//! Git objects: blobs, trees, commits and tags, and reading and writing them from the store.
+
 use flate2::Compression;
 use sha1::{Digest, Sha1};
 use std::{
+    borrow::Cow,
+    collections::BTreeMap,
     io::{Read, Write},
-    os::unix::prelude::PermissionsExt,
+    os::unix::ffi::OsStrExt,
     path::PathBuf,
-    time::SystemTime,
 };
 
+use crate::{
+    date,
+    error::{Error, Result},
+    index::mode_from_metadata,
+    repository::Repository,
+    util::is_hash,
+};
+
+/// An object in the store.
 #[derive(Debug)]
 pub enum Object {
+    /// The contents of a file.
     Blob(Vec<u8>),
+    /// A directory listing.
     Tree(Vec<TreeEntry>),
+    /// A snapshot of the tree, with its history.
     Commit(Commit),
+    /// An annotated tag.
+    Tag(Tag),
 }
 
-#[derive(Debug, Clone)]
+/// A single file or directory in a tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TreeEntry {
+    /// File mode, in octal, e.g., `100644` or `40000`
     pub mode: String,
+    /// Name of the file or directory (not its full path)
     pub name: String,
+    /// Hash of the blob or tree object
     pub hash: String,
 }
 
-#[derive(Debug)]
+/// A commit object.
+#[derive(Debug, Clone, PartialEq)]
 pub struct Commit {
+    /// Hash of the commit's root tree
     pub tree_hash: String,
-    parent_hash: Option<String>,
-    message: String,
-    timestamp: SystemTime,
+    /// Hashes of the commit's parents, in order
+    pub parent_hashes: Vec<String>,
+    /// Who wrote the change, and when
+    pub author: Signature,
+    /// Who made the commit, and when
+    pub committer: Signature,
+    /// Any other headers (e.g., `encoding`, `mergetag` or `gpgsig`), in their original order.
+    /// Multi-line values are joined with newlines.
+    pub extra_headers: Vec<(String, Vec<u8>)>,
+    /// The raw commit message, including any trailing newline
+    pub message: Vec<u8>,
+    /// How many of the other headers came before each extra header
+    extra_header_positions: Vec<usize>,
+}
+
+/// An annotated tag object.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tag {
+    /// Hash of the tagged object
+    pub object_hash: String,
+    /// Type of the tagged object, e.g., `commit`
+    pub object_type: String,
+    /// Name of the tag, without `refs/tags/`
+    pub name: String,
+    /// Who made the tag, and when (very old tags don't have this)
+    pub tagger: Option<Signature>,
+    /// Any other headers, in their original order
+    pub extra_headers: Vec<(String, Vec<u8>)>,
+    /// The raw tag message, including any trailing newline and signature
+    pub message: Vec<u8>,
+    /// How many of the other headers came before each extra header
+    extra_header_positions: Vec<usize>,
+}
+
+/// The name, email address and time in author, committer and tagger lines.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signature {
+    /// The person's name, as written. It's in the commit's `encoding`, so needn't be UTF-8.
+    pub name: Vec<u8>,
+    /// The person's email address, without angle brackets
+    pub email: Vec<u8>,
+    /// Seconds since the Unix epoch
+    pub time: i64,
+    /// Offset of the person's timezone from UTC, in minutes
+    pub tz_offset: i32,
+    /// Whether a zero offset is written `-0000`, as some tools do for an unknown timezone
+    pub negative_utc: bool,
+}
+
+impl Signature {
+    /// Parse a signature, e.g., `A U Thor <author@example.com> 1112911993 -0700`.
+    pub fn parse(input: &[u8]) -> Result<Self> {
+        let invalid = || {
+            Error::CorruptObject(format!(
+                "invalid signature '{}'",
+                String::from_utf8_lossy(input)
+            ))
+        };
+
+        let email_start = input.iter().position(|b| *b == b'<').ok_or_else(invalid)?;
+        let email_len = input[email_start + 1..]
+            .iter()
+            .position(|b| *b == b'>')
+            .ok_or_else(invalid)?;
+        let name = &input[..email_start];
+        // The space before the email address separates it from the name
+        let name = name.strip_suffix(b" ").unwrap_or(name);
+        let email = &input[email_start + 1..email_start + 1 + email_len];
+        let rest = std::str::from_utf8(&input[email_start + 1 + email_len + 1..])
+            .map_err(|_| invalid())?;
+        let (time, tz_offset) = rest.trim().split_once(' ').ok_or_else(invalid)?;
+
+        let time = time.parse::<i64>().map_err(|_| invalid())?;
+        let negative_utc = tz_offset == "-0000";
+        let tz_offset = date::parse_offset(tz_offset).ok_or_else(invalid)?;
+
+        Ok(Signature {
+            name: name.to_vec(),
+            email: email.to_vec(),
+            time,
+            tz_offset,
+            negative_utc,
+        })
+    }
+
+    /// Encode the signature as it appears in objects and reflogs.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut output = self.name.clone();
+        output.extend(b" <");
+        output.extend(&self.email);
+        output.extend(format!("> {} {}", self.time, self.format_tz_offset()).as_bytes());
+        output
+    }
+
+    /// The name, for display, with any bytes that aren't UTF-8 replaced and trailing spaces
+    /// removed.
+    pub fn display_name(&self) -> Cow<'_, str> {
+        match String::from_utf8_lossy(&self.name) {
+            Cow::Borrowed(name) => Cow::Borrowed(name.trim_end()),
+            Cow::Owned(name) => Cow::Owned(name.trim_end().to_owned()),
+        }
+    }
+
+    /// The email address, for display, with any bytes that aren't UTF-8 replaced.
+    pub fn display_email(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.email)
+    }
+
+    /// Format the timezone offset as it appears in signatures, e.g., `-0700`.
+    pub fn format_tz_offset(&self) -> String {
+        match self.tz_offset {
+            0 if self.negative_utc => "-0000".to_owned(),
+            tz_offset => date::format_offset(tz_offset),
+        }
+    }
+
+    /// Format the time as `git log` does by default, e.g., `Thu Apr 7 15:13:13 2005 -0700`.
+    pub fn format_date(&self) -> String {
+        date::format_date(self.time, self.tz_offset)
+    }
+}
+
+impl std::fmt::Display for Signature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} <{}> {} {}",
+            self.display_name(),
+            self.display_email(),
+            self.time,
+            self.format_tz_offset()
+        )
+    }
+}
+
+/// Header names and values, in order.
+type Headers = Vec<(String, Vec<u8>)>;
+
+/// Split the headers of a commit or tag from its message. Continuation lines (those starting
+/// with a space) are joined onto the previous header's value with newlines.
+fn parse_headers(input: &[u8]) -> Result<(Headers, Vec<u8>)> {
+    let mut headers: Headers = Vec::new();
+    let mut rest = input;
+    while !rest.is_empty() {
+        let line_end = rest.iter().position(|b| *b == b'\n').unwrap_or(rest.len());
+        let line = &rest[0..line_end];
+        rest = &rest[(line_end + 1).min(rest.len())..];
+
+        if line.is_empty() {
+            // End of headers
+            break;
+        } else if let Some(continuation) = line.strip_prefix(b" ") {
+            let (_, value) = headers.last_mut().ok_or_else(|| {
+                Error::CorruptObject("continuation line before first header".into())
+            })?;
+            value.push(b'\n');
+            value.extend(continuation);
+        } else {
+            let space = line.iter().position(|b| *b == b' ').unwrap_or(line.len());
+            let key = std::str::from_utf8(&line[0..space])
+                .map_err(|_| Error::CorruptObject("invalid header name".into()))?;
+            let value = &line[(space + 1).min(line.len())..];
+            headers.push((key.to_owned(), value.to_vec()));
+        }
+    }
+    Ok((headers, rest.to_vec()))
+}
+
+/// Encode the headers of a commit or tag: the standard ones, in order, with each extra header
+/// after as many standard headers as came before it when it was parsed. New extra headers go at
+/// the end.
+fn encode_headers(
+    output: &mut Vec<u8>,
+    standard: &[(&str, Cow<[u8]>)],
+    extra: &[(String, Vec<u8>)],
+    extra_positions: &[usize],
+) {
+    let positions = extra_positions
+        .iter()
+        .copied()
+        .chain(std::iter::repeat(usize::MAX));
+    let mut extra = positions.zip(extra).peekable();
+    for (i, (key, value)) in standard.iter().enumerate() {
+        while let Some((_, (key, value))) = extra.next_if(|(position, _)| *position <= i) {
+            encode_header(output, key, value);
+        }
+        encode_header(output, key, value);
+    }
+    for (_, (key, value)) in extra {
+        encode_header(output, key, value);
+    }
+}
+
+/// Encode a header, with any newlines in its value as continuation lines.
+fn encode_header(output: &mut Vec<u8>, key: &str, value: &[u8]) {
+    output.extend(key.as_bytes());
+    output.push(b' ');
+    for (i, line) in value.split(|b| *b == b'\n').enumerate() {
+        if i > 0 {
+            output.extend(b"\n ");
+        }
+        output.extend(line);
+    }
+    output.push(b'\n');
 }
 
 impl Commit {
-    fn new(tree_hash: &str, parent_hash: Option<&str>, message: &str) -> Self {
+    fn new(
+        tree_hash: &str,
+        parent_hashes: &[&str],
+        author: Signature,
+        committer: Signature,
+        message: &str,
+    ) -> Self {
+        let mut message = message.as_bytes().to_vec();
+        if !message.is_empty() && !message.ends_with(b"\n") {
+            message.push(b'\n');
+        }
+
         Commit {
             tree_hash: tree_hash.to_owned(),
-            parent_hash: parent_hash.map(|s| s.to_owned()),
-            message: message.to_owned(),
-            timestamp: SystemTime::now(),
+            parent_hashes: parent_hashes.iter().map(|s| (*s).to_owned()).collect(),
+            author,
+            committer,
+            extra_headers: Vec::new(),
+            message,
+            extra_header_positions: Vec::new(),
         }
     }
 
-    fn encode(&self) -> Vec<u8> {
-        let mut output = String::new();
-
-        output.push_str("tree ");
-        output.push_str(&self.tree_hash);
-        output.push('\n');
+    fn parse(input: &[u8]) -> Result<Self> {
+        let (headers, message) = parse_headers(input)?;
 
-        if let Some(parent_hash) = self.parent_hash.as_ref() {
-            output.push_str("parent ");
-            output.push_str(parent_hash);
-            output.push('\n');
+        let mut tree_hash = None;
+        let mut parent_hashes = Vec::new();
+        let mut author = None;
+        let mut committer = None;
+        let mut extra_headers = Vec::new();
+        let mut extra_header_positions = Vec::new();
+        let mut standard_count = 0;
+        for (key, value) in headers {
+            match key.as_str() {
+                "tree" if tree_hash.is_none() => tree_hash = Some(parse_hash(&value)?),
+                "parent" => parent_hashes.push(parse_hash(&value)?),
+                "author" if author.is_none() => author = Some(Signature::parse(&value)?),
+                "committer" if committer.is_none() => committer = Some(Signature::parse(&value)?),
+                _ => {
+                    extra_header_positions.push(standard_count);
+                    extra_headers.push((key, value));
+                    continue;
+                }
+            }
+            standard_count += 1;
         }
 
-        let timestamp_seconds = self
-            .timestamp
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        output.push_str(&format!(
-            "author Author Name <author@example.com> {} +0000\n",
-            timestamp_seconds
-        ));
-        output.push_str(&format!(
-            "committer Committer Name <committer@example.com> {} +0000\n",
-            timestamp_seconds,
-        ));
+        let missing = |header| Error::CorruptObject(format!("commit has no {}", header));
+        Ok(Commit {
+            tree_hash: tree_hash.ok_or_else(|| missing("tree"))?,
+            parent_hashes,
+            author: author.ok_or_else(|| missing("author"))?,
+            committer: committer.ok_or_else(|| missing("committer"))?,
+            extra_headers,
+            message,
+            extra_header_positions,
+        })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut output = Vec::new();
 
-        output.push('\n');
-        output.push_str(&self.message);
-        output.push('\n');
+        let mut standard = vec![("tree", Cow::from(self.tree_hash.as_bytes()))];
+        for parent_hash in self.parent_hashes.iter() {
+            standard.push(("parent", Cow::from(parent_hash.as_bytes())));
+        }
+        standard.push(("author", Cow::from(self.author.encode())));
+        standard.push(("committer", Cow::from(self.committer.encode())));
+        encode_headers(
+            &mut output,
+            &standard,
+            &self.extra_headers,
+            &self.extra_header_positions,
+        );
 
-        output.as_bytes().to_vec()
+        output.push(b'\n');
+        output.extend(&self.message);
+
+        output
     }
 
     fn encoded_len(&self) -> usize {
@@ -79,43 +341,198 @@ impl Commit {
     }
 }
 
+impl Tag {
+    fn new(
+        object_hash: &str,
+        object_type: &str,
+        name: &str,
+        tagger: Signature,
+        message: &str,
+    ) -> Self {
+        let mut message = message.as_bytes().to_vec();
+        if !message.ends_with(b"\n") {
+            message.push(b'\n');
+        }
+
+        Tag {
+            object_hash: object_hash.to_owned(),
+            object_type: object_type.to_owned(),
+            name: name.to_owned(),
+            tagger: Some(tagger),
+            extra_headers: Vec::new(),
+            message,
+            extra_header_positions: Vec::new(),
+        }
+    }
+
+    fn parse(input: &[u8]) -> Result<Self> {
+        let (headers, message) = parse_headers(input)?;
+
+        let mut object_hash = None;
+        let mut object_type = None;
+        let mut name = None;
+        let mut tagger = None;
+        let mut extra_headers = Vec::new();
+        let mut extra_header_positions = Vec::new();
+        let mut standard_count = 0;
+        for (key, value) in headers {
+            match key.as_str() {
+                "object" if object_hash.is_none() => object_hash = Some(parse_hash(&value)?),
+                "type" if object_type.is_none() => {
+                    object_type = match value.as_slice() {
+                        b"blob" | b"tree" | b"commit" | b"tag" => {
+                            Some(String::from_utf8(value).unwrap())
+                        }
+                        _ => return Err(Error::CorruptObject("invalid tagged object type".into())),
+                    }
+                }
+                "tag" if name.is_none() => {
+                    name = Some(
+                        String::from_utf8(value)
+                            .map_err(|_| Error::CorruptObject("invalid tag name".into()))?,
+                    )
+                }
+                "tagger" if tagger.is_none() => tagger = Some(Signature::parse(&value)?),
+                _ => {
+                    extra_header_positions.push(standard_count);
+                    extra_headers.push((key, value));
+                    continue;
+                }
+            }
+            standard_count += 1;
+        }
+
+        let missing = |header| Error::CorruptObject(format!("tag has no {}", header));
+        Ok(Tag {
+            object_hash: object_hash.ok_or_else(|| missing("object"))?,
+            object_type: object_type.ok_or_else(|| missing("type"))?,
+            name: name.ok_or_else(|| missing("tag"))?,
+            tagger,
+            extra_headers,
+            message,
+            extra_header_positions,
+        })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut output = Vec::new();
+
+        let mut standard = vec![
+            ("object", Cow::from(self.object_hash.as_bytes())),
+            ("type", Cow::from(self.object_type.as_bytes())),
+            ("tag", Cow::from(self.name.as_bytes())),
+        ];
+        if let Some(tagger) = self.tagger.as_ref() {
+            standard.push(("tagger", Cow::from(tagger.encode())));
+        }
+        encode_headers(
+            &mut output,
+            &standard,
+            &self.extra_headers,
+            &self.extra_header_positions,
+        );
+
+        output.push(b'\n');
+        output.extend(&self.message);
+
+        output
+    }
+}
+
+/// Parse a hash from an object header.
+fn parse_hash(input: &[u8]) -> Result<String> {
+    match std::str::from_utf8(input) {
+        Ok(hash) if is_hash(hash) => Ok(hash.to_owned()),
+        _ => Err(Error::CorruptObject(format!(
+            "invalid hash '{}'",
+            String::from_utf8_lossy(input)
+        ))),
+    }
+}
+
+/// Read a tree and all of its subtrees, returning every file (blob, symlink or gitlink) in it,
+/// keyed by its full path with `/` separators.
+pub fn read_tree_recursive(
+    repo: &Repository,
+    tree_hash: &str,
+) -> Result<BTreeMap<String, TreeEntry>> {
+    let mut files = BTreeMap::new();
+    let mut trees = vec![(String::new(), tree_hash.to_owned())];
+    while let Some((dir, tree_hash)) = trees.pop() {
+        for entry in Object::read_tree(repo, &tree_hash)? {
+            let path = format!("{}{}", dir, entry.name);
+            if entry.is_tree() {
+                trees.push((format!("{}/", path), entry.hash));
+            } else {
+                files.insert(path, entry);
+            }
+        }
+    }
+    Ok(files)
+}
+
 impl TreeEntry {
+    /// Whether this entry is a subdirectory.
+    pub fn is_tree(&self) -> bool {
+        self.mode == "40000"
+    }
+
     fn new<P>(path: P) -> Result<Self>
     where
         P: Into<PathBuf>,
     {
         let path: PathBuf = path.into();
 
-        let metadata = std::fs::metadata(&path)?;
-        let mode = if metadata.is_dir() {
-            "40000".to_owned()
-        } else {
-            format!("{:o}", metadata.permissions().mode())
+        let metadata = std::fs::symlink_metadata(&path)?;
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name.to_owned(),
+            None => return Err(Error::InvalidFileName(path)),
         };
 
-        let name = path.file_name().unwrap().to_str().unwrap().to_owned();
+        // Nested repositories are recorded as the commit they have checked out
+        if metadata.is_dir() {
+            if let Some(nested) = Repository::open_work_tree(&path)? {
+                let hash = nested
+                    .read_ref("HEAD")?
+                    .ok_or(Error::NoCommitCheckedOut(path))?;
+                return Ok(TreeEntry {
+                    mode: "160000".to_owned(),
+                    name,
+                    hash,
+                });
+            }
+        }
 
-        let object = Object::new_from_path(path)?;
-        let hash = object.hash();
+        let mode = match metadata.is_dir() {
+            true => "40000".to_owned(),
+            false => format!("{:o}", mode_from_metadata(&metadata)),
+        };
+        let hash = Object::new_from_path(path)?.hash();
 
         Ok(TreeEntry { mode, name, hash })
     }
 
     fn parse(input: &[u8]) -> Result<(&[u8], Self)> {
-        let mut i = 0;
-        while input[i] != b' ' {
-            i += 1;
-        }
-        let mode = std::str::from_utf8(&input[0..i])?.to_owned();
+        let truncated = || Error::CorruptObject("truncated tree entry".into());
+
+        let i = input
+            .iter()
+            .position(|b| *b == b' ')
+            .ok_or_else(truncated)?;
+        let mode = std::str::from_utf8(&input[0..i])
+            .map_err(|_| Error::CorruptObject("invalid tree entry mode".into()))?
+            .to_owned();
         let input = &input[i + 1..];
 
-        let mut i = 0;
-        while input[i] != 0 {
-            i += 1;
-        }
-        let name = std::str::from_utf8(&input[0..i])?.to_owned();
+        let i = input.iter().position(|b| *b == 0).ok_or_else(truncated)?;
+        let name = std::str::from_utf8(&input[0..i])
+            .map_err(|_| Error::CorruptObject("invalid tree entry name".into()))?
+            .to_owned();
         let input = &input[i + 1..];
 
+        if input.len() < 20 {
+            return Err(truncated());
+        }
         let hash = hex::encode(&input[0..20]);
         let input = &input[20..];
 
@@ -131,7 +548,7 @@ impl TreeEntry {
         let mut output = Vec::new();
 
         output.write_all(self.mode.as_bytes()).unwrap();
-        output.write_all(&[b' ']).unwrap();
+        output.write_all(b" ").unwrap();
         output.write_all(self.name.as_bytes()).unwrap();
         output.write_all(&[0]).unwrap();
         output.write_all(&hex::decode(&self.hash).unwrap()).unwrap();
@@ -141,15 +558,21 @@ impl TreeEntry {
 }
 
 impl Object {
-    pub fn parse_from_hash<P>(directory: P, hash: &str) -> Result<Self>
-    where
-        P: Into<PathBuf>,
-    {
-        let mut path: PathBuf = directory.into();
-        path.push(".git/objects");
-        path.push(&hash[0..2]);
-        path.push(&hash[2..40]);
-        Object::parse_from_path(path)
+    /// Parse an object from the store, looking for it first as a loose object and then in packs.
+    pub fn parse_from_hash(repo: &Repository, hash: &str) -> Result<Self> {
+        if !is_hash(hash) {
+            return Err(Error::MissingObject(hash.to_owned()));
+        }
+
+        let path = repo.object_path(hash);
+        if path.exists() {
+            return Object::parse_from_path(path);
+        }
+
+        match repo.pack_store()?.read(hash)? {
+            Some(content) => Object::parse(&content),
+            None => Err(Error::MissingObject(hash.to_owned())),
+        }
     }
 
     /// Parse an object from the store.
@@ -166,22 +589,32 @@ impl Object {
     }
 
     fn parse(content: &[u8]) -> Result<Self> {
+        let invalid_header = || Error::CorruptObject("invalid object header".into());
+
         let (object_header, object_content) = {
-            let mut i = 0;
-            while content[i] != 0 {
-                i += 1;
-            }
-            (std::str::from_utf8(&content[0..i])?, &content[i + 1..])
+            let i = content
+                .iter()
+                .position(|b| *b == 0)
+                .ok_or_else(invalid_header)?;
+            let object_header =
+                std::str::from_utf8(&content[0..i]).map_err(|_| invalid_header())?;
+            (object_header, &content[i + 1..])
         };
 
-        if object_header.starts_with("blob") {
-            let object_size = object_header[5..].parse::<usize>()?;
-            assert_eq!(object_content.len(), object_size);
-            Ok(Object::Blob(object_content.to_vec()))
-        } else if object_header.starts_with("tree") {
-            let object_size = object_header[5..].parse::<usize>()?;
-            assert_eq!(object_content.len(), object_size);
+        let (object_type, object_size) =
+            object_header.split_once(' ').ok_or_else(invalid_header)?;
+        let object_size = object_size.parse::<usize>().map_err(|_| invalid_header())?;
+        if object_content.len() != object_size {
+            return Err(Error::CorruptObject(format!(
+                "object size is {} but header says {}",
+                object_content.len(),
+                object_size
+            )));
+        }
 
+        if object_type == "blob" {
+            Ok(Object::Blob(object_content.to_vec()))
+        } else if object_type == "tree" {
             let mut rest = object_content;
             let mut entries = Vec::new();
             while !rest.is_empty() {
@@ -191,44 +624,82 @@ impl Object {
             }
 
             Ok(Object::Tree(entries))
-        } else if object_header.starts_with("commit") {
-            let commit_content = std::str::from_utf8(object_content)?;
-
-            let mut tree_hash = None;
-            for line in commit_content.lines() {
-                if line.starts_with("tree") {
-                    tree_hash = Some(line.trim_start_matches("tree "));
-                }
-            }
-            assert!(tree_hash.is_some());
-
-            Ok(Object::Commit(Commit {
-                tree_hash: tree_hash.unwrap().to_owned(),
-                parent_hash: None,
-                message: "".into(),
-                timestamp: SystemTime::now(),
-            }))
+        } else if object_type == "commit" {
+            Ok(Object::Commit(Commit::parse(object_content)?))
+        } else if object_type == "tag" {
+            Ok(Object::Tag(Tag::parse(object_content)?))
         } else {
-            anyhow::bail!("invalid object");
+            Err(Error::CorruptObject("invalid object type".into()))
+        }
+    }
+
+    /// Read a commit object from the store.
+    pub fn read_commit(repo: &Repository, hash: &str) -> Result<Commit> {
+        match Object::parse_from_hash(repo, hash)? {
+            Object::Commit(commit) => Ok(commit),
+            object => Err(object.wrong_type(hash, "commit")),
+        }
+    }
+
+    /// Read a tree object from the store.
+    pub fn read_tree(repo: &Repository, hash: &str) -> Result<Vec<TreeEntry>> {
+        match Object::parse_from_hash(repo, hash)? {
+            Object::Tree(entries) => Ok(entries),
+            object => Err(object.wrong_type(hash, "tree")),
+        }
+    }
+
+    fn wrong_type(&self, hash: &str, expected: &'static str) -> Error {
+        Error::WrongObjectType {
+            hash: hash.to_owned(),
+            expected,
+            actual: self.type_name(),
         }
     }
 
     /// Create a new commit object.
-    pub fn new_commit(tree_hash: &str, parent_hash: Option<&str>, message: &str) -> Self {
-        Object::Commit(Commit::new(tree_hash, parent_hash, message))
+    pub fn new_commit(
+        tree_hash: &str,
+        parent_hashes: &[&str],
+        author: Signature,
+        committer: Signature,
+        message: &str,
+    ) -> Self {
+        Object::Commit(Commit::new(
+            tree_hash,
+            parent_hashes,
+            author,
+            committer,
+            message,
+        ))
+    }
+
+    /// Create a new annotated tag object, pointing at an object of the given type.
+    pub fn new_tag(
+        object_hash: &str,
+        object_type: &str,
+        name: &str,
+        tagger: Signature,
+        message: &str,
+    ) -> Self {
+        Object::Tag(Tag::new(object_hash, object_type, name, tagger, message))
     }
 
-    /// Create a new object from the given file or directory.
+    /// Create a new object from the given file or directory. A symlink's blob is its target,
+    /// rather than the contents of the file it points to.
     pub fn new_from_path<P>(path: P) -> Result<Self>
     where
         P: Into<PathBuf>,
     {
         let path = path.into();
-        let metadata = std::fs::metadata(&path)?;
-        if metadata.is_dir() {
+        let metadata = std::fs::symlink_metadata(&path)?;
+        if metadata.file_type().is_symlink() {
+            let target = std::fs::read_link(&path)?;
+            Ok(Object::Blob(target.as_os_str().as_bytes().to_vec()))
+        } else if metadata.is_dir() {
             let mut entries = Vec::new();
             for path in std::fs::read_dir(&path)? {
-                let path = path.unwrap().path();
+                let path = path?.path();
                 if path.ends_with(".git") {
                     continue;
                 }
@@ -243,12 +714,9 @@ impl Object {
     }
 
     /// Add this object to the store.
-    pub fn add(&self) -> Result<()> {
-        let hash = self.hash();
-        let mut path = PathBuf::from(".git/objects");
-        path.push(&hash[0..2]);
-        std::fs::create_dir_all(&path)?;
-        path.push(&hash[2..40]);
+    pub fn add(&self, repo: &Repository) -> Result<()> {
+        let path = repo.object_path(&self.hash());
+        std::fs::create_dir_all(path.parent().unwrap())?;
 
         let file = std::fs::File::create(path)?;
         let mut encoder = flate2::write::ZlibEncoder::new(file, Compression::default());
@@ -267,6 +735,9 @@ impl Object {
             Object::Commit(commit) => {
                 encoder.write_all(&commit.encode())?;
             }
+            Object::Tag(tag) => {
+                encoder.write_all(&tag.encode())?;
+            }
         }
 
         Ok(())
@@ -290,50 +761,277 @@ impl Object {
             Object::Commit(commit) => {
                 hasher.update(commit.encode());
             }
+            Object::Tag(tag) => {
+                hasher.update(tag.encode());
+            }
         }
 
         hex::encode(hasher.finalize())
     }
 
-    /// Generate the header string for this object.
-    fn header(&self) -> String {
-        let mut header = String::new();
+    /// The name of this object's type, e.g., `blob`.
+    pub fn type_name(&self) -> &'static str {
         match self {
-            Object::Blob(content) => {
-                header.push_str("blob ");
-                header.push_str(&content.len().to_string());
-            }
-            Object::Tree(entries) => {
-                header.push_str("tree ");
-                let content_len = entries.iter().map(|e| e.encoded_len()).sum::<usize>();
-                header.push_str(&content_len.to_string());
-            }
-            Object::Commit(commit) => {
-                header.push_str("commit ");
-                let content_len = commit.encoded_len();
-                header.push_str(&content_len.to_string());
-            }
+            Object::Blob(_) => "blob",
+            Object::Tree(_) => "tree",
+            Object::Commit(_) => "commit",
+            Object::Tag(_) => "tag",
         }
-        header
+    }
+
+    /// The size of this object's encoded contents, in bytes.
+    pub fn size(&self) -> usize {
+        match self {
+            Object::Blob(content) => content.len(),
+            Object::Tree(entries) => entries.iter().map(|e| e.encoded_len()).sum::<usize>(),
+            Object::Commit(commit) => commit.encoded_len(),
+            Object::Tag(tag) => tag.encode().len(),
+        }
+    }
+
+    /// Generate the header string for this object.
+    fn header(&self) -> String {
+        format!("{} {}", self.type_name(), self.size())
     }
 
     /// Print the contents of this object.
-    pub fn print(&self) {
+    pub fn print(&self) -> Result<()> {
+        let mut stdout = std::io::stdout().lock();
         match self {
             Object::Blob(content) => {
-                print!(
-                    "{}",
-                    std::str::from_utf8(content).expect("non utf-8 blob contents")
-                );
+                stdout.write_all(content)?;
             }
             Object::Tree(entries) => {
                 for entry in entries.iter() {
-                    println!("{}", entry.name);
+                    writeln!(stdout, "{}", entry.name)?;
                 }
             }
             Object::Commit(commit) => {
-                println!("{}", std::str::from_utf8(&commit.encode()).unwrap())
+                stdout.write_all(&commit.encode())?;
+            }
+            Object::Tag(tag) => {
+                stdout.write_all(&tag.encode())?;
             }
         }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Commit, Object, Signature, Tag, TreeEntry};
+    use crate::{error::Error, repository::Repository, test_util::TempDir};
+    use std::os::unix::{ffi::OsStrExt, fs::PermissionsExt};
+
+    #[test]
+    fn test_parse_corrupt_object() {
+        assert!(matches!(
+            Object::parse(b"blob 5\0hello"),
+            Ok(Object::Blob(content)) if content == b"hello"
+        ));
+        assert!(matches!(
+            Object::parse(b"blob 6\0hello"),
+            Err(Error::CorruptObject(_))
+        ));
+        assert!(matches!(
+            Object::parse(b"blob 5hello"),
+            Err(Error::CorruptObject(_))
+        ));
+        assert!(matches!(
+            Object::parse(b"tree 10\x00100644 abc"),
+            Err(Error::CorruptObject(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_and_encode_commit() {
+        let content = b"tree 0123456789abcdef0123456789abcdef01234567\n\
+parent 1111111111111111111111111111111111111111\n\
+parent 2222222222222222222222222222222222222222\n\
+author A U Thor <author@example.com> 1112911993 -0700\n\
+committer C O Mitter <committer@example.com> 1112912000 +0530\n\
+encoding ISO-8859-1\n\
+gpgsig -----BEGIN PGP SIGNATURE-----\n \n abc\n -----END PGP SIGNATURE-----\n\
+\n\
+Merge branches\n\n\xe9t\xe9\n";
+        let commit = Commit::parse(content).unwrap();
+
+        assert_eq!(commit.parent_hashes.len(), 2);
+        assert_eq!(
+            commit.author,
+            Signature {
+                name: b"A U Thor".to_vec(),
+                email: b"author@example.com".to_vec(),
+                time: 1112911993,
+                tz_offset: -7 * 60,
+                negative_utc: false,
+            }
+        );
+        assert_eq!(commit.committer.tz_offset, 5 * 60 + 30);
+        assert_eq!(
+            commit.extra_headers[0],
+            ("encoding".into(), b"ISO-8859-1".to_vec())
+        );
+        assert_eq!(
+            commit.extra_headers[1].1,
+            b"-----BEGIN PGP SIGNATURE-----\n\nabc\n-----END PGP SIGNATURE-----"
+        );
+        assert_eq!(commit.message, b"Merge branches\n\n\xe9t\xe9\n");
+
+        assert_eq!(commit.encode(), content);
+    }
+
+    #[test]
+    fn test_commit_hash() {
+        // Made by git itself
+        let content = b"commit 150\0\
+tree a1dffc7a64c0b2d395484bf452e9aeb1da3a18f2\n\
+author A U Thor <a@x.com> 1112911993 -0700\n\
+committer A U Thor <a@x.com> 1112912000 +0530\n\
+\n\
+subject\n\nbody\n";
+        let object = Object::parse(content).unwrap();
+        assert_eq!(object.hash(), "f2b6025b652ef13ca6d14470dd1109e4f42e9275");
+    }
+
+    #[test]
+    fn test_commit_round_trip() {
+        // Made by git itself, with `i18n.commitEncoding` set to ISO-8859-1
+        let latin1 = b"commit 171\0\
+tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+author Jos\xe9 <jose@example.com> 1112911993 -0700\n\
+committer Jos\xe9 <jose@example.com> 1112912000 +0000\n\
+encoding ISO-8859-1\n\
+\n\
+caf\xe9\n";
+        let object = Object::parse(latin1).unwrap();
+        let Object::Commit(commit) = &object else {
+            panic!("not a commit");
+        };
+        assert_eq!(commit.author.name, b"Jos\xe9");
+        assert_eq!(commit.author.display_name(), "Jos\u{fffd}");
+        assert_eq!(object.hash(), "06fae57d14269aca56bfe549c3e6275445820791");
+
+        // An unknown timezone
+        let negative_utc = b"commit 138\0\
+tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+author A U Thor <a@x.com> 1112911993 -0000\n\
+committer A U Thor <a@x.com> 1112912000 -0000\n\
+\n\
+m\n";
+        let object = Object::parse(negative_utc).unwrap();
+        assert_eq!(object.hash(), "778f657ae4db98abcbc1a6aba963651ce8dbf06c");
+
+        // Extra spaces in names, and extra headers before the committer
+        let unusual = b"commit 189\0\
+tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+author A U  Thor  <a@x.com> 1112911993 +0000\n\
+author Second <b@x.com> 1112911993 +0000\n\
+encoding UTF-8\n\
+committer C <c@x.com> 1112912000 +0000\n\
+\n\
+m\n";
+        let object = Object::parse(unusual).unwrap();
+        assert!(
+            matches!(&object, Object::Commit(commit) if commit.author.display_name() == "A U  Thor")
+        );
+        assert_eq!(object.hash(), "e398c512029b4b5a06c281b3214a1459cd5c85b5");
+    }
+
+    #[test]
+    fn test_parse_corrupt_commit() {
+        assert!(matches!(
+            Commit::parse(b"tree abc\n\nmessage"),
+            Err(Error::CorruptObject(_))
+        ));
+        assert!(matches!(
+            Commit::parse(
+                b"tree 0123456789abcdef0123456789abcdef01234567\n\
+author A <a> 1 +0000\n\nmessage"
+            ),
+            Err(Error::CorruptObject(_))
+        ));
+        assert!(Signature::parse(b"A <a> 1 0000").is_err());
+        assert!(Signature::parse(b"A a 1 +0000").is_err());
+    }
+
+    #[test]
+    fn test_tag_hash() {
+        // Made by git itself
+        let content = b"tag 110\0\
+object f2b6025b652ef13ca6d14470dd1109e4f42e9275\n\
+type commit\n\
+tag realtag\n\
+tagger T <t@t> 1792194687 +0000\n\
+\n\
+real\n";
+        let object = Object::parse(content).unwrap();
+        assert!(matches!(&object, Object::Tag(tag) if tag.name == "realtag"));
+        assert_eq!(object.hash(), "608f189fbbeeceb822758b1754334a09999b58d7");
+    }
+
+    #[test]
+    fn test_parse_tag_without_tagger() {
+        let content = b"object 0123456789abcdef0123456789abcdef01234567\n\
+type tree\n\
+tag v0.99\n\
+\n\
+Old tag\n";
+        let tag = Tag::parse(content).unwrap();
+        assert_eq!(tag.object_type, "tree");
+        assert_eq!(tag.tagger, None);
+        assert_eq!(tag.encode(), content);
+
+        assert!(matches!(
+            Tag::parse(b"object 0123456789abcdef0123456789abcdef01234567\ntype car\ntag a\n\n"),
+            Err(Error::CorruptObject(_))
+        ));
+    }
+
+    #[test]
+    fn test_tree_entry_modes() {
+        let temp = TempDir::new("tree-entry");
+        let dir = temp.path();
+        let mode = |name: &str| TreeEntry::new(dir.join(name)).unwrap().mode;
+
+        // Only the owner's executable bit matters
+        std::fs::write(dir.join("file"), "a\n").unwrap();
+        std::fs::set_permissions(dir.join("file"), std::fs::Permissions::from_mode(0o664)).unwrap();
+        assert_eq!(mode("file"), "100644");
+        std::fs::write(dir.join("script"), "b\n").unwrap();
+        std::fs::set_permissions(dir.join("script"), std::fs::Permissions::from_mode(0o775))
+            .unwrap();
+        assert_eq!(mode("script"), "100755");
+
+        // Symlinks are hashed as their targets, even if they're broken
+        std::os::unix::fs::symlink("missing", dir.join("link")).unwrap();
+        let link = TreeEntry::new(dir.join("link")).unwrap();
+        assert_eq!(link.mode, "120000");
+        assert_eq!(link.hash, Object::Blob(b"missing".to_vec()).hash());
+
+        // Nested repositories are recorded as the commit they have checked out
+        let nested = Repository::init(dir.join("nested")).unwrap();
+        assert!(matches!(
+            TreeEntry::new(dir.join("nested")),
+            Err(Error::NoCommitCheckedOut(_))
+        ));
+        let hash = "0123456789abcdef0123456789abcdef01234567";
+        std::fs::write(nested.git_dir().join("HEAD"), format!("{}\n", hash)).unwrap();
+        let gitlink = TreeEntry::new(dir.join("nested")).unwrap();
+        assert_eq!(
+            (gitlink.mode.as_str(), gitlink.hash.as_str()),
+            ("160000", hash)
+        );
+
+        std::fs::create_dir(dir.join("subdir")).unwrap();
+        assert_eq!(mode("subdir"), "40000");
+
+        // Names have to be UTF-8 to be stored in a tree
+        let latin1 = dir.join(std::ffi::OsStr::from_bytes(b"caf\xe9"));
+        std::fs::write(&latin1, "c\n").unwrap();
+        assert!(matches!(
+            TreeEntry::new(&latin1),
+            Err(Error::InvalidFileName(_))
+        ));
     }
 }
@@ -1,53 +1,76 @@
-use anyhow::Result;
+//! Parsing pack files, as sent by remotes.
+
 use flate2::read::ZlibDecoder;
 use sha1::{Digest, Sha1};
 use std::{collections::HashMap, io::Read};
 
-use crate::util::{high_bit, parse_size};
+use crate::{
+    error::{Error, Result},
+    util::{byte_at, crc32, high_bit, parse_offset, parse_size},
+};
 
+/// An object parsed from a pack file, with any deltas resolved.
 #[derive(Debug)]
 pub struct PackedObject {
     ty: PackedObjectType,
     content: Vec<u8>,
+    /// Position of the object in its pack file
+    offset: usize,
+    /// CRC-32 checksum of the object's packed representation
+    crc32: u32,
 }
 
-#[derive(Debug, PartialEq)]
-enum PackedObjectType {
+/// The type of a packed object.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PackedObjectType {
+    /// A commit object
     Commit,
+    /// A tree object
     Tree,
+    /// A blob object
     Blob,
+    /// An annotated tag object
     Tag,
+    /// A delta against the object this many bytes before it in the pack
     OfsDelta(Option<usize>),
+    /// A delta against the object with this hash
     RefDelta(Option<String>),
 }
 
 impl PackedObject {
-    fn parse_header(input: &[u8]) -> Result<(&[u8], (PackedObjectType, usize))> {
-        let mut object_type = match (input[0] & 0x70) >> 4 {
+    pub(crate) fn parse_header(input: &[u8]) -> Result<(&[u8], (PackedObjectType, usize))> {
+        let first_byte = byte_at(input, 0)?;
+        let mut object_type = match (first_byte & 0x70) >> 4 {
             1 => PackedObjectType::Commit,
             2 => PackedObjectType::Tree,
             3 => PackedObjectType::Blob,
             4 => PackedObjectType::Tag,
             6 => PackedObjectType::OfsDelta(None),
             7 => PackedObjectType::RefDelta(None),
-            _ => anyhow::bail!("invalid object type"),
+            _ => return Err(Error::CorruptPack("invalid object type".into())),
         };
 
-        let (input, object_size) = if high_bit(input[0]) {
+        let (input, object_size) = if high_bit(first_byte) {
             let (rest, object_size_extra) = parse_size(&input[1..])?;
-            let object_size = ((input[0] & 0x0f) as usize) | (object_size_extra << 4);
+            let object_size = object_size_extra
+                .checked_mul(1 << 4)
+                .ok_or_else(|| Error::CorruptPack("object size is too large".into()))?
+                | (first_byte & 0x0f) as usize;
             (rest, object_size)
         } else {
-            (&input[1..], (input[0] & 0x0f) as usize)
+            (&input[1..], (first_byte & 0x0f) as usize)
         };
 
         let input = match &mut object_type {
             PackedObjectType::OfsDelta(offset) => {
-                let (rest, offset_value) = parse_size(input)?;
+                let (rest, offset_value) = parse_offset(input)?;
                 *offset = Some(offset_value);
                 rest
             }
             PackedObjectType::RefDelta(hash) => {
+                if input.len() < 20 {
+                    return Err(Error::CorruptPack("truncated delta base hash".into()));
+                }
                 *hash = Some(hex::encode(&input[0..20]));
                 &input[20..]
             }
@@ -57,7 +80,9 @@ impl PackedObject {
         Ok((input, (object_type, object_size)))
     }
 
-    fn parse(input: &[u8]) -> Result<(&[u8], PackedObject)> {
+    /// Parse a packed object which starts at `offset` in its pack file.
+    fn parse(input: &[u8], offset: usize) -> Result<(&[u8], PackedObject)> {
+        let packed = input;
         let (input, (object_type, _object_size)) = PackedObject::parse_header(input)?;
 
         let mut decoder = ZlibDecoder::new(input);
@@ -65,37 +90,70 @@ impl PackedObject {
         decoder.read_to_end(&mut content)?;
 
         let input = &input[decoder.total_in() as usize..];
+        let crc32 = crc32(&packed[0..packed.len() - input.len()]);
 
         Ok((
             input,
             PackedObject {
                 ty: object_type,
                 content,
+                offset,
+                crc32,
             },
         ))
     }
 
-    pub fn header(&self) -> String {
-        let mut header = String::new();
-        match self.ty {
-            PackedObjectType::Commit => header.push_str("commit "),
-            PackedObjectType::Tree => header.push_str("tree "),
-            PackedObjectType::Blob => header.push_str("blob "),
-            PackedObjectType::Tag => header.push_str("tag "),
-            _ => unreachable!(),
+    fn is_delta(&self) -> bool {
+        matches!(
+            self.ty,
+            PackedObjectType::OfsDelta(_) | PackedObjectType::RefDelta(_)
+        )
+    }
+
+    /// Find the already-resolved base object of this delta, which was found at `offset` in the
+    /// pack.
+    fn delta_base<'a>(
+        &self,
+        offset: usize,
+        objects: &'a HashMap<String, PackedObject>,
+        offsets: &HashMap<usize, String>,
+    ) -> Option<&'a PackedObject> {
+        match &self.ty {
+            PackedObjectType::OfsDelta(delta_offset) => {
+                let base_offset = offset.checked_sub(delta_offset.unwrap())?;
+                objects.get(offsets.get(&base_offset)?)
+            }
+            PackedObjectType::RefDelta(hash) => objects.get(hash.as_ref().unwrap()),
+            _ => None,
         }
-        header.push_str(&self.content.len().to_string());
-        header
     }
 
+    /// The type of the object.
+    pub fn ty(&self) -> &PackedObjectType {
+        &self.ty
+    }
+
+    /// The object's content, without a header.
+    pub fn content(&self) -> &[u8] {
+        &self.content
+    }
+
+    /// Position of the object in its pack file.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// CRC-32 checksum of the object's packed representation.
+    pub fn crc32(&self) -> u32 {
+        self.crc32
+    }
+
+    /// Encode the object in the same format as loose objects, i.e., with a header.
     pub fn encode(&self) -> Vec<u8> {
-        let mut content = Vec::new();
-        content.extend(self.header().as_bytes());
-        content.push(0);
-        content.extend(&self.content);
-        content
+        encode_object(&self.ty, &self.content)
     }
 
+    /// Return the hash of this object.
     pub fn hash(&self) -> String {
         let mut hasher = Sha1::new();
         hasher.update(self.encode());
@@ -103,47 +161,89 @@ impl PackedObject {
     }
 }
 
+/// Encode a resolved object in the same format as loose objects, i.e., with a header.
+pub(crate) fn encode_object(ty: &PackedObjectType, content: &[u8]) -> Vec<u8> {
+    let mut output = Vec::new();
+    match ty {
+        PackedObjectType::Commit => output.extend(b"commit "),
+        PackedObjectType::Tree => output.extend(b"tree "),
+        PackedObjectType::Blob => output.extend(b"blob "),
+        PackedObjectType::Tag => output.extend(b"tag "),
+        _ => unreachable!(),
+    }
+    output.extend(content.len().to_string().as_bytes());
+    output.push(0);
+    output.extend(content);
+    output
+}
+
+/// Parse all of the objects in a pack file, keyed by their hashes.
 pub fn parse_pack_file(input: &[u8]) -> Result<HashMap<String, PackedObject>> {
-    assert_eq!(&input[0..4], b"PACK");
+    // Header and trailing checksum
+    if input.len() < 12 + 20 || &input[0..4] != b"PACK" {
+        return Err(Error::CorruptPack("missing pack header".into()));
+    }
 
     let checksum = {
         let mut hasher = Sha1::new();
         hasher.update(&input[0..input.len() - 20]);
         hex::encode(hasher.finalize())
     };
-    assert_eq!(
-        hex::encode(&input[input.len() - 20..]),
-        checksum,
-        "pack file checksum failure"
-    );
+    if hex::encode(&input[input.len() - 20..]) != checksum {
+        return Err(Error::BadPackChecksum);
+    }
 
     let version = u32::from_be_bytes([input[4], input[5], input[6], input[7]]);
-    assert!(version == 2 || version == 3);
+    if version != 2 && version != 3 {
+        return Err(Error::CorruptPack(format!(
+            "unsupported pack version {}",
+            version
+        )));
+    }
     let object_count = u32::from_be_bytes([input[8], input[9], input[10], input[11]]);
 
+    let pack = input;
     let mut input = &input[12..];
 
-    let mut objects: HashMap<String, PackedObject> = HashMap::new();
+    let mut unresolved = Vec::with_capacity(object_count as usize);
     for _ in 0..object_count {
-        let (rest, mut packed_object) = PackedObject::parse(input)?;
+        let offset = pack.len() - input.len();
+        let (rest, packed_object) = PackedObject::parse(input, offset)?;
         input = rest;
+        unresolved.push((offset, packed_object));
+    }
 
-        match &packed_object.ty {
-            PackedObjectType::OfsDelta(_) => {
-                todo!("offset delta");
-            }
-            PackedObjectType::RefDelta(hash) => {
-                let hash = hash.as_ref().unwrap();
-                let ref_object = objects
-                    .get(hash)
-                    .expect("could not find delta reference object");
-                packed_object.content = patch_delta(&packed_object.content, &ref_object.content)?;
-                packed_object.ty = PackedObjectType::Blob;
+    let mut objects: HashMap<String, PackedObject> = HashMap::new();
+    // Hashes of the resolved objects, keyed by their position in the pack
+    let mut offsets: HashMap<usize, String> = HashMap::new();
+    while !unresolved.is_empty() {
+        // Deltas whose base hasn't been resolved yet (e.g., because it appears later in the
+        // pack) are deferred until the next pass
+        let mut deferred = Vec::new();
+        let unresolved_count = unresolved.len();
+        for (offset, mut packed_object) in unresolved {
+            if packed_object.is_delta() {
+                let base_object = match packed_object.delta_base(offset, &objects, &offsets) {
+                    Some(base_object) => base_object,
+                    None => {
+                        deferred.push((offset, packed_object));
+                        continue;
+                    }
+                };
+                packed_object.content = patch_delta(&packed_object.content, &base_object.content)?;
+                packed_object.ty = base_object.ty.clone();
             }
-            _ => {}
+            let hash = packed_object.hash();
+            offsets.insert(offset, hash.clone());
+            objects.insert(hash, packed_object);
+        }
+
+        if deferred.len() == unresolved_count {
+            return Err(Error::CorruptPack(
+                "could not find delta base object".into(),
+            ));
         }
-        let hash = packed_object.hash();
-        objects.insert(hash, packed_object);
+        unresolved = deferred;
     }
 
     Ok(objects)
@@ -155,9 +255,12 @@ enum PatchInstruction {
     Add { data: Vec<u8> },
 }
 
-fn patch_delta(input: &[u8], source: &[u8]) -> Result<Vec<u8>> {
-    let (input, _source_buf_len) = parse_size(input)?;
+pub(crate) fn patch_delta(input: &[u8], source: &[u8]) -> Result<Vec<u8>> {
+    let (input, source_buf_len) = parse_size(input)?;
     let (input, target_buf_len) = parse_size(input)?;
+    if source_buf_len != source.len() {
+        return Err(Error::CorruptPack("delta base object size mismatch".into()));
+    }
 
     let mut result = Vec::with_capacity(target_buf_len);
 
@@ -168,7 +271,12 @@ fn patch_delta(input: &[u8], source: &[u8]) -> Result<Vec<u8>> {
 
         match instruction {
             PatchInstruction::Copy { offset, size } => {
-                result.extend(&source[offset..offset + size]);
+                // A size of zero is used for the largest possible copy
+                let size = if size == 0 { 0x10000 } else { size };
+                let data = source.get(offset..offset + size).ok_or_else(|| {
+                    Error::CorruptPack("delta copies from outside of base object".into())
+                })?;
+                result.extend(data);
             }
             PatchInstruction::Add { data } => {
                 result.extend(data);
@@ -176,11 +284,16 @@ fn patch_delta(input: &[u8], source: &[u8]) -> Result<Vec<u8>> {
         }
     }
 
+    if result.len() != target_buf_len {
+        return Err(Error::CorruptPack("delta result size mismatch".into()));
+    }
+
     Ok(result)
 }
 
 fn parse_patch_instruction(input: &[u8]) -> Result<(&[u8], PatchInstruction)> {
-    match high_bit(input[0]) {
+    let first_byte = byte_at(input, 0)?;
+    match high_bit(first_byte) {
         true => {
             // Copy instruction
             let mut bytes_read = 1;
@@ -189,15 +302,15 @@ fn parse_patch_instruction(input: &[u8]) -> Result<(&[u8], PatchInstruction)> {
             #[allow(clippy::needless_range_loop)]
             for i in 0..4 {
                 // Offset bytes
-                if input[0] & (1 << i) != 0 {
-                    offset_bytes[i] = input[bytes_read];
+                if first_byte & (1 << i) != 0 {
+                    offset_bytes[i] = byte_at(input, bytes_read)?;
                     bytes_read += 1;
                 }
             }
             for i in 4..7 {
                 // Size bytes
-                if input[0] & (1 << i) != 0 {
-                    size_bytes[i - 4] = input[bytes_read];
+                if first_byte & (1 << i) != 0 {
+                    size_bytes[i - 4] = byte_at(input, bytes_read)?;
                     bytes_read += 1;
                 }
             }
@@ -211,9 +324,14 @@ fn parse_patch_instruction(input: &[u8]) -> Result<(&[u8], PatchInstruction)> {
         }
         false => {
             // Add instruction
-            let size = (input[0] & 0x7f) as usize;
-            assert!(size > 0, "invalid instruction");
-            let data = input[1..1 + size].to_vec();
+            let size = (first_byte & 0x7f) as usize;
+            if size == 0 {
+                return Err(Error::CorruptPack("invalid delta instruction".into()));
+            }
+            let data = input
+                .get(1..1 + size)
+                .ok_or_else(|| Error::CorruptPack("truncated delta instruction".into()))?
+                .to_vec();
             Ok((&input[1 + size..], PatchInstruction::Add { data }))
         }
     }
@@ -221,7 +339,63 @@ fn parse_patch_instruction(input: &[u8]) -> Result<(&[u8], PatchInstruction)> {
 
 #[cfg(test)]
 mod tests {
-    use super::{parse_patch_instruction, PackedObject, PackedObjectType, PatchInstruction};
+    use std::io::Write;
+
+    use flate2::{write::ZlibEncoder, Compression};
+    use sha1::{Digest, Sha1};
+
+    use super::{
+        parse_pack_file, parse_patch_instruction, PackedObject, PackedObjectType, PatchInstruction,
+    };
+    use crate::error::Error;
+
+    /// Encode a packed object. `base` is the already-encoded base offset or hash for deltas.
+    fn encode_packed_object(ty: u8, content: &[u8], base: &[u8]) -> Vec<u8> {
+        let mut output = Vec::new();
+
+        let mut size = content.len();
+        let mut byte = (ty << 4) | (size & 0x0f) as u8;
+        size >>= 4;
+        while size > 0 {
+            output.push(byte | 0x80);
+            byte = (size & 0x7f) as u8;
+            size >>= 7;
+        }
+        output.push(byte);
+        output.extend(base);
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(content).unwrap();
+        output.extend(encoder.finish().unwrap());
+        output
+    }
+
+    /// Encode the negative offset of an offset delta's base object.
+    fn encode_offset(mut offset: usize) -> Vec<u8> {
+        let mut output = vec![(offset & 0x7f) as u8];
+        offset >>= 7;
+        while offset > 0 {
+            offset -= 1;
+            output.push(0x80 | (offset & 0x7f) as u8);
+            offset >>= 7;
+        }
+        output.reverse();
+        output
+    }
+
+    /// Assemble a pack file from already-encoded objects.
+    fn encode_pack_file(objects: &[Vec<u8>]) -> Vec<u8> {
+        let mut pack = Vec::new();
+        pack.extend(b"PACK");
+        pack.extend(2u32.to_be_bytes());
+        pack.extend((objects.len() as u32).to_be_bytes());
+        for object in objects.iter() {
+            pack.extend(object);
+        }
+        let checksum = Sha1::digest(&pack);
+        pack.extend(checksum);
+        pack
+    }
 
     #[test]
     fn test_parse_object_header() {
@@ -258,4 +432,78 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn test_parse_offset_delta_chain() {
+        let base = encode_packed_object(3, b"hello world", &[]);
+        // Copy "hello " from the base, then add "there"
+        let first_delta = encode_packed_object(
+            6,
+            &[11, 11, 0x90, 6, 5, b't', b'h', b'e', b'r', b'e'],
+            &encode_offset(base.len()),
+        );
+        // Copy all of "hello there", then add "!"
+        let second_delta = encode_packed_object(
+            6,
+            &[11, 12, 0x90, 11, 1, b'!'],
+            &encode_offset(first_delta.len()),
+        );
+        let pack = encode_pack_file(&[base, first_delta, second_delta]);
+
+        let objects = parse_pack_file(&pack).unwrap();
+        assert_eq!(objects.len(), 3);
+        let mut contents = objects
+            .values()
+            .map(|o| std::str::from_utf8(&o.content).unwrap())
+            .collect::<Vec<_>>();
+        contents.sort();
+        assert_eq!(contents, vec!["hello there", "hello there!", "hello world"]);
+    }
+
+    #[test]
+    fn test_parse_ref_delta_before_base() {
+        let mut base_content = b"100644 a.md\0".to_vec();
+        base_content.extend([0xaa; 20]);
+        let base = encode_packed_object(2, &base_content, &[]);
+        let base_hash = Sha1::digest([b"tree 32\0".as_slice(), &base_content].concat());
+
+        // Add an entry in front of the base tree's only entry
+        let mut delta_content = vec![32, 64, 32];
+        let mut new_entry = b"100644 1.md\0".to_vec();
+        new_entry.extend([0xbb; 20]);
+        delta_content.extend(&new_entry);
+        delta_content.extend([0x90, 32]);
+        let delta = encode_packed_object(7, &delta_content, &base_hash);
+
+        let pack = encode_pack_file(&[delta, base]);
+        let objects = parse_pack_file(&pack).unwrap();
+        assert_eq!(objects.len(), 2);
+
+        let tree_content = [new_entry, base_content].concat();
+        let tree_hash = hex::encode(Sha1::digest(
+            [b"tree 64\0".as_slice(), &tree_content].concat(),
+        ));
+        let tree = &objects[&tree_hash];
+        assert_eq!(tree.ty, PackedObjectType::Tree);
+        assert_eq!(tree.content, tree_content);
+    }
+
+    #[test]
+    fn test_parse_corrupt_pack_file() {
+        let mut pack = encode_pack_file(&[encode_packed_object(3, b"hello world", &[])]);
+        assert!(parse_pack_file(&pack[0..10]).is_err());
+
+        let last = pack.len() - 1;
+        pack[last] ^= 0xff;
+        assert!(matches!(
+            parse_pack_file(&pack),
+            Err(Error::BadPackChecksum)
+        ));
+
+        // Copy past the end of the base object
+        let base = encode_packed_object(3, b"hello world", &[]);
+        let delta = encode_packed_object(6, &[11, 12, 0x90, 12], &encode_offset(base.len()));
+        let pack = encode_pack_file(&[base, delta]);
+        assert!(matches!(parse_pack_file(&pack), Err(Error::CorruptPack(_))));
+    }
 }
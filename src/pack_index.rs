@@ -0,0 +1,229 @@
+//! Reading and writing pack index (`.idx`) files.
+
+use sha1::{Digest, Sha1};
+use std::{collections::HashMap, path::PathBuf};
+
+use crate::{
+    error::{Error, Result},
+    pack::PackedObject,
+    util::read_u32,
+};
+
+const MAGIC: [u8; 4] = [0xff, b't', b'O', b'c'];
+const VERSION: u32 = 2;
+/// Offsets with this bit set are indices into the table of 64-bit offsets
+const LARGE_OFFSET_FLAG: u32 = 0x8000_0000;
+
+/// A version 2 pack index (`.idx`) file.
+#[derive(Debug)]
+pub struct PackIndex {
+    /// Entries sorted by hash
+    entries: Vec<PackIndexEntry>,
+    pack_checksum: String,
+}
+
+#[derive(Debug, PartialEq)]
+struct PackIndexEntry {
+    hash: String,
+    crc32: u32,
+    offset: u64,
+}
+
+impl PackIndex {
+    /// Build an index for the objects parsed from a pack file with the given checksum.
+    pub fn new(objects: &HashMap<String, PackedObject>, pack_checksum: &str) -> Self {
+        let mut entries = objects
+            .iter()
+            .map(|(hash, object)| PackIndexEntry {
+                hash: hash.clone(),
+                crc32: object.crc32(),
+                offset: object.offset() as u64,
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by(|a, b| a.hash.cmp(&b.hash));
+
+        PackIndex {
+            entries,
+            pack_checksum: pack_checksum.to_owned(),
+        }
+    }
+
+    /// Parse an index from a `.idx` file.
+    pub fn parse_from_path<P>(path: P) -> Result<Self>
+    where
+        P: Into<PathBuf>,
+    {
+        let content = std::fs::read(path.into())?;
+        PackIndex::parse(&content)
+    }
+
+    fn parse(input: &[u8]) -> Result<Self> {
+        // Header, fan-out table and two checksums
+        if input.len() < 8 + 256 * 4 + 2 * 20 {
+            return Err(Error::CorruptPack("truncated pack index".into()));
+        }
+        if input[0..4] != MAGIC || read_u32(&input[4..]) != VERSION {
+            return Err(Error::CorruptPack("unsupported pack index version".into()));
+        }
+
+        let checksum = hex::encode(Sha1::digest(&input[0..input.len() - 20]));
+        if hex::encode(&input[input.len() - 20..]) != checksum {
+            return Err(Error::CorruptPack("pack index checksum failure".into()));
+        }
+
+        let fan_out = &input[8..8 + 256 * 4];
+        let object_count = read_u32(&fan_out[255 * 4..]) as usize;
+        if input.len() < 8 + 256 * 4 + object_count * (20 + 4 + 4) + 2 * 20 {
+            return Err(Error::CorruptPack("truncated pack index".into()));
+        }
+
+        let hashes = &input[8 + 256 * 4..];
+        let crc32s = &hashes[object_count * 20..];
+        let offsets = &crc32s[object_count * 4..];
+        let large_offsets = &offsets[object_count * 4..];
+
+        let mut large_offset_count = 0;
+        let mut entries = Vec::with_capacity(object_count);
+        for i in 0..object_count {
+            let offset = read_u32(&offsets[i * 4..]);
+            let offset = if offset & LARGE_OFFSET_FLAG != 0 {
+                let j = (offset & !LARGE_OFFSET_FLAG) as usize;
+                large_offset_count = large_offset_count.max(j + 1);
+                let large_offset = large_offsets
+                    .get(j * 8..j * 8 + 8)
+                    .ok_or_else(|| Error::CorruptPack("truncated pack index".into()))?;
+                u64::from_be_bytes(large_offset.try_into().unwrap())
+            } else {
+                offset as u64
+            };
+
+            entries.push(PackIndexEntry {
+                hash: hex::encode(&hashes[i * 20..i * 20 + 20]),
+                crc32: read_u32(&crc32s[i * 4..]),
+                offset,
+            });
+        }
+
+        let pack_checksum_start = large_offset_count * 8;
+        let pack_checksum = large_offsets
+            .get(pack_checksum_start..pack_checksum_start + 20)
+            .ok_or_else(|| Error::CorruptPack("truncated pack index".into()))?;
+        let pack_checksum = hex::encode(pack_checksum);
+
+        Ok(PackIndex {
+            entries,
+            pack_checksum,
+        })
+    }
+
+    /// Encode this index in the `.idx` file format.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut output = Vec::new();
+        output.extend(MAGIC);
+        output.extend(VERSION.to_be_bytes());
+
+        let mut fan_out = [0u32; 256];
+        for entry in self.entries.iter() {
+            let first_byte = u8::from_str_radix(&entry.hash[0..2], 16).unwrap();
+            fan_out[first_byte as usize] += 1;
+        }
+        let mut total = 0;
+        for count in fan_out.iter_mut() {
+            total += *count;
+            *count = total;
+        }
+        for count in fan_out.iter() {
+            output.extend(count.to_be_bytes());
+        }
+
+        for entry in self.entries.iter() {
+            output.extend(hex::decode(&entry.hash).unwrap());
+        }
+        for entry in self.entries.iter() {
+            output.extend(entry.crc32.to_be_bytes());
+        }
+
+        let mut large_offsets = Vec::new();
+        for entry in self.entries.iter() {
+            if entry.offset < LARGE_OFFSET_FLAG as u64 {
+                output.extend((entry.offset as u32).to_be_bytes());
+            } else {
+                output.extend((LARGE_OFFSET_FLAG | large_offsets.len() as u32).to_be_bytes());
+                large_offsets.push(entry.offset);
+            }
+        }
+        for offset in large_offsets.iter() {
+            output.extend(offset.to_be_bytes());
+        }
+
+        output.extend(hex::decode(&self.pack_checksum).unwrap());
+        let checksum = Sha1::digest(&output);
+        output.extend(checksum);
+
+        output
+    }
+
+    /// The hashes of all objects in the pack that start with the given (lowercase) prefix.
+    pub fn hashes_with_prefix<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = &'a str> {
+        let start = self.entries.partition_point(|e| e.hash.as_str() < prefix);
+        self.entries[start..]
+            .iter()
+            .map(|e| e.hash.as_str())
+            .take_while(move |hash| hash.starts_with(prefix))
+    }
+
+    /// Find the offset of an object in the pack file.
+    pub fn find(&self, hash: &str) -> Option<usize> {
+        self.entries
+            .binary_search_by(|e| e.hash.as_str().cmp(hash))
+            .ok()
+            .map(|i| self.entries[i].offset as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PackIndex, PackIndexEntry};
+
+    #[test]
+    fn test_encode_and_parse() {
+        let index = PackIndex {
+            entries: vec![
+                PackIndexEntry {
+                    hash: "0a".repeat(20),
+                    crc32: 0x12345678,
+                    offset: 12,
+                },
+                PackIndexEntry {
+                    hash: "0b".repeat(20),
+                    crc32: 0x9abcdef0,
+                    offset: 0x1_0000_0000,
+                },
+                PackIndexEntry {
+                    hash: "f0".repeat(20),
+                    crc32: 0,
+                    offset: 345,
+                },
+            ],
+            pack_checksum: "cd".repeat(20),
+        };
+
+        let encoded = index.encode();
+        // Header, fan-out, 3 entries, one large offset, and two checksums
+        assert_eq!(encoded.len(), 8 + 256 * 4 + 3 * (20 + 4 + 4) + 8 + 2 * 20);
+
+        let parsed = PackIndex::parse(&encoded).unwrap();
+        assert_eq!(parsed.entries, index.entries);
+        assert_eq!(parsed.pack_checksum, index.pack_checksum);
+
+        assert_eq!(parsed.find(&"0b".repeat(20)), Some(0x1_0000_0000));
+        assert_eq!(parsed.find(&"0c".repeat(20)), None);
+
+        assert_eq!(parsed.hashes_with_prefix("0").count(), 2);
+        assert_eq!(
+            parsed.hashes_with_prefix("f0f").collect::<Vec<_>>(),
+            [&"f0".repeat(20)]
+        );
+        assert_eq!(parsed.hashes_with_prefix("0c").count(), 0);
+    }
+}
@@ -0,0 +1,230 @@
+//! Reading objects out of the packs in an object store.
+
+use flate2::read::ZlibDecoder;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use crate::{
+    error::{Error, Result},
+    pack::{encode_object, patch_delta, PackedObject, PackedObjectType},
+    pack_index::PackIndex,
+};
+
+/// Limit on the total size of the delta bases kept in the cache.
+const DELTA_BASE_CACHE_LIMIT: usize = 96 * 1024 * 1024;
+
+/// Enough bytes to hold the largest possible packed object header.
+const MAX_HEADER_LEN: u64 = 64;
+
+/// The pack files in an objects directory.
+pub(crate) struct PackStore {
+    packs: Vec<Pack>,
+    cache: DeltaBaseCache,
+}
+
+struct Pack {
+    index: PackIndex,
+    file: File,
+}
+
+impl PackStore {
+    /// Open all of the packs in the given objects directory.
+    pub fn open(objects_dir: &Path) -> Result<Self> {
+        let mut packs = Vec::new();
+
+        let pack_dir = objects_dir.join("pack");
+        if pack_dir.is_dir() {
+            for entry in std::fs::read_dir(pack_dir)? {
+                let index_path = entry?.path();
+                if index_path.extension().map_or(true, |ext| ext != "idx") {
+                    continue;
+                }
+
+                let index = PackIndex::parse_from_path(&index_path)?;
+                let file = File::open(index_path.with_extension("pack"))?;
+                packs.push(Pack { index, file });
+            }
+        }
+
+        Ok(PackStore {
+            packs,
+            cache: DeltaBaseCache::new(DELTA_BASE_CACHE_LIMIT),
+        })
+    }
+
+    /// Read an object, encoded as it would be as a loose object.
+    pub fn read(&mut self, hash: &str) -> Result<Option<Vec<u8>>> {
+        match self.find(hash) {
+            Some((pack, offset)) => {
+                let (ty, content) = self.read_at(pack, offset)?;
+                Ok(Some(encode_object(&ty, &content)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Whether any of the packs contains an object.
+    pub fn contains(&self, hash: &str) -> bool {
+        self.find(hash).is_some()
+    }
+
+    /// The hashes of all packed objects that start with the given prefix.
+    pub fn hashes_with_prefix(&self, prefix: &str) -> Vec<String> {
+        self.packs
+            .iter()
+            .flat_map(|pack| pack.index.hashes_with_prefix(prefix))
+            .map(str::to_owned)
+            .collect()
+    }
+
+    /// Find which pack an object is in, and its offset in that pack.
+    fn find(&self, hash: &str) -> Option<(usize, usize)> {
+        self.packs
+            .iter()
+            .enumerate()
+            .find_map(|(i, pack)| pack.index.find(hash).map(|offset| (i, offset)))
+    }
+
+    /// Read the object at `offset` in a pack, walking back along its chain of deltas to a base
+    /// object or a cached base, and then applying the deltas.
+    fn read_at(&mut self, pack: usize, offset: usize) -> Result<(PackedObjectType, Vec<u8>)> {
+        let mut deltas = Vec::new();
+        let (mut pack, mut offset) = (pack, offset);
+
+        let (ty, mut content) = loop {
+            if let Some(base) = self.cache.get(pack, offset) {
+                break base;
+            }
+
+            let (ty, content) = self.read_raw(pack, offset)?;
+            match ty {
+                PackedObjectType::OfsDelta(delta_offset) => {
+                    deltas.push((pack, offset, content));
+                    offset = offset.checked_sub(delta_offset.unwrap()).ok_or_else(|| {
+                        Error::CorruptPack("offset delta base is before start of pack".into())
+                    })?;
+                }
+                PackedObjectType::RefDelta(hash) => {
+                    deltas.push((pack, offset, content));
+                    (pack, offset) = self.find(hash.as_ref().unwrap()).ok_or_else(|| {
+                        Error::CorruptPack("could not find delta base object".into())
+                    })?;
+                }
+                _ => {
+                    if !deltas.is_empty() {
+                        self.cache.insert(pack, offset, ty.clone(), content.clone());
+                    }
+                    break (ty, content);
+                }
+            }
+        };
+
+        while let Some((pack, offset, delta)) = deltas.pop() {
+            content = patch_delta(&delta, &content)?;
+            // Only bases are worth caching, not the object that was asked for
+            if !deltas.is_empty() {
+                self.cache.insert(pack, offset, ty.clone(), content.clone());
+            }
+        }
+
+        Ok((ty, content))
+    }
+
+    /// Read and inflate the object at `offset` in a pack, without resolving deltas.
+    fn read_raw(&mut self, pack: usize, offset: usize) -> Result<(PackedObjectType, Vec<u8>)> {
+        let file = &mut self.packs[pack].file;
+
+        file.seek(SeekFrom::Start(offset as u64))?;
+        let mut header = Vec::new();
+        (&mut *file).take(MAX_HEADER_LEN).read_to_end(&mut header)?;
+        let (rest, (ty, size)) = PackedObject::parse_header(&header)?;
+        let header_len = header.len() - rest.len();
+
+        file.seek(SeekFrom::Start((offset + header_len) as u64))?;
+        let mut decoder = ZlibDecoder::new(BufReader::new(&mut *file));
+        let mut content = Vec::with_capacity(size);
+        decoder.read_to_end(&mut content)?;
+
+        Ok((ty, content))
+    }
+}
+
+/// Least-recently-used cache of resolved delta base objects, limited by their total size.
+struct DeltaBaseCache {
+    entries: HashMap<(usize, usize), (u64, PackedObjectType, Vec<u8>)>,
+    /// Keys of the cached entries, ordered by when they were last used
+    usage: BTreeMap<u64, (usize, usize)>,
+    clock: u64,
+    size: usize,
+    limit: usize,
+}
+
+impl DeltaBaseCache {
+    fn new(limit: usize) -> Self {
+        DeltaBaseCache {
+            entries: HashMap::new(),
+            usage: BTreeMap::new(),
+            clock: 0,
+            size: 0,
+            limit,
+        }
+    }
+
+    fn get(&mut self, pack: usize, offset: usize) -> Option<(PackedObjectType, Vec<u8>)> {
+        let (last_used, ty, content) = self.entries.get_mut(&(pack, offset))?;
+
+        self.clock += 1;
+        self.usage.remove(last_used);
+        self.usage.insert(self.clock, (pack, offset));
+        *last_used = self.clock;
+
+        Some((ty.clone(), content.clone()))
+    }
+
+    fn insert(&mut self, pack: usize, offset: usize, ty: PackedObjectType, content: Vec<u8>) {
+        if content.len() > self.limit || self.entries.contains_key(&(pack, offset)) {
+            return;
+        }
+
+        self.size += content.len();
+        while self.size > self.limit {
+            let (_, key) = self.usage.pop_first().unwrap();
+            let (_, _, evicted) = self.entries.remove(&key).unwrap();
+            self.size -= evicted.len();
+        }
+
+        self.clock += 1;
+        self.usage.insert(self.clock, (pack, offset));
+        self.entries
+            .insert((pack, offset), (self.clock, ty, content));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DeltaBaseCache;
+    use crate::pack::PackedObjectType;
+
+    #[test]
+    fn test_delta_base_cache_eviction() {
+        let mut cache = DeltaBaseCache::new(10);
+        cache.insert(0, 1, PackedObjectType::Blob, vec![1; 4]);
+        cache.insert(0, 2, PackedObjectType::Blob, vec![2; 4]);
+
+        // Using the first entry should make the second the one to be evicted
+        assert!(cache.get(0, 1).is_some());
+        cache.insert(0, 3, PackedObjectType::Tree, vec![3; 4]);
+        assert!(cache.get(0, 2).is_none());
+        assert_eq!(cache.get(0, 1), Some((PackedObjectType::Blob, vec![1; 4])));
+        assert_eq!(cache.get(0, 3), Some((PackedObjectType::Tree, vec![3; 4])));
+        assert_eq!(cache.size, 8);
+
+        // Entries too large for the cache aren't stored
+        cache.insert(1, 1, PackedObjectType::Blob, vec![0; 11]);
+        assert!(cache.get(1, 1).is_none());
+    }
+}
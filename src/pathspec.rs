@@ -0,0 +1,161 @@
+//! Pathspecs: the paths and patterns commands take to say which files they apply to.
+
+use std::path::{Component, Path};
+
+use crate::{
+    error::{Error, Result},
+    repository::Repository,
+    wildmatch::fnmatch,
+};
+
+/// A list of paths or glob patterns, relative to the root of the working tree.
+#[derive(Debug)]
+pub struct Pathspec {
+    patterns: Vec<String>,
+}
+
+/// How a path matched a pathspec.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PathspecMatch {
+    /// Which of the patterns matched
+    pub pattern: usize,
+    /// Whether the path is inside a directory the pattern names, rather than matching it directly
+    pub in_directory: bool,
+}
+
+impl Pathspec {
+    /// Build a pathspec from arguments relative to the current directory.
+    pub fn new<S>(repo: &Repository, args: &[S]) -> Result<Self>
+    where
+        S: AsRef<str>,
+    {
+        let prefix = current_prefix(repo)?;
+        let patterns = args
+            .iter()
+            .map(|arg| normalize(Path::new(&prefix), arg.as_ref()))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Pathspec { patterns })
+    }
+
+    /// A pathspec matching everything in the working tree.
+    pub fn all() -> Self {
+        Pathspec {
+            patterns: vec![String::new()],
+        }
+    }
+
+    /// A pathspec of patterns that are already relative to the root of the working tree.
+    #[cfg(test)]
+    pub(crate) fn from_patterns(patterns: &[&str]) -> Self {
+        Pathspec {
+            patterns: patterns.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+
+    /// The patterns, relative to the root of the working tree.
+    pub fn patterns(&self) -> &[String] {
+        &self.patterns
+    }
+
+    /// Find the first pattern that matches a path.
+    pub fn matches(&self, path: &str) -> Option<PathspecMatch> {
+        self.patterns.iter().enumerate().find_map(|(i, pattern)| {
+            let in_directory = if pattern.is_empty() {
+                true
+            } else if path == pattern {
+                false
+            } else if path.starts_with(pattern.as_str()) && path.as_bytes()[pattern.len()] == b'/' {
+                true
+            } else if has_wildcards(pattern) && fnmatch(pattern, path) {
+                false
+            } else {
+                return None;
+            };
+            Some(PathspecMatch {
+                pattern: i,
+                in_directory,
+            })
+        })
+    }
+}
+
+/// The current directory's path from the root of the working tree, with `/` separators, e.g.,
+/// `src/bin`, or an empty string at the root.
+pub fn current_prefix(repo: &Repository) -> Result<String> {
+    let work_tree = repo.work_tree()?.canonicalize()?;
+    let current_dir = std::env::current_dir()?.canonicalize()?;
+    let prefix = current_dir
+        .strip_prefix(&work_tree)
+        .map_err(|_| Error::OutsideRepository(current_dir.clone()))?;
+    Ok(prefix
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/"))
+}
+
+/// Whether a pattern contains any glob characters.
+fn has_wildcards(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '[', '\\'])
+}
+
+/// Turn a path relative to `prefix` (the current directory's path in the working tree) into one
+/// relative to the root of the working tree, without `.` or `..` components.
+fn normalize(prefix: &Path, arg: &str) -> Result<String> {
+    let mut components: Vec<String> = Vec::new();
+    for component in prefix.join(arg).components() {
+        match component {
+            Component::Normal(name) => components.push(name.to_string_lossy().into_owned()),
+            Component::CurDir => (),
+            Component::ParentDir => {
+                if components.pop().is_none() {
+                    return Err(Error::OutsideRepository(arg.into()));
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => {
+                return Err(Error::OutsideRepository(arg.into()))
+            }
+        }
+    }
+    Ok(components.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize, Pathspec, PathspecMatch};
+    use std::path::Path;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize(Path::new(""), ".").unwrap(), "");
+        assert_eq!(
+            normalize(Path::new("src"), "main.rs").unwrap(),
+            "src/main.rs"
+        );
+        assert_eq!(
+            normalize(Path::new("src/a"), "../b/./c/").unwrap(),
+            "src/b/c"
+        );
+        assert!(normalize(Path::new("src"), "../..").is_err());
+        assert!(normalize(Path::new(""), "/etc").is_err());
+    }
+
+    #[test]
+    fn test_matches() {
+        let pathspec = Pathspec {
+            patterns: vec!["src".into(), "*.md".into(), "Cargo.toml".into()],
+        };
+        let matched = |pattern, in_directory| {
+            Some(PathspecMatch {
+                pattern,
+                in_directory,
+            })
+        };
+        assert_eq!(pathspec.matches("src/main.rs"), matched(0, true));
+        assert_eq!(pathspec.matches("src"), matched(0, false));
+        assert_eq!(pathspec.matches("docs/README.md"), matched(1, false));
+        assert_eq!(pathspec.matches("Cargo.toml"), matched(2, false));
+        assert_eq!(pathspec.matches("srcs/main.rs"), None);
+        assert_eq!(Pathspec::all().matches("a/b"), matched(0, true));
+    }
+}
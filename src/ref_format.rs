@@ -0,0 +1,330 @@
+//! Formatting and sorting refs for `for-each-ref`, with `%(atom)` placeholders like
+//! `%(refname:short)` or `%(*objectname)`.
+
+use crate::{
+    error::{Error, Result},
+    object::Object,
+    refs::{self, Reference},
+    repository::Repository,
+    revision::{self, DEFAULT_ABBREV},
+};
+
+/// A ref with the object it points to, and whether it's the current branch.
+#[derive(Debug)]
+pub struct RefDetails {
+    /// The ref itself
+    pub reference: Reference,
+    /// The object the ref points to
+    pub object: Object,
+    /// Whether `HEAD` points to the ref
+    pub is_head: bool,
+}
+
+impl RefDetails {
+    /// Read the object a ref points to. `head` is the ref `HEAD` points to, if any.
+    pub fn read(repo: &Repository, reference: Reference, head: Option<&str>) -> Result<Self> {
+        let object = Object::parse_from_hash(repo, &reference.hash)?;
+        Ok(RefDetails {
+            is_head: head == Some(reference.name.as_str()),
+            reference,
+            object,
+        })
+    }
+}
+
+/// Expand the `%(atom)` placeholders, `%%` and `%xx` hex escapes in a `for-each-ref` format.
+pub fn format(repo: &Repository, details: &RefDetails, format: &str) -> Result<String> {
+    let mut output = Vec::new();
+    let mut rest = format;
+    while let Some(percent) = rest.find('%') {
+        output.extend(&rest.as_bytes()[..percent]);
+        rest = &rest[percent + 1..];
+        if let Some(atom_start) = rest.strip_prefix('(') {
+            let end = atom_start.find(')').ok_or_else(|| {
+                Error::InvalidFormat(format!("malformed format string {}", format))
+            })?;
+            output.extend(atom(repo, details, &atom_start[..end])?.0.as_bytes());
+            rest = &atom_start[end + 1..];
+        } else if let Some(after) = rest.strip_prefix('%') {
+            output.push(b'%');
+            rest = after;
+        } else if let Some(byte) = rest
+            .get(..2)
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            output.push(byte);
+            rest = &rest[2..];
+        } else {
+            output.push(b'%');
+        }
+    }
+    output.extend(rest.as_bytes());
+    Ok(String::from_utf8_lossy(&output).into_owned())
+}
+
+/// Sort refs by `--sort` keys, which are atoms, optionally prefixed with `-` to sort in
+/// descending order. As in git, the last key is the primary one.
+pub fn sort(repo: &Repository, refs: Vec<RefDetails>, keys: &[String]) -> Result<Vec<RefDetails>> {
+    // Each sort is stable, so sorting by each key in turn leaves the last as the primary one
+    let mut refs = refs;
+    for key in keys.iter() {
+        let (descending, name) = match key.strip_prefix('-') {
+            Some(name) => (true, name),
+            None => (false, key.as_str()),
+        };
+        let mut keyed = refs
+            .into_iter()
+            .map(|details| Ok((atom(repo, &details, name)?, details)))
+            .collect::<Result<Vec<_>>>()?;
+        keyed.sort_by(|(a, _), (b, _)| {
+            let ordering = match (a.1, b.1) {
+                (Some(a), Some(b)) => a.cmp(&b),
+                _ => a.0.cmp(&b.0),
+            };
+            if descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+        refs = keyed.into_iter().map(|(_, details)| details).collect();
+    }
+    Ok(refs)
+}
+
+/// The value of a `for-each-ref` atom for a ref, along with a number to sort by for atoms that
+/// are numeric, like dates. Atoms starting with `*` describe the object a tag points to.
+pub fn atom(repo: &Repository, details: &RefDetails, atom: &str) -> Result<(String, Option<i64>)> {
+    let text = |value: String| Ok((value, None));
+    let unknown = || Error::InvalidFormat(format!("unknown field name: {}", atom));
+    let (name, modifier) = match atom.split_once(':') {
+        Some((name, modifier)) => (name, Some(modifier)),
+        None => (atom, None),
+    };
+
+    let peeled;
+    let (hash, object) = match name.strip_prefix('*') {
+        Some(_) => match &details.object {
+            Object::Tag(_) => {
+                let hash = revision::peel(repo, &details.reference.hash, "")?;
+                peeled = Object::parse_from_hash(repo, &hash)?;
+                (hash, &peeled)
+            }
+            _ => return text(String::new()),
+        },
+        None => (details.reference.hash.clone(), &details.object),
+    };
+    let name = name.trim_start_matches('*');
+
+    match (name, modifier) {
+        ("refname", None) => return text(details.reference.name.clone()),
+        ("refname", Some("short")) => {
+            return text(refs::shorten(&details.reference.name).to_owned())
+        }
+        ("symref", None) => return text(details.reference.target.clone().unwrap_or_default()),
+        ("symref", Some("short")) => {
+            let target = details.reference.target.as_deref().unwrap_or_default();
+            return text(refs::shorten(target).to_owned());
+        }
+        ("upstream", None | Some("short")) => {
+            let upstream = match details.reference.name.starts_with("refs/heads/") {
+                true => revision::upstream(repo, &details.reference.name)?,
+                false => None,
+            };
+            let upstream = upstream.unwrap_or_default();
+            return match modifier {
+                Some(_) => text(refs::shorten(&upstream).to_owned()),
+                None => text(upstream),
+            };
+        }
+        ("HEAD", None) => return text(if details.is_head { "*" } else { " " }.to_owned()),
+        ("objectname", None) => return text(hash),
+        ("objectname", Some(modifier)) if modifier.starts_with("short") => {
+            let len = match modifier.strip_prefix("short=") {
+                Some(len) => len.parse().map_err(|_| {
+                    Error::InvalidFormat(format!("positive value expected '{}'", atom))
+                })?,
+                None => DEFAULT_ABBREV,
+            };
+            return text(revision::abbreviate(repo, &hash, len)?);
+        }
+        ("objecttype", None) => return text(object.type_name().to_owned()),
+        ("objectsize", None) => {
+            let size = object.size();
+            return Ok((size.to_string(), Some(size as i64)));
+        }
+        _ => (),
+    }
+
+    // The rest describe the contents of commits and tags
+    let (message, author, committer, tagger, tree, parents) = match object {
+        Object::Commit(commit) => (
+            commit.message.clone(),
+            Some(&commit.author),
+            Some(&commit.committer),
+            None,
+            commit.tree_hash.clone(),
+            commit.parent_hashes.join(" "),
+        ),
+        Object::Tag(tag) => (
+            tag.message.clone(),
+            None,
+            None,
+            tag.tagger.as_ref(),
+            String::new(),
+            String::new(),
+        ),
+        _ => (Vec::new(), None, None, None, String::new(), String::new()),
+    };
+    let message = String::from_utf8_lossy(&message);
+    let (subject, body) = match message.split_once("\n\n") {
+        Some((subject, body)) => (subject, body),
+        None => (message.trim_end_matches('\n'), ""),
+    };
+
+    let (person, field) = match name {
+        "tree" => return text(tree),
+        "parent" => return text(parents),
+        "subject" => return text(subject.lines().collect::<Vec<_>>().join(" ")),
+        "body" => return text(body.to_owned()),
+        "contents" => return text(message.into_owned()),
+        // The creator is whoever made the object: the committer or tagger
+        _ => ["author", "committer", "tagger", "creator"]
+            .iter()
+            .find_map(|person| name.strip_prefix(person).map(|field| (*person, field)))
+            .ok_or_else(unknown)?,
+    };
+    let signature = match person {
+        "author" => author,
+        "committer" => committer,
+        "tagger" => tagger,
+        _ => committer.or(tagger),
+    };
+    let signature = match signature {
+        Some(signature) => signature,
+        None => return text(String::new()),
+    };
+    match field {
+        "" if person != "creator" => text(signature.to_string()),
+        "name" if person != "creator" => text(signature.display_name().into_owned()),
+        "email" if person != "creator" => text(format!("<{}>", signature.display_email())),
+        "date" => Ok((signature.format_date(), Some(signature.time))),
+        _ => Err(unknown()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{atom, format, sort, RefDetails};
+    use crate::{
+        config::ConfigFile,
+        error::Error,
+        object::Object,
+        refs::RefTransaction,
+        test_util::{signature, TempRepo},
+    };
+
+    #[test]
+    fn test_atoms() {
+        let repo = TempRepo::new("ref-format");
+        let tree = Object::Tree(Vec::new());
+        tree.add(&repo).unwrap();
+        let commit = Object::new_commit(
+            &tree.hash(),
+            &[],
+            signature(1000),
+            signature(2000),
+            "Subject\n\nBody\n",
+        );
+        commit.add(&repo).unwrap();
+        let tag = Object::new_tag(&commit.hash(), "commit", "v1", signature(3000), "Tag\n");
+        tag.add(&repo).unwrap();
+
+        let mut transaction = RefTransaction::new(&repo);
+        transaction.update("refs/heads/main", &commit.hash(), None);
+        transaction.update("refs/heads/topic", &commit.hash(), None);
+        transaction.update("refs/tags/v1", &tag.hash(), None);
+        transaction.commit().unwrap();
+        let mut config = ConfigFile::open(repo.git_dir().join("config")).unwrap();
+        config.set("branch.topic.remote", "origin", false).unwrap();
+        config
+            .set("branch.topic.merge", "refs/heads/main", false)
+            .unwrap();
+        config
+            .set(
+                "remote.origin.fetch",
+                "+refs/heads/*:refs/remotes/origin/*",
+                false,
+            )
+            .unwrap();
+        config.save().unwrap();
+
+        let refs = repo.list_refs("refs/").unwrap();
+        let details = refs
+            .into_iter()
+            .map(|r| RefDetails::read(&repo, r, Some("refs/heads/main")).unwrap())
+            .collect::<Vec<_>>();
+        let [main, topic, v1] = &details[..] else {
+            panic!("expected three refs");
+        };
+        let value = |details, name| atom(&repo, details, name).unwrap().0;
+
+        assert_eq!(value(main, "refname:short"), "main");
+        assert_eq!(value(v1, "refname"), "refs/tags/v1");
+        assert_eq!(value(main, "objectname"), commit.hash());
+        assert_eq!(value(main, "objectname:short"), commit.hash()[..7]);
+        assert_eq!(value(main, "objectname:short=10"), commit.hash()[..10]);
+        assert_eq!(
+            (value(main, "HEAD"), value(topic, "HEAD")),
+            ("*".into(), " ".into())
+        );
+        assert_eq!(value(main, "subject"), "Subject");
+        assert_eq!(value(main, "body"), "Body\n");
+
+        // Branches show the remote-tracking branch they merge from, if any
+        assert_eq!(value(topic, "upstream"), "refs/remotes/origin/main");
+        assert_eq!(value(topic, "upstream:short"), "origin/main");
+        assert_eq!(value(main, "upstream"), "");
+
+        // `*` atoms describe what a tag points to, and are empty for other refs
+        assert_eq!(value(v1, "objecttype"), "tag");
+        assert_eq!(value(v1, "*objecttype"), "commit");
+        assert_eq!(value(v1, "*objectname"), commit.hash());
+        assert_eq!(value(v1, "*subject"), "Subject");
+        assert_eq!(value(main, "*objectname"), "");
+
+        // Dates sort by time rather than as text
+        assert_eq!(
+            atom(&repo, main, "authordate").unwrap(),
+            ("Thu Jan 1 00:16:40 1970 +0000".into(), Some(1000))
+        );
+        assert_eq!(atom(&repo, main, "creatordate").unwrap().1, Some(2000));
+        assert_eq!(atom(&repo, v1, "creatordate").unwrap().1, Some(3000));
+        assert_eq!(value(v1, "authordate"), "");
+
+        assert!(matches!(
+            atom(&repo, main, "nonsense"),
+            Err(Error::InvalidFormat(_))
+        ));
+        assert!(matches!(
+            atom(&repo, main, "creatorname"),
+            Err(Error::InvalidFormat(_))
+        ));
+
+        assert_eq!(
+            format(&repo, main, "%(refname:short)%09%%%(HEAD)").unwrap(),
+            "main\t%*"
+        );
+        assert!(matches!(
+            format(&repo, main, "%(refname"),
+            Err(Error::InvalidFormat(_))
+        ));
+
+        let sorted = sort(&repo, details, &["refname".into(), "-creatordate".into()]).unwrap();
+        let names = sorted.iter().map(|d| d.reference.name.as_str());
+        assert_eq!(
+            names.collect::<Vec<_>>(),
+            ["refs/tags/v1", "refs/heads/main", "refs/heads/topic"]
+        );
+    }
+}
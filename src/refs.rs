@@ -0,0 +1,827 @@
+//! Refs: loose ones, each in its own file under the git directory, and packed ones, all in
+//! `.git/packed-refs`.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    io::{ErrorKind, Write},
+};
+
+use crate::{
+    config::Config,
+    error::{Error, Result},
+    lockfile::{write_locked, LockFile},
+    object::{Object, Signature},
+    repository::Repository,
+    util::{is_directory, is_hash, is_missing, is_valid_ref_name},
+    wildmatch::wildmatch,
+};
+
+/// The hash that stands for "no object": the old value of a ref that's being created, or the new
+/// value of one that's being deleted.
+pub const NULL_HASH: &str = "0000000000000000000000000000000000000000";
+
+/// A ref, and the object it points to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reference {
+    /// Full name of the ref, e.g., `refs/heads/main`
+    pub name: String,
+    /// Hash of the object it points to, after following any symbolic refs
+    pub hash: String,
+    /// The ref it points to, if it's a symbolic ref
+    pub target: Option<String>,
+    /// For an annotated tag, the object it ultimately tags, if that's recorded in `packed-refs`
+    pub peeled: Option<String>,
+}
+
+/// The refs in `.git/packed-refs`, where git moves refs to save having a file for each.
+#[derive(Debug, Default)]
+pub struct PackedRefs {
+    /// The comment at the top, saying how the file was written
+    header: Option<String>,
+    /// Sorted by name
+    refs: Vec<Reference>,
+}
+
+impl PackedRefs {
+    /// Read the repository's packed refs, if there are any.
+    pub fn read(repo: &Repository) -> Result<Self> {
+        match fs::read_to_string(repo.git_dir().join("packed-refs")) {
+            Ok(content) => PackedRefs::parse(&content),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(PackedRefs::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Parse the contents of a `packed-refs` file: a header comment, then a line with the hash
+    /// and name of each ref, each followed by a `^` line with the peeled hash if it's a tag.
+    pub fn parse(content: &str) -> Result<Self> {
+        let corrupt =
+            |line: &str| Error::InvalidRef(format!("unexpected line in packed-refs: {}", line));
+        let mut header = None;
+        let mut refs: Vec<Reference> = Vec::new();
+        for line in content.lines() {
+            if line.starts_with('#') || line.is_empty() {
+                if refs.is_empty() && header.is_none() && line.starts_with('#') {
+                    header = Some(line.to_owned());
+                }
+                continue;
+            }
+            if let Some(peeled) = line.strip_prefix('^') {
+                match refs.last_mut() {
+                    Some(last) if is_hash(peeled) && last.peeled.is_none() => {
+                        last.peeled = Some(peeled.to_owned())
+                    }
+                    _ => return Err(corrupt(line)),
+                }
+                continue;
+            }
+            match line.split_once(' ') {
+                Some((hash, name)) if is_hash(hash) && is_valid_ref_name(name) => {
+                    refs.push(Reference {
+                        name: name.to_owned(),
+                        hash: hash.to_owned(),
+                        target: None,
+                        peeled: None,
+                    })
+                }
+                _ => return Err(corrupt(line)),
+            }
+        }
+
+        refs.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(PackedRefs { header, refs })
+    }
+
+    /// Encode the packed refs in the format of `.git/packed-refs`.
+    pub fn encode(&self) -> String {
+        let mut output = String::new();
+        if let Some(header) = self.header.as_ref() {
+            output.push_str(header);
+            output.push('\n');
+        }
+        for reference in self.refs.iter() {
+            output.push_str(&format!("{} {}\n", reference.hash, reference.name));
+            if let Some(peeled) = reference.peeled.as_ref() {
+                output.push_str(&format!("^{}\n", peeled));
+            }
+        }
+        output
+    }
+
+    /// Find a packed ref by its full name.
+    pub fn find(&self, name: &str) -> Option<&Reference> {
+        self.refs
+            .binary_search_by(|r| r.name.as_str().cmp(name))
+            .ok()
+            .map(|i| &self.refs[i])
+    }
+
+    /// All the packed refs, sorted by name.
+    pub fn refs(&self) -> &[Reference] {
+        &self.refs
+    }
+}
+
+/// An entry in a reflog, recording one update to a ref.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReflogEntry {
+    /// What the ref pointed to before, or all zeros if it didn't exist
+    pub old_hash: String,
+    /// What the ref was changed to point to
+    pub new_hash: String,
+    /// Who changed it, and when
+    pub committer: Signature,
+    /// Why it was changed, e.g., `commit: Fix typo`
+    pub message: String,
+}
+
+/// The contents of a loose ref file.
+enum LooseRef {
+    Hash(String),
+    Symbolic(String),
+}
+
+/// Read a loose ref, or `None` if there's no file for it.
+fn read_loose_ref(repo: &Repository, name: &str) -> Result<Option<LooseRef>> {
+    let content = match fs::read_to_string(repo.git_dir().join(name)) {
+        Ok(content) => content,
+        // A directory (like `refs/heads`), or a path inside a file, isn't a ref either
+        Err(err) if is_missing(&err) || is_directory(&err) => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let content = content.trim_end();
+    match content.strip_prefix("ref: ") {
+        Some(target) if is_valid_ref_name(target) => {
+            Ok(Some(LooseRef::Symbolic(target.to_owned())))
+        }
+        None if is_hash(content) => Ok(Some(LooseRef::Hash(content.to_owned()))),
+        _ => Err(Error::InvalidRef(name.to_owned())),
+    }
+}
+
+/// Whether a ref's name matches a `for-each-ref` pattern: either a prefix ending at a `/`, or
+/// a glob.
+pub fn matches_pattern(name: &str, pattern: &str) -> bool {
+    match name.strip_prefix(pattern) {
+        Some(rest) => rest.is_empty() || pattern.ends_with('/') || rest.starts_with('/'),
+        None => wildmatch(pattern, name, false),
+    }
+}
+
+/// Shorten a ref's name as far as is usual, e.g., `refs/heads/main` to `main`.
+pub fn shorten(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/", "refs/"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
+
+impl Repository {
+    /// Read the hash a ref points to, following symbolic refs, or `None` if it doesn't exist
+    /// (e.g., `HEAD` on an unborn branch).
+    pub fn read_ref(&self, name: &str) -> Result<Option<String>> {
+        let mut name = name.to_owned();
+        let mut packed_refs = None;
+        // Same limit on chains of symbolic refs as git
+        for _ in 0..5 {
+            match read_loose_ref(self, &name)? {
+                Some(LooseRef::Hash(hash)) => return Ok(Some(hash)),
+                Some(LooseRef::Symbolic(target)) => name = target,
+                None => {
+                    // Packed refs can't be symbolic, so this is as far as it goes
+                    let packed_refs = match packed_refs.as_mut() {
+                        Some(packed_refs) => packed_refs,
+                        None => packed_refs.insert(PackedRefs::read(self)?),
+                    };
+                    return Ok(packed_refs.find(&name).map(|r| r.hash.clone()));
+                }
+            }
+        }
+        Err(Error::InvalidRef(name))
+    }
+
+    /// The ref a symbolic ref (like `HEAD`) points to, or `None` if it isn't symbolic (e.g., a
+    /// detached `HEAD`).
+    pub fn symbolic_ref(&self, name: &str) -> Result<Option<String>> {
+        let content = fs::read_to_string(self.git_dir().join(name))?;
+        match content.trim_end().strip_prefix("ref: ") {
+            Some(target) if is_valid_ref_name(target) => Ok(Some(target.to_owned())),
+            Some(_) => Err(Error::InvalidRef(name.to_owned())),
+            None => Ok(None),
+        }
+    }
+
+    /// Point a ref at the given hash, creating it if necessary, without writing to its reflog.
+    /// Symbolic refs are followed, so writing `HEAD` updates the current branch.
+    pub fn write_ref(&self, name: &str, hash: &str) -> Result<()> {
+        let mut transaction = RefTransaction::new(self);
+        transaction.update(name, hash, None);
+        transaction.commit()
+    }
+
+    /// Make `name` (e.g., `HEAD`) a symbolic ref pointing to `target`.
+    pub fn write_symbolic_ref(&self, name: &str, target: &str) -> Result<()> {
+        if name != "HEAD" && !is_valid_ref_name(name) {
+            return Err(Error::InvalidRef(name.to_owned()));
+        }
+        if !is_valid_ref_name(target) {
+            return Err(Error::InvalidRef(target.to_owned()));
+        }
+        write_locked(
+            &self.git_dir().join(name),
+            format!("ref: {}\n", target).as_bytes(),
+        )
+    }
+
+    /// Delete a ref, whether it's loose or packed, returning whether it existed.
+    pub fn delete_ref(&self, name: &str) -> Result<bool> {
+        let existed = self.read_ref(name)?.is_some();
+        let mut transaction = RefTransaction::new(self);
+        transaction.delete(name, None);
+        transaction.commit()?;
+        Ok(existed)
+    }
+
+    /// Record an update to a ref in its reflog, under `.git/logs/`.
+    ///
+    /// Like git, this only starts new logs for `HEAD`, branches, remote-tracking branches and
+    /// notes, and only if `core.logAllRefUpdates` is set (as it is by default in repositories
+    /// with a working tree). Setting it to `always` logs every ref.
+    pub fn append_reflog(
+        &self,
+        name: &str,
+        old_hash: Option<&str>,
+        new_hash: &str,
+        committer: &Signature,
+        message: &str,
+    ) -> Result<()> {
+        let path = self.git_dir().join("logs").join(name);
+        if !path.exists() {
+            let config = Config::load(Some(self))?;
+            let logged_by_default = name == "HEAD"
+                || ["refs/heads/", "refs/remotes/", "refs/notes/"]
+                    .iter()
+                    .any(|prefix| name.starts_with(prefix));
+            let log = match config.get("core.logAllRefUpdates") {
+                Some(value) if value.eq_ignore_ascii_case("always") => true,
+                Some(_) => {
+                    logged_by_default && config.get_bool("core.logAllRefUpdates")? == Some(true)
+                }
+                None => logged_by_default && self.work_tree().is_ok(),
+            };
+            if !log {
+                return Ok(());
+            }
+            fs::create_dir_all(path.parent().unwrap())?;
+        }
+
+        // The message has to fit on the line
+        let message = message.lines().next().unwrap_or("");
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        let mut line = format!("{} {} ", old_hash.unwrap_or(NULL_HASH), new_hash).into_bytes();
+        line.extend(committer.encode());
+        if !message.is_empty() {
+            line.push(b'\t');
+            line.extend(message.as_bytes());
+        }
+        line.push(b'\n');
+        file.write_all(&line)?;
+        Ok(())
+    }
+
+    /// Read a ref's reflog, oldest entry first. Refs without a log have an empty one.
+    pub fn read_reflog(&self, name: &str) -> Result<Vec<ReflogEntry>> {
+        // Names in signatures needn't be UTF-8, so lines are only decoded a field at a time
+        let content = match fs::read(self.git_dir().join("logs").join(name)) {
+            Ok(content) => content,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+        content
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let corrupt = || Error::InvalidRef(format!("corrupt reflog for '{}'", name));
+                let (line, message) = match line.iter().position(|b| *b == b'\t') {
+                    Some(tab) => (&line[..tab], &line[tab + 1..]),
+                    None => (line, &b""[..]),
+                };
+                let mut fields = line.splitn(3, |b| *b == b' ');
+                let mut next_hash = || {
+                    fields
+                        .next()
+                        .and_then(|field| std::str::from_utf8(field).ok())
+                        .filter(|hash| is_hash(hash))
+                        .ok_or_else(corrupt)
+                };
+                let old_hash = next_hash()?;
+                let new_hash = next_hash()?;
+                let committer = fields.next().ok_or_else(corrupt)?;
+                Ok(ReflogEntry {
+                    old_hash: old_hash.to_owned(),
+                    new_hash: new_hash.to_owned(),
+                    committer: Signature::parse(committer)?,
+                    message: String::from_utf8_lossy(message).into_owned(),
+                })
+            })
+            .collect()
+    }
+
+    /// List all refs whose names start with `prefix` (e.g., `refs/tags/`), both loose and
+    /// packed, sorted by name. Symbolic refs are resolved, and any that are broken are skipped.
+    pub fn list_refs(&self, prefix: &str) -> Result<Vec<Reference>> {
+        let mut refs = BTreeMap::new();
+        for packed in PackedRefs::read(self)?.refs {
+            if packed.name.starts_with(prefix) {
+                refs.insert(packed.name.clone(), packed);
+            }
+        }
+
+        // Loose refs take priority over packed ones
+        let mut dirs = vec![match prefix.rfind('/') {
+            Some(slash) => prefix[..slash].to_owned(),
+            None => "refs".to_owned(),
+        }];
+        while let Some(dir) = dirs.pop() {
+            let entries = match fs::read_dir(self.git_dir().join(&dir)) {
+                Ok(entries) => entries,
+                Err(err) if is_missing(&err) => continue,
+                Err(err) => return Err(err.into()),
+            };
+            for entry in entries {
+                let entry = entry?;
+                let name = format!("{}/{}", dir, entry.file_name().to_string_lossy());
+                if entry.file_type()?.is_dir() {
+                    dirs.push(name);
+                    continue;
+                }
+                if !name.starts_with(prefix) || !is_valid_ref_name(&name) {
+                    continue;
+                }
+                let (hash, target) = match read_loose_ref(self, &name) {
+                    Ok(Some(LooseRef::Hash(hash))) => (Some(hash), None),
+                    Ok(Some(LooseRef::Symbolic(target))) => (self.read_ref(&target)?, Some(target)),
+                    Ok(None) | Err(_) => (None, None),
+                };
+                match hash {
+                    Some(hash) => {
+                        let reference = Reference {
+                            name: name.clone(),
+                            hash,
+                            target,
+                            peeled: None,
+                        };
+                        refs.insert(name, reference);
+                    }
+                    None => {
+                        refs.remove(&name);
+                    }
+                }
+            }
+        }
+
+        Ok(refs.into_values().collect())
+    }
+}
+
+/// What a [`RefTransaction`] does to one ref.
+enum RefChange {
+    Update(String),
+    Delete,
+    Verify,
+}
+
+struct RefUpdate {
+    name: String,
+    change: RefChange,
+    /// The value the ref must have for the transaction to go ahead, where [`NULL_HASH`] means
+    /// that it mustn't exist
+    old_hash: Option<String>,
+}
+
+/// A set of changes to refs that are made together, or not at all.
+///
+/// Each ref is locked (by creating `<ref>.lock`) and checked against its expected old value
+/// before any of them are changed, so a transaction fails cleanly if another process is
+/// changing the same refs. Updates are recorded in the refs' reflogs if
+/// [`reflog`](RefTransaction::reflog) is called.
+pub struct RefTransaction<'a> {
+    repo: &'a Repository,
+    updates: Vec<RefUpdate>,
+    no_deref: bool,
+    reflog: Option<(Signature, String)>,
+}
+
+impl<'a> RefTransaction<'a> {
+    /// Start an empty transaction.
+    pub fn new(repo: &'a Repository) -> Self {
+        RefTransaction {
+            repo,
+            updates: Vec::new(),
+            no_deref: false,
+            reflog: None,
+        }
+    }
+
+    /// Change symbolic refs themselves, rather than the refs they point to. By default, updating
+    /// `HEAD` updates the current branch.
+    pub fn no_deref(&mut self, no_deref: bool) {
+        self.no_deref = no_deref;
+    }
+
+    /// Record the changes in the refs' reflogs (and in `HEAD`'s, if the current branch
+    /// changes), with the given committer and message.
+    pub fn reflog(&mut self, committer: Signature, message: &str) {
+        self.reflog = Some((committer, message.to_owned()));
+    }
+
+    /// Point a ref at `new_hash`, if it's currently at `old_hash` (or doesn't exist, if that's
+    /// [`NULL_HASH`]). With no `old_hash`, the ref is updated whatever its current value. As in
+    /// git, a `new_hash` of [`NULL_HASH`] deletes the ref.
+    pub fn update(&mut self, name: &str, new_hash: &str, old_hash: Option<&str>) {
+        let change = match new_hash {
+            NULL_HASH => RefChange::Delete,
+            _ => RefChange::Update(new_hash.to_owned()),
+        };
+        self.push(name, change, old_hash);
+    }
+
+    /// Delete a ref, if it's currently at `old_hash`.
+    pub fn delete(&mut self, name: &str, old_hash: Option<&str>) {
+        self.push(name, RefChange::Delete, old_hash);
+    }
+
+    /// Check that a ref is at `old_hash` without changing it. With no `old_hash`, the ref
+    /// mustn't exist.
+    pub fn verify(&mut self, name: &str, old_hash: Option<&str>) {
+        self.push(name, RefChange::Verify, Some(old_hash.unwrap_or(NULL_HASH)));
+    }
+
+    fn push(&mut self, name: &str, change: RefChange, old_hash: Option<&str>) {
+        self.updates.push(RefUpdate {
+            name: name.to_owned(),
+            change,
+            old_hash: old_hash.map(str::to_owned),
+        });
+    }
+
+    /// Lock and check every ref, then make all the changes.
+    pub fn commit(self) -> Result<()> {
+        let repo = self.repo;
+        let failed = |name: &str, reason: String| {
+            Error::RefUpdateFailed(format!("cannot lock ref '{}': {}", name, reason))
+        };
+
+        let mut names = HashSet::new();
+        for update in self.updates.iter() {
+            if update.name != "HEAD" && !is_valid_ref_name(&update.name) {
+                return Err(Error::InvalidRef(update.name.clone()));
+            }
+            if !names.insert(update.name.as_str()) {
+                return Err(Error::RefUpdateFailed(format!(
+                    "multiple updates for ref '{}' not allowed",
+                    update.name
+                )));
+            }
+        }
+
+        // Take all the locks before changing anything
+        let head_target = repo.symbolic_ref("HEAD")?;
+        let mut locked = Vec::new();
+        for update in self.updates.iter() {
+            let name = match self.no_deref {
+                true => update.name.clone(),
+                false => resolve_symbolic_ref(repo, &update.name)?,
+            };
+            let current = repo.read_ref(&name)?;
+            if let RefChange::Update(new_hash) = &update.change {
+                if current.is_none() {
+                    check_ref_conflicts(repo, &name)?;
+                }
+                check_new_value(repo, &name, new_hash)?;
+            }
+
+            let path = repo.git_dir().join(&name);
+            fs::create_dir_all(path.parent().unwrap())?;
+            let lock = LockFile::acquire(&path)?;
+
+            // The ref might have changed before it was locked, so check it again
+            let current = repo.read_ref(&name)?;
+            match (update.old_hash.as_deref(), current.as_deref()) {
+                (None, _) => (),
+                (Some(NULL_HASH), None) => (),
+                (Some(NULL_HASH), Some(_)) => {
+                    return Err(failed(&name, "reference already exists".to_owned()))
+                }
+                (Some(_), None) => {
+                    let reason = format!("unable to resolve reference '{}'", name);
+                    return Err(failed(&name, reason));
+                }
+                (Some(old_hash), Some(current)) if old_hash != current => {
+                    let reason = format!("is at {} but expected {}", current, old_hash);
+                    return Err(failed(&name, reason));
+                }
+                (Some(_), Some(_)) => (),
+            }
+            locked.push((update, name, current, lock));
+        }
+
+        // Deleted refs have to go from packed-refs too, or they'd reappear
+        let deleted: HashSet<_> = locked
+            .iter()
+            .filter(|(update, ..)| matches!(update.change, RefChange::Delete))
+            .map(|(_, name, ..)| name.as_str())
+            .collect();
+        let packed_refs_path = repo.git_dir().join("packed-refs");
+        let mut packed_refs = PackedRefs::read(repo)?;
+        if packed_refs
+            .refs
+            .iter()
+            .any(|r| deleted.contains(r.name.as_str()))
+        {
+            let mut lock = LockFile::acquire(&packed_refs_path)?;
+            packed_refs = PackedRefs::read(repo)?;
+            packed_refs
+                .refs
+                .retain(|r| !deleted.contains(r.name.as_str()));
+            lock.write_all(packed_refs.encode().as_bytes())?;
+            lock.commit()?;
+        }
+
+        for (update, name, current, mut lock) in locked {
+            let new_hash = match &update.change {
+                RefChange::Update(new_hash) => {
+                    lock.write_all(format!("{}\n", new_hash).as_bytes())?;
+                    lock.commit()?;
+                    new_hash.as_str()
+                }
+                RefChange::Delete => {
+                    match fs::remove_file(repo.git_dir().join(&name)) {
+                        Ok(()) => (),
+                        Err(err) if err.kind() == ErrorKind::NotFound => (),
+                        Err(err) => return Err(err.into()),
+                    }
+                    drop(lock);
+                    match fs::remove_file(repo.git_dir().join("logs").join(&name)) {
+                        Ok(()) => (),
+                        Err(err) if err.kind() == ErrorKind::NotFound => (),
+                        Err(err) => return Err(err.into()),
+                    }
+                    remove_empty_dirs(repo, &name);
+                    NULL_HASH
+                }
+                RefChange::Verify => continue,
+            };
+
+            if let Some((committer, message)) = self.reflog.as_ref() {
+                let old_hash = current.as_deref();
+                if new_hash != NULL_HASH {
+                    repo.append_reflog(&name, old_hash, new_hash, committer, message)?;
+                }
+                if update.name != name {
+                    repo.append_reflog(&update.name, old_hash, new_hash, committer, message)?;
+                }
+                if update.name != "HEAD" && head_target.as_deref() == Some(name.as_str()) {
+                    repo.append_reflog("HEAD", old_hash, new_hash, committer, message)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Follow a chain of symbolic refs to the name of the ref at the end, which may not exist yet.
+fn resolve_symbolic_ref(repo: &Repository, name: &str) -> Result<String> {
+    let mut name = name.to_owned();
+    for _ in 0..5 {
+        match read_loose_ref(repo, &name)? {
+            Some(LooseRef::Symbolic(target)) => name = target,
+            _ => return Ok(name),
+        }
+    }
+    Err(Error::InvalidRef(name))
+}
+
+/// Check that a ref called `name` could be created, before doing anything that depends on it: the
+/// name has to be valid, and there mustn't be refs in the way (see [`RefTransaction::commit`]).
+pub fn check_new_ref(repo: &Repository, name: &str) -> Result<()> {
+    if !is_valid_ref_name(name) {
+        return Err(Error::InvalidRef(name.to_owned()));
+    }
+    check_ref_conflicts(repo, name)
+}
+
+/// Check that a new ref wouldn't need a directory where there's already a ref, or the other way
+/// around, like `refs/heads/a` and `refs/heads/a/b`.
+fn check_ref_conflicts(repo: &Repository, name: &str) -> Result<()> {
+    let conflict = |existing: &str| {
+        Error::RefUpdateFailed(format!(
+            "cannot lock ref '{}': '{}' exists; cannot create '{}'",
+            name, existing, name
+        ))
+    };
+    for (slash, _) in name.match_indices('/').skip(1) {
+        if repo.read_ref(&name[..slash])?.is_some() {
+            return Err(conflict(&name[..slash]));
+        }
+    }
+    match repo.list_refs(&format!("{}/", name))?.first() {
+        Some(existing) => Err(conflict(&existing.name)),
+        None => Ok(()),
+    }
+}
+
+/// Check that a ref can point to an object: it has to exist, and branches have to be commits.
+fn check_new_value(repo: &Repository, name: &str, new_hash: &str) -> Result<()> {
+    let failed = |reason: String| {
+        Error::RefUpdateFailed(format!("cannot update ref '{}': {}", name, reason))
+    };
+    if !is_hash(new_hash) || !repo.has_object(new_hash)? {
+        return Err(failed(format!(
+            "trying to write ref '{}' with nonexistent object {}",
+            name, new_hash
+        )));
+    }
+    if name.starts_with("refs/heads/") {
+        let object = Object::parse_from_hash(repo, new_hash)?;
+        if !matches!(object, Object::Commit(_)) {
+            return Err(failed(format!(
+                "trying to write non-commit object {} to branch '{}'",
+                new_hash, name
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Remove the directories a deleted ref (and its reflog) were in, if they're now empty, but not
+/// the top-level ones like `refs/heads`.
+fn remove_empty_dirs(repo: &Repository, name: &str) {
+    for base in [repo.git_dir().to_owned(), repo.git_dir().join("logs")] {
+        let mut dir = name;
+        while let Some(slash) = dir.rfind('/') {
+            dir = &dir[..slash];
+            if dir.matches('/').count() < 2 || fs::remove_dir(base.join(dir)).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{matches_pattern, shorten, PackedRefs, RefTransaction, NULL_HASH};
+    use crate::{
+        error::Error,
+        object::Object,
+        test_util::{signature, TempRepo},
+    };
+
+    #[test]
+    fn test_parse_packed_refs() {
+        let tag = "3f8c7e1a1f4a1c2b5e0d9a8b7c6d5e4f3a2b1c0d";
+        let commit = "0123456789abcdef0123456789abcdef01234567";
+        let content = format!(
+            "# pack-refs with: peeled fully-peeled sorted \n\
+             {commit} refs/heads/main\n\
+             {tag} refs/tags/v1\n\
+             ^{commit}\n\
+             {commit} refs/heads/feature\n"
+        );
+        let packed = PackedRefs::parse(&content).unwrap();
+        let names: Vec<_> = packed.refs().iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(
+            names,
+            ["refs/heads/feature", "refs/heads/main", "refs/tags/v1"]
+        );
+
+        let v1 = packed.find("refs/tags/v1").unwrap();
+        assert_eq!(v1.hash, tag);
+        assert_eq!(v1.peeled.as_deref(), Some(commit));
+        assert_eq!(packed.find("refs/heads/main").unwrap().peeled, None);
+        assert!(packed.find("refs/heads/missing").is_none());
+
+        assert!(PackedRefs::parse(&format!("^{commit}\n")).is_err());
+        assert!(PackedRefs::parse(&format!("{commit} refs/heads/a..b\n")).is_err());
+        assert!(PackedRefs::parse("nonsense\n").is_err());
+    }
+
+    #[test]
+    fn test_matches_pattern() {
+        assert!(matches_pattern("refs/heads/main", "refs/heads"));
+        assert!(matches_pattern("refs/heads/main", "refs/heads/"));
+        assert!(matches_pattern("refs/heads/main", "refs/heads/main"));
+        assert!(!matches_pattern("refs/heads/main", "refs/heads/ma"));
+        assert!(!matches_pattern("refs/heads/main", "refs/tags"));
+        assert!(matches_pattern("refs/tags/v1.0", "refs/tags/v*"));
+        assert!(matches_pattern(
+            "refs/remotes/origin/main",
+            "refs/*/origin/*"
+        ));
+        assert!(!matches_pattern("refs/heads/main", "refs/*/v*"));
+    }
+
+    #[test]
+    fn test_shorten() {
+        assert_eq!(shorten("refs/heads/main"), "main");
+        assert_eq!(shorten("refs/tags/v1"), "v1");
+        assert_eq!(shorten("refs/remotes/origin/main"), "origin/main");
+        assert_eq!(shorten("refs/notes/commits"), "notes/commits");
+        assert_eq!(shorten("HEAD"), "HEAD");
+    }
+
+    #[test]
+    fn test_transaction() {
+        let repo = TempRepo::new("refs");
+        let git_dir = repo.git_dir();
+        let tree = Object::Tree(Vec::new());
+        tree.add(&repo).unwrap();
+        let commit = |message| {
+            let object = Object::new_commit(&tree.hash(), &[], signature(0), signature(0), message);
+            object.add(&repo).unwrap();
+            object.hash()
+        };
+        let (first, second) = (commit("first"), commit("second"));
+
+        // Updating HEAD creates the branch it points to, and logs to both
+        let mut transaction = RefTransaction::new(&repo);
+        transaction.update("HEAD", &first, Some(NULL_HASH));
+        transaction.update("refs/heads/other", &first, None);
+        transaction.reflog(signature(0), "create");
+        transaction.commit().unwrap();
+        assert_eq!(
+            repo.read_ref("refs/heads/master").unwrap(),
+            Some(first.clone())
+        );
+        assert_eq!(repo.read_reflog("HEAD").unwrap().len(), 1);
+        let log = repo.read_reflog("refs/heads/master").unwrap();
+        assert_eq!(log[0].old_hash, NULL_HASH);
+        assert_eq!(log[0].new_hash, first);
+        assert_eq!(log[0].message, "create");
+
+        // Nothing changes if any check fails
+        let mut transaction = RefTransaction::new(&repo);
+        transaction.update("refs/heads/other", &second, None);
+        transaction.update("refs/heads/master", &second, Some(&second));
+        assert!(matches!(
+            transaction.commit(),
+            Err(Error::RefUpdateFailed(_))
+        ));
+        let mut transaction = RefTransaction::new(&repo);
+        transaction.update("refs/heads/other", &second, None);
+        transaction.verify("refs/heads/missing", Some(&first));
+        assert!(matches!(
+            transaction.commit(),
+            Err(Error::RefUpdateFailed(_))
+        ));
+        let mut transaction = RefTransaction::new(&repo);
+        transaction.update("refs/heads/master/sub", &second, None);
+        assert!(matches!(
+            transaction.commit(),
+            Err(Error::RefUpdateFailed(_))
+        ));
+        let mut transaction = RefTransaction::new(&repo);
+        transaction.update("refs/heads/blob", &tree.hash(), None);
+        assert!(matches!(
+            transaction.commit(),
+            Err(Error::RefUpdateFailed(_))
+        ));
+        assert_eq!(
+            repo.read_ref("refs/heads/other").unwrap(),
+            Some(first.clone())
+        );
+        assert!(!git_dir.join("refs/heads/other.lock").exists());
+
+        // Deleting a ref removes it from packed-refs as well
+        std::fs::write(
+            git_dir.join("packed-refs"),
+            format!(
+                "# pack-refs with: peeled fully-peeled sorted \n{} refs/tags/v1\n",
+                second
+            ),
+        )
+        .unwrap();
+        let mut transaction = RefTransaction::new(&repo);
+        transaction.verify("refs/heads/other", Some(&first));
+        transaction.delete("refs/tags/v1", Some(&second));
+        transaction.delete("refs/heads/master", None);
+        transaction.commit().unwrap();
+        assert_eq!(repo.read_ref("refs/tags/v1").unwrap(), None);
+        assert_eq!(repo.read_ref("HEAD").unwrap(), None);
+        assert_eq!(repo.read_reflog("refs/heads/master").unwrap(), Vec::new());
+        let packed = std::fs::read_to_string(git_dir.join("packed-refs")).unwrap();
+        assert_eq!(packed, "# pack-refs with: peeled fully-peeled sorted \n");
+
+        // Updating a ref to the null hash deletes it, as in git
+        let mut transaction = RefTransaction::new(&repo);
+        transaction.update("refs/heads/other", NULL_HASH, Some(&first));
+        transaction.commit().unwrap();
+        assert_eq!(repo.read_ref("refs/heads/other").unwrap(), None);
+        assert!(!git_dir.join("refs/heads/other").exists());
+    }
+}
@@ -0,0 +1,197 @@
+//! Finding and opening repositories.
+
+use std::{
+    cell::{RefCell, RefMut},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    error::{Error, Result},
+    pack_store::PackStore,
+    util::is_hash,
+};
+
+/// A git repository, i.e., a git directory and (unless it is bare) a working tree.
+pub struct Repository {
+    git_dir: PathBuf,
+    work_tree: Option<PathBuf>,
+    /// Opened lazily, the first time an object isn't found loose
+    pack_store: RefCell<Option<PackStore>>,
+}
+
+impl Repository {
+    /// Open the repository with the given git directory and working tree.
+    pub fn open<P>(git_dir: P, work_tree: Option<PathBuf>) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        Repository {
+            git_dir: git_dir.into(),
+            work_tree,
+            pack_store: RefCell::new(None),
+        }
+    }
+
+    /// Find the repository containing the current directory.
+    ///
+    /// `GIT_DIR` and `GIT_WORK_TREE` are honoured if set, otherwise each directory from the
+    /// current directory up to the root is checked for a `.git` directory (or `gitdir:` file),
+    /// or for being a bare git directory itself.
+    pub fn discover() -> Result<Self> {
+        let current_dir = std::env::current_dir()?;
+        let env_work_tree = std::env::var_os("GIT_WORK_TREE").map(|p| current_dir.join(p));
+
+        if let Some(git_dir) = std::env::var_os("GIT_DIR") {
+            let git_dir = current_dir.join(git_dir);
+            if !is_git_dir(&git_dir) {
+                return Err(Error::NotARepository(git_dir));
+            }
+            let work_tree = env_work_tree.unwrap_or(current_dir);
+            return Ok(Repository::open(git_dir, Some(work_tree)));
+        }
+
+        for dir in current_dir.ancestors() {
+            let dot_git = dir.join(".git");
+            if dot_git.is_file() {
+                let git_dir = read_git_file(&dot_git)?;
+                let work_tree = env_work_tree.unwrap_or_else(|| dir.to_owned());
+                return Ok(Repository::open(git_dir, Some(work_tree)));
+            } else if is_git_dir(&dot_git) {
+                let work_tree = env_work_tree.unwrap_or_else(|| dir.to_owned());
+                return Ok(Repository::open(dot_git, Some(work_tree)));
+            } else if is_git_dir(dir) {
+                return Ok(Repository::open(dir, env_work_tree));
+            }
+        }
+
+        Err(Error::NotARepository(current_dir))
+    }
+
+    /// Open the repository whose working tree is `path`, if it has a `.git` directory (or
+    /// `gitdir:` file) of its own, as nested repositories do.
+    pub fn open_work_tree(path: &Path) -> Result<Option<Self>> {
+        let dot_git = path.join(".git");
+        let git_dir = if dot_git.is_file() {
+            read_git_file(&dot_git)?
+        } else if is_git_dir(&dot_git) {
+            dot_git
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(Repository::open(git_dir, Some(path.to_owned()))))
+    }
+
+    /// Create a new repository with its working tree at `path`, or reinitialise an existing one.
+    pub fn init<P>(path: P) -> Result<Self>
+    where
+        P: Into<PathBuf>,
+    {
+        let work_tree: PathBuf = path.into();
+        let git_dir = work_tree.join(".git");
+
+        fs::create_dir_all(git_dir.join("objects"))?;
+        fs::create_dir_all(git_dir.join("refs/heads"))?;
+        fs::create_dir_all(git_dir.join("refs/tags"))?;
+        let head_path = git_dir.join("HEAD");
+        if !head_path.exists() {
+            fs::write(head_path, "ref: refs/heads/master\n")?;
+        }
+        let config_path = git_dir.join("config");
+        if !config_path.exists() {
+            fs::write(
+                config_path,
+                "[core]\n\trepositoryformatversion = 0\n\tfilemode = true\n\tbare = false\n\
+\tlogallrefupdates = true\n",
+            )?;
+        }
+
+        Ok(Repository::open(git_dir, Some(work_tree)))
+    }
+
+    /// The git directory, usually `.git` in the root of the working tree.
+    pub fn git_dir(&self) -> &Path {
+        &self.git_dir
+    }
+
+    /// The root of the working tree, which bare repositories don't have.
+    pub fn work_tree(&self) -> Result<&Path> {
+        match self.work_tree.as_ref() {
+            Some(work_tree) => Ok(work_tree),
+            None => Err(Error::BareRepository),
+        }
+    }
+
+    /// The directory containing the object store.
+    pub fn objects_dir(&self) -> PathBuf {
+        self.git_dir.join("objects")
+    }
+
+    /// The path of the loose object with the given hash.
+    pub fn object_path(&self, hash: &str) -> PathBuf {
+        let mut path = self.objects_dir();
+        path.push(&hash[0..2]);
+        path.push(&hash[2..40]);
+        path
+    }
+
+    /// Whether the store contains an object, either loose or packed.
+    pub fn has_object(&self, hash: &str) -> Result<bool> {
+        Ok(self.object_path(hash).exists() || self.pack_store()?.contains(hash))
+    }
+
+    /// The hashes of all objects, loose or packed, that start with the given prefix of at least
+    /// two hex digits, sorted.
+    pub fn find_objects(&self, prefix: &str) -> Result<Vec<String>> {
+        let prefix = prefix.to_ascii_lowercase();
+        let mut hashes = self.pack_store()?.hashes_with_prefix(&prefix);
+        let dir = self.objects_dir().join(&prefix[0..2]);
+        match fs::read_dir(dir) {
+            Ok(entries) => {
+                for entry in entries {
+                    let hash = format!("{}{}", &prefix[0..2], entry?.file_name().to_string_lossy());
+                    if is_hash(&hash) && hash.starts_with(&prefix) {
+                        hashes.push(hash);
+                    }
+                }
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+            Err(err) => return Err(err.into()),
+        }
+        hashes.sort();
+        hashes.dedup();
+        Ok(hashes)
+    }
+
+    /// The path of the index file, which `GIT_INDEX_FILE` can override.
+    pub fn index_path(&self) -> PathBuf {
+        match std::env::var_os("GIT_INDEX_FILE") {
+            Some(path) => PathBuf::from(path),
+            None => self.git_dir.join("index"),
+        }
+    }
+
+    /// The store of all of this repository's packs.
+    pub(crate) fn pack_store(&self) -> Result<RefMut<'_, PackStore>> {
+        let mut pack_store = self.pack_store.borrow_mut();
+        if pack_store.is_none() {
+            *pack_store = Some(PackStore::open(&self.objects_dir())?);
+        }
+        Ok(RefMut::map(pack_store, |p| p.as_mut().unwrap()))
+    }
+}
+
+/// Whether the given directory looks like a git directory.
+fn is_git_dir(path: &Path) -> bool {
+    path.join("HEAD").is_file() && path.join("objects").is_dir() && path.join("refs").is_dir()
+}
+
+/// Read the git directory from a `.git` file, as used by worktrees and submodules.
+fn read_git_file(path: &Path) -> Result<PathBuf> {
+    let content = fs::read_to_string(path)?;
+    let git_dir = match content.trim_end().strip_prefix("gitdir: ") {
+        Some(git_dir) => git_dir,
+        None => return Err(Error::NotARepository(path.to_owned())),
+    };
+    Ok(path.parent().unwrap().join(git_dir))
+}
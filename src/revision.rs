@@ -0,0 +1,429 @@
+//! Parsing revisions, git's syntax for naming objects: `main~2`, `v1.0^{tree}`,
+//! `HEAD:src/main.rs`, `@{upstream}` and so on (see `gitrevisions(7)`).
+
+use crate::{
+    config::Config,
+    error::{Error, Result},
+    index::Index,
+    object::Object,
+    refs::NULL_HASH,
+    repository::Repository,
+    util::{is_hash, is_valid_ref_name},
+};
+
+/// The shortest abbreviated hash that is looked up
+const MIN_ABBREV: usize = 4;
+/// How long abbreviated hashes are by default, if that's enough for them to be unique
+pub const DEFAULT_ABBREV: usize = 7;
+
+/// Find the hash of the object a revision names.
+pub fn resolve(repo: &Repository, revision: &str) -> Result<String> {
+    let found = if let Some(path) = revision.strip_prefix(':') {
+        resolve_index_path(repo, path)?
+    } else if let Some(colon) = find_path_separator(revision) {
+        let tree = peel(repo, &resolve(repo, &revision[..colon])?, "tree")?;
+        resolve_tree_path(repo, &tree, &revision[colon + 1..])?
+    } else {
+        resolve_suffixes(repo, revision)?
+    };
+    found.ok_or_else(|| Error::UnknownRevision(revision.to_owned()))
+}
+
+/// Find the commit a revision names, peeling any tags.
+pub fn resolve_commit(repo: &Repository, revision: &str) -> Result<String> {
+    peel(repo, &resolve(repo, revision)?, "commit")
+}
+
+/// Follow an object to one of the given type, as in `rev^{type}`: tags are followed to the
+/// object they tag, and commits to their tree. The type `object` accepts any object, and an
+/// empty type follows tags to whatever isn't a tag.
+pub fn peel(repo: &Repository, hash: &str, object_type: &str) -> Result<String> {
+    let expected = match object_type {
+        "" | "object" => "",
+        "commit" => "commit",
+        "tree" => "tree",
+        "blob" => "blob",
+        "tag" => "tag",
+        _ => {
+            return Err(Error::UnknownRevision(format!(
+                "{}^{{{}}}",
+                hash, object_type
+            )))
+        }
+    };
+
+    let mut hash = hash.to_owned();
+    loop {
+        let object = Object::parse_from_hash(repo, &hash)?;
+        if object.type_name() == expected || object_type == "object" {
+            return Ok(hash);
+        }
+        match object {
+            Object::Tag(tag) => hash = tag.object_hash,
+            Object::Commit(commit) if expected == "tree" => return Ok(commit.tree_hash),
+            _ if expected.is_empty() => return Ok(hash),
+            object => {
+                return Err(Error::WrongObjectType {
+                    hash,
+                    expected,
+                    actual: object.type_name(),
+                })
+            }
+        }
+    }
+}
+
+/// Find the full name of the ref a short name refers to, trying the same places in the same
+/// order as git: `name`, `refs/name`, `refs/tags/name`, `refs/heads/name`,
+/// `refs/remotes/name` and `refs/remotes/name/HEAD`.
+pub fn dwim_ref(repo: &Repository, name: &str) -> Result<Option<String>> {
+    // Only names like `HEAD` and `FETCH_HEAD` are looked for directly in the git directory
+    let is_pseudo_ref =
+        !name.is_empty() && name.bytes().all(|b| b.is_ascii_uppercase() || b == b'_');
+    let candidates = [
+        name.to_owned(),
+        format!("refs/{}", name),
+        format!("refs/tags/{}", name),
+        format!("refs/heads/{}", name),
+        format!("refs/remotes/{}", name),
+        format!("refs/remotes/{}/HEAD", name),
+    ];
+    for (i, candidate) in candidates.into_iter().enumerate() {
+        if !(is_valid_ref_name(&candidate) || (i == 0 && is_pseudo_ref)) {
+            continue;
+        }
+        match repo.read_ref(&candidate) {
+            Ok(Some(_)) => return Ok(Some(candidate)),
+            Ok(None) => (),
+            // e.g., `refs/heads/a/b` when `refs/heads/a` is a file
+            Err(Error::Io(_)) => (),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(None)
+}
+
+/// Abbreviate a hash to the shortest prefix of at least `min_len` digits that no other object's
+/// hash starts with.
+pub fn abbreviate(repo: &Repository, hash: &str, min_len: usize) -> Result<String> {
+    let min_len = min_len.clamp(MIN_ABBREV, hash.len());
+    let len = repo
+        .find_objects(&hash[..min_len])?
+        .iter()
+        .filter(|other| *other != hash)
+        .map(|other| {
+            other
+                .bytes()
+                .zip(hash.bytes())
+                .take_while(|(a, b)| a == b)
+                .count()
+                + 1
+        })
+        .fold(min_len, usize::max);
+    Ok(hash[..len.min(hash.len())].to_owned())
+}
+
+/// Resolve a revision that may end with `^{type}`, `^n` or `~n`.
+fn resolve_suffixes(repo: &Repository, revision: &str) -> Result<Option<String>> {
+    if revision.ends_with('}') {
+        if let Some(start) = revision.rfind("^{") {
+            let object_type = &revision[start + 2..revision.len() - 1];
+            return match resolve_suffixes(repo, &revision[..start])? {
+                Some(hash) => peel(repo, &hash, object_type).map(Some),
+                None => Ok(None),
+            };
+        }
+    }
+
+    let digits_start = revision
+        .trim_end_matches(|c: char| c.is_ascii_digit())
+        .len();
+    let operator = revision[..digits_start].bytes().last();
+    if let Some(operator @ (b'^' | b'~')) = operator {
+        let digits = &revision[digits_start..];
+        let n = match digits {
+            "" => 1,
+            _ => match digits.parse::<usize>() {
+                Ok(n) => n,
+                Err(_) => return Ok(None),
+            },
+        };
+        let hash = match resolve_suffixes(repo, &revision[..digits_start - 1])? {
+            Some(hash) => peel(repo, &hash, "commit")?,
+            None => return Ok(None),
+        };
+
+        if operator == b'^' {
+            // `^0` is the commit itself, and `^n` its nth parent
+            if n == 0 {
+                return Ok(Some(hash));
+            }
+            let commit = Object::read_commit(repo, &hash)?;
+            return Ok(commit.parent_hashes.get(n - 1).cloned());
+        }
+        // `~n` is the nth generation ancestor, following first parents
+        let mut hash = hash;
+        for _ in 0..n {
+            let commit = Object::read_commit(repo, &hash)?;
+            match commit.parent_hashes.into_iter().next() {
+                Some(parent) => hash = parent,
+                None => return Ok(None),
+            }
+        }
+        return Ok(Some(hash));
+    }
+
+    resolve_name(repo, revision)
+}
+
+/// Resolve a hash (perhaps abbreviated), a ref name, or a ref with an `@{...}` selector.
+fn resolve_name(repo: &Repository, name: &str) -> Result<Option<String>> {
+    if name.len() == 40 && is_hash(&name.to_ascii_lowercase()) {
+        return Ok(Some(name.to_ascii_lowercase()));
+    }
+    if name == "@" {
+        return repo.read_ref("HEAD");
+    }
+    if name.ends_with('}') {
+        if let Some(start) = name.rfind("@{") {
+            return resolve_selector(repo, &name[..start], &name[start + 2..name.len() - 1]);
+        }
+    }
+
+    // Refs take priority over abbreviated hashes, as in git
+    if let Some(ref_name) = dwim_ref(repo, name)? {
+        return repo.read_ref(&ref_name);
+    }
+    if name.len() >= MIN_ABBREV && name.len() < 40 && name.bytes().all(|b| b.is_ascii_hexdigit()) {
+        let mut hashes = repo.find_objects(name)?;
+        return match hashes.len() {
+            0 => Ok(None),
+            1 => Ok(hashes.pop()),
+            _ => Err(Error::AmbiguousObject(name.to_owned())),
+        };
+    }
+    Ok(None)
+}
+
+/// Resolve `name@{selector}`, where the selector is `upstream` (or `u`) or a number of reflog
+/// entries back. An empty name means the current branch.
+fn resolve_selector(repo: &Repository, name: &str, selector: &str) -> Result<Option<String>> {
+    if selector.eq_ignore_ascii_case("upstream") || selector.eq_ignore_ascii_case("u") {
+        let branch = match name {
+            "" | "HEAD" => match repo.symbolic_ref("HEAD")? {
+                Some(ref_name) => ref_name,
+                None => return Ok(None),
+            },
+            _ => name.to_owned(),
+        };
+        return match upstream(repo, &branch)? {
+            Some(upstream) => repo.read_ref(&upstream),
+            None => Ok(None),
+        };
+    }
+    let n = match selector.parse::<usize>() {
+        Ok(n) => n,
+        // Dates and previous branches (`@{-1}`) aren't supported
+        Err(_) => return Ok(None),
+    };
+
+    let ref_name = match name {
+        "" => repo
+            .symbolic_ref("HEAD")?
+            .unwrap_or_else(|| "HEAD".to_owned()),
+        _ => match dwim_ref(repo, name)? {
+            Some(ref_name) => ref_name,
+            None => return Ok(None),
+        },
+    };
+    let entries = repo.read_reflog(&ref_name)?;
+    if n < entries.len() {
+        Ok(Some(entries[entries.len() - 1 - n].new_hash.clone()))
+    } else if n == entries.len() && n > 0 && entries[0].old_hash != NULL_HASH {
+        // One further back than the log goes is what the oldest entry changed from
+        Ok(Some(entries[0].old_hash.clone()))
+    } else {
+        Ok(None)
+    }
+}
+
+/// The ref a branch (e.g., `refs/heads/main`) is set to track, with `branch.<name>.remote` and
+/// `branch.<name>.merge`, mapped to its remote-tracking branch by the remote's fetch refspecs.
+/// The ref might not exist, if the upstream branch is gone.
+pub fn upstream(repo: &Repository, branch: &str) -> Result<Option<String>> {
+    let branch = branch.strip_prefix("refs/heads/").unwrap_or(branch);
+
+    let config = Config::load(Some(repo))?;
+    let remote = config.get(&format!("branch.{}.remote", branch));
+    let merge = config.get(&format!("branch.{}.merge", branch));
+    let (remote, merge) = match (remote, merge) {
+        (Some(remote), Some(merge)) => (remote, merge),
+        _ => return Ok(None),
+    };
+    // A remote of `.` means the upstream is a local branch
+    if remote == "." {
+        return Ok(Some(merge.to_owned()));
+    }
+
+    for refspec in config.get_all(&format!("remote.{}.fetch", remote)) {
+        let refspec = refspec.strip_prefix('+').unwrap_or(refspec);
+        let (source, destination) = match refspec.split_once(':') {
+            Some(sides) => sides,
+            None => continue,
+        };
+        let tracking = match source.split_once('*') {
+            Some((prefix, suffix)) => merge
+                .strip_prefix(prefix)
+                .and_then(|rest| rest.strip_suffix(suffix))
+                .map(|matched| destination.replacen('*', matched, 1)),
+            None if source == merge => Some(destination.to_owned()),
+            None => None,
+        };
+        if tracking.is_some() {
+            return Ok(tracking);
+        }
+    }
+    Ok(None)
+}
+
+/// Find the colon separating a revision from a path in `rev:path`, ignoring any inside braces
+/// (as in `@{...}` or `^{...}`).
+fn find_path_separator(revision: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in revision.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ':' if depth == 0 => return Some(i),
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Resolve `:path` or `:n:path`, a file staged in the index (at stage `n`, by default 0).
+fn resolve_index_path(repo: &Repository, path: &str) -> Result<Option<String>> {
+    let (stage, path) = match path.as_bytes() {
+        [stage @ b'0'..=b'3', b':', ..] => (stage - b'0', &path[2..]),
+        _ => (0, path),
+    };
+    let index = Index::read(repo)?;
+    Ok(index.find(path, stage).map(|entry| entry.hash.clone()))
+}
+
+/// Resolve `rev:path`, a file or directory in a tree.
+fn resolve_tree_path(repo: &Repository, tree_hash: &str, path: &str) -> Result<Option<String>> {
+    let mut hash = tree_hash.to_owned();
+    let mut is_tree = true;
+    for name in path
+        .split('/')
+        .filter(|name| !name.is_empty() && *name != ".")
+    {
+        if !is_tree {
+            return Ok(None);
+        }
+        let entries = Object::read_tree(repo, &hash)?;
+        match entries.into_iter().find(|entry| entry.name == name) {
+            Some(entry) => {
+                is_tree = entry.is_tree();
+                hash = entry.hash;
+            }
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{abbreviate, find_path_separator, resolve};
+    use crate::{
+        error::Error,
+        object::{Object, TreeEntry},
+        test_util::{signature, TempRepo},
+    };
+
+    #[test]
+    fn test_resolve() {
+        let repo = TempRepo::new("revision");
+        let add = |object: Object| {
+            object.add(&repo).unwrap();
+            object.hash()
+        };
+
+        let blob = add(Object::Blob(b"hello\n".to_vec()));
+        let subtree = add(Object::Tree(vec![TreeEntry {
+            mode: "100644".to_owned(),
+            name: "file".to_owned(),
+            hash: blob.clone(),
+        }]));
+        let tree = add(Object::Tree(vec![TreeEntry {
+            mode: "40000".to_owned(),
+            name: "dir".to_owned(),
+            hash: subtree.clone(),
+        }]));
+        let commit = |parents: &[&str]| {
+            add(Object::new_commit(
+                &tree,
+                parents,
+                signature(0),
+                signature(0),
+                "m",
+            ))
+        };
+        let root = commit(&[]);
+        let side = commit(&[]);
+        let merge = commit(&[&root, &side]);
+        let tag = add(Object::new_tag(&merge, "commit", "v1", signature(0), "t"));
+        repo.write_ref("refs/heads/main", &merge).unwrap();
+        repo.write_ref("refs/tags/v1", &tag).unwrap();
+        std::fs::write(repo.git_dir().join("HEAD"), "ref: refs/heads/main\n").unwrap();
+
+        let resolve = |revision| resolve(&repo, revision).unwrap();
+        assert_eq!(resolve("HEAD"), merge);
+        assert_eq!(resolve("@"), merge);
+        assert_eq!(resolve("main"), merge);
+        assert_eq!(resolve("heads/main"), merge);
+        assert_eq!(resolve("v1"), tag);
+        assert_eq!(resolve("v1^{}"), merge);
+        assert_eq!(resolve("v1^{tree}"), tree);
+        assert_eq!(resolve("HEAD^"), root);
+        assert_eq!(resolve("HEAD^2"), side);
+        assert_eq!(resolve("v1~1"), root);
+        assert_eq!(resolve("HEAD^0"), merge);
+        assert_eq!(resolve("main:dir"), subtree);
+        assert_eq!(resolve("main:dir/file"), blob);
+        assert_eq!(resolve("HEAD:"), tree);
+        assert_eq!(resolve(&merge[..8]), merge);
+        assert_eq!(resolve(&merge.to_uppercase()), merge);
+
+        for unknown in [
+            "nope",
+            "HEAD~2",
+            "HEAD^3",
+            "HEAD:nope",
+            "HEAD:dir/file/x",
+            "HEAD@{1}",
+        ] {
+            assert!(matches!(
+                super::resolve(&repo, unknown),
+                Err(Error::UnknownRevision(revision)) if revision == unknown
+            ));
+        }
+        assert!(matches!(
+            super::resolve(&repo, "HEAD^{blob}"),
+            Err(Error::WrongObjectType { .. })
+        ));
+
+        assert_eq!(abbreviate(&repo, &merge, 7).unwrap(), merge[..7]);
+        assert_eq!(abbreviate(&repo, &merge, 50).unwrap(), merge);
+    }
+
+    #[test]
+    fn test_find_path_separator() {
+        assert_eq!(find_path_separator("HEAD:src/main.rs"), Some(4));
+        assert_eq!(find_path_separator("HEAD@{1}:a"), Some(8));
+        assert_eq!(find_path_separator("HEAD^{/fix: typo}"), None);
+        assert_eq!(find_path_separator("main~2"), None);
+    }
+}
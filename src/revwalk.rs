@@ -0,0 +1,268 @@
+//! Walking the history of commits, newest first, as `git log` does.
+
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap},
+};
+
+use crate::{
+    error::Result,
+    object::{Commit, Object},
+    repository::Repository,
+};
+
+/// How many more commits to look at once only uninteresting ones are left to walk, in case clock
+/// skew has put an interesting commit after some of them (the same allowance as git's).
+const SLOP: usize = 5;
+
+/// Walks commits reachable from some starting points but not from others, in order of commit
+/// date (newest first).
+///
+/// ```no_run
+/// use git_starter_rust::{Repository, RevWalk};
+///
+/// let repo = Repository::discover()?;
+/// let mut walk = RevWalk::new(&repo);
+/// walk.push(&repo.read_ref("HEAD")?.unwrap())?;
+/// for commit in walk {
+///     let (hash, commit) = commit?;
+///     println!("{} {}", hash, String::from_utf8_lossy(&commit.message));
+/// }
+/// # Ok::<(), git_starter_rust::Error>(())
+/// ```
+pub struct RevWalk<'a> {
+    repo: &'a Repository,
+    first_parent: bool,
+    /// Every commit that has been queued, with whether it is reachable from a hidden commit
+    commits: HashMap<String, (Commit, bool)>,
+    queue: BinaryHeap<QueueEntry>,
+    /// Count of commits queued so far, which breaks ties between commits with the same date
+    queued: usize,
+    has_hidden: bool,
+    /// When there are hidden commits, the commits to output, found before anything is output
+    limited: Option<std::vec::IntoIter<String>>,
+}
+
+/// A commit waiting to be walked, ordered so the newest is popped first.
+#[derive(PartialEq, Eq)]
+struct QueueEntry {
+    time: i64,
+    order: usize,
+    hash: String,
+}
+
+impl Ord for QueueEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.time
+            .cmp(&other.time)
+            .then_with(|| other.order.cmp(&self.order))
+    }
+}
+
+impl PartialOrd for QueueEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a> RevWalk<'a> {
+    /// Start a walk with no commits.
+    pub fn new(repo: &'a Repository) -> Self {
+        RevWalk {
+            repo,
+            first_parent: false,
+            commits: HashMap::new(),
+            queue: BinaryHeap::new(),
+            queued: 0,
+            has_hidden: false,
+            limited: None,
+        }
+    }
+
+    /// Follow only the first parent of merge commits.
+    pub fn first_parent(&mut self, first_parent: bool) {
+        self.first_parent = first_parent;
+    }
+
+    /// Walk the commit with the given hash and its ancestors.
+    pub fn push(&mut self, hash: &str) -> Result<()> {
+        self.enqueue(hash, false)
+    }
+
+    /// Leave out the commit with the given hash and its ancestors, as in `^hash`.
+    pub fn hide(&mut self, hash: &str) -> Result<()> {
+        self.has_hidden = true;
+        match self.commits.get_mut(hash) {
+            Some((_, hidden)) => {
+                *hidden = true;
+                self.hide_ancestors(hash);
+                Ok(())
+            }
+            None => self.enqueue(hash, true),
+        }
+    }
+
+    /// Add a commit to the queue, unless it has been queued already.
+    fn enqueue(&mut self, hash: &str, hidden: bool) -> Result<()> {
+        if self.commits.contains_key(hash) {
+            return Ok(());
+        }
+        let commit = Object::read_commit(self.repo, hash)?;
+        self.queue.push(QueueEntry {
+            time: commit.committer.time,
+            order: self.queued,
+            hash: hash.to_owned(),
+        });
+        self.queued += 1;
+        self.commits.insert(hash.to_owned(), (commit, hidden));
+        Ok(())
+    }
+
+    /// Queue the parents of a commit that has just been popped from the queue. Hidden commits
+    /// pass that on to all their parents, even when only following first parents.
+    fn enqueue_parents(&mut self, hash: &str) -> Result<()> {
+        let (commit, hidden) = &self.commits[hash];
+        let hidden = *hidden;
+        let parents = match (hidden, self.first_parent) {
+            (false, true) => commit.parent_hashes.iter().take(1).cloned().collect(),
+            _ => commit.parent_hashes.clone(),
+        };
+        if hidden {
+            self.hide_ancestors(hash);
+        }
+        for parent in parents {
+            self.enqueue(&parent, hidden)?;
+        }
+        Ok(())
+    }
+
+    /// Mark the ancestors of a hidden commit as hidden, as far as they have been queued.
+    fn hide_ancestors(&mut self, hash: &str) {
+        let mut stack = self.commits[hash].0.parent_hashes.clone();
+        while let Some(hash) = stack.pop() {
+            if let Some((commit, hidden)) = self.commits.get_mut(&hash) {
+                if !*hidden {
+                    *hidden = true;
+                    stack.extend(commit.parent_hashes.iter().cloned());
+                }
+            }
+        }
+    }
+
+    /// Find all the commits to output, when some are hidden. Commits are only known to be hidden
+    /// once all paths to them from hidden commits have been walked, so the queue has to be walked
+    /// until only hidden commits are left in it.
+    fn limit(&mut self) -> Result<Vec<String>> {
+        let mut output = Vec::new();
+        let mut slop = SLOP;
+        let mut last_time = None;
+        while let Some(entry) = self.queue.pop() {
+            self.enqueue_parents(&entry.hash)?;
+            if self.commits[&entry.hash].1 {
+                slop = self.still_interesting(last_time, slop);
+                if slop == 0 {
+                    break;
+                }
+                continue;
+            }
+            last_time = Some(entry.time);
+            output.push(entry.hash);
+        }
+
+        output.retain(|hash| !self.commits[hash].1);
+        Ok(output)
+    }
+
+    /// Count down the commits left to look at, once only hidden ones are queued and they are all
+    /// older than the last commit that will be output.
+    fn still_interesting(&self, last_time: Option<i64>, slop: usize) -> usize {
+        if self.queue.iter().any(|e| !self.commits[&e.hash].1) {
+            return SLOP;
+        }
+        match (self.queue.peek(), last_time) {
+            (None, _) => 0,
+            (Some(next), Some(last_time)) if last_time <= next.time => SLOP,
+            _ => slop - 1,
+        }
+    }
+
+    fn next_commit(&mut self) -> Result<Option<String>> {
+        if self.has_hidden {
+            if self.limited.is_none() {
+                self.limited = Some(self.limit()?.into_iter());
+            }
+            return Ok(self.limited.as_mut().unwrap().next());
+        }
+
+        match self.queue.pop() {
+            Some(entry) => {
+                self.enqueue_parents(&entry.hash)?;
+                Ok(Some(entry.hash))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+impl Iterator for RevWalk<'_> {
+    type Item = Result<(String, Commit)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_commit() {
+            Ok(Some(hash)) => {
+                let commit = self.commits[&hash].0.clone();
+                Some(Ok((hash, commit)))
+            }
+            Ok(None) => None,
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RevWalk;
+    use crate::{
+        object::Object,
+        test_util::{signature, TempRepo},
+    };
+
+    #[test]
+    fn test_walk() {
+        let repo = TempRepo::new("revwalk");
+        let tree = Object::Tree(Vec::new());
+        tree.add(&repo).unwrap();
+
+        let commit = |parents: &[&str], time: i64| {
+            let object =
+                Object::new_commit(&tree.hash(), parents, signature(time), signature(time), "m");
+            object.add(&repo).unwrap();
+            object.hash()
+        };
+        // root - a - merge
+        //     \- b -/
+        let root = commit(&[], 0);
+        let a = commit(&[&root], 10);
+        let b = commit(&[&root], 20);
+        let merge = commit(&[&a, &b], 30);
+
+        let walk = |push: &[&str], hide: &[&str], first_parent: bool| {
+            let mut walk = RevWalk::new(&repo);
+            walk.first_parent(first_parent);
+            push.iter().for_each(|hash| walk.push(hash).unwrap());
+            hide.iter().for_each(|hash| walk.hide(hash).unwrap());
+            walk.map(|c| c.unwrap().0).collect::<Vec<String>>()
+        };
+        assert_eq!(
+            walk(&[&merge], &[], false),
+            [&merge, &b, &a, &root].map(String::as_str)
+        );
+        assert_eq!(
+            walk(&[&merge], &[], true),
+            [&merge, &a, &root].map(String::as_str)
+        );
+        assert_eq!(walk(&[&merge], &[&a], false), [merge.as_str(), &b]);
+        assert_eq!(walk(&[&a, &b], &[&b], false), [a.as_str()]);
+        assert!(walk(&[&a], &[&merge], false).is_empty());
+    }
+}
@@ -0,0 +1,1245 @@
+//! Comparing `HEAD`, the index and the working tree, to find what's staged, what isn't, and
+//! which files aren't tracked at all.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs,
+    io::Write,
+};
+
+use crate::{
+    error::Result,
+    ignore::Ignore,
+    index::{self, mode_from_metadata, Index, IndexEntry},
+    object::{read_tree_recursive, Object},
+    pathspec::Pathspec,
+    refs::{self, NULL_HASH},
+    repository::Repository,
+    revision,
+    revwalk::RevWalk,
+    util::is_missing,
+    worktree::{self, FileModes},
+};
+
+/// Similarity scores are out of this, as in git.
+const MAX_SCORE: u64 = 60000;
+
+/// Files that are at least half the same count as renamed.
+const MIN_SCORE: u64 = MAX_SCORE / 2;
+
+/// Files with the same name count as renamed if they're at least three quarters the same, if
+/// there's no other file with that name to choose from.
+const MIN_BASENAME_SCORE: u64 = MIN_SCORE + (MAX_SCORE - MIN_SCORE) / 2;
+
+/// How many of the best rename sources to remember for each new file.
+const CANDIDATES_PER_FILE: usize = 4;
+
+/// How a file differs from `HEAD` to the index, or from the index to the working tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    /// The file is new.
+    Added,
+    /// The file is gone.
+    Deleted,
+    /// The file's contents or executable bit changed.
+    Modified,
+    /// The file changed between a regular file, a symlink and a gitlink.
+    TypeChanged,
+    /// The file was moved from another path. Only staged renames are detected.
+    Renamed,
+}
+
+impl Change {
+    /// The letter for the change in `status --short`, e.g., `M`.
+    pub fn letter(self) -> char {
+        match self {
+            Change::Added => 'A',
+            Change::Deleted => 'D',
+            Change::Modified => 'M',
+            Change::TypeChanged => 'T',
+            Change::Renamed => 'R',
+        }
+    }
+}
+
+/// Which untracked files to look for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UntrackedFiles {
+    /// None of them.
+    No,
+    /// Untracked files, but only the directory for directories with no tracked files in.
+    Normal,
+    /// Every untracked file.
+    All,
+}
+
+/// A file's mode and hash in `HEAD` or the index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Version {
+    /// File mode, e.g., `0o100644`
+    pub mode: u32,
+    /// Hash of the blob (or commit, for gitlinks)
+    pub hash: String,
+}
+
+/// The versions of a path with a merge conflict: the common ancestor's, ours and theirs, for
+/// index stages 1 to 3.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    /// Stages 1 to 3, each missing if the file doesn't exist on that side
+    pub stages: [Option<Version>; 3],
+}
+
+impl Conflict {
+    /// The two-letter code for the conflict in `status --short`, e.g., `UU` if both sides
+    /// modified the file or `DU` if we deleted it and they modified it.
+    pub fn code(&self) -> &'static str {
+        let [base, ours, theirs] = &self.stages;
+        match [base.is_some(), ours.is_some(), theirs.is_some()] {
+            [true, false, false] => "DD",
+            [false, true, false] => "AU",
+            [true, true, false] => "UD",
+            [false, false, true] => "UA",
+            [true, false, true] => "DU",
+            [false, true, true] => "AA",
+            _ => "UU",
+        }
+    }
+}
+
+/// A tracked path that's changed since `HEAD`, in the working tree or both.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusEntry {
+    /// Path from the root of the working tree
+    pub path: String,
+    /// For a staged rename, the path in `HEAD` and how similar the contents are, as a percentage
+    pub renamed_from: Option<(String, u32)>,
+    /// How the index differs from `HEAD`
+    pub staged: Option<Change>,
+    /// How the working tree differs from the index
+    pub unstaged: Option<Change>,
+    /// The version in `HEAD` (of the path it was renamed from, for renames)
+    pub head: Option<Version>,
+    /// The version in the index, which is missing for files that were added with `add -N`
+    pub index: Option<Version>,
+    /// The file's mode in the working tree, if it exists
+    pub worktree_mode: Option<u32>,
+    /// The versions on each side, if the path has a merge conflict
+    pub conflict: Option<Conflict>,
+}
+
+/// The differences between `HEAD`, the index and the working tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Status {
+    /// Tracked paths with changes, sorted by path
+    pub entries: Vec<StatusEntry>,
+    /// Untracked files that aren't ignored, sorted, with directories that only have untracked
+    /// files in given as `dir/` (unless every untracked file was asked for)
+    pub untracked: Vec<String>,
+}
+
+impl Status {
+    /// Compare `HEAD`, the index and the working tree, for paths that match `pathspec`.
+    ///
+    /// Files whose stat data shows that they haven't changed aren't hashed. Those that have been
+    /// touched without changing are refreshed in `index`, so they don't need hashing next time.
+    pub fn new(
+        repo: &Repository,
+        index: &mut Index,
+        pathspec: &Pathspec,
+        untracked_files: UntrackedFiles,
+    ) -> Result<Self> {
+        let head_files = match repo.read_ref("HEAD")? {
+            Some(head) => {
+                let commit = Object::read_commit(repo, &head)?;
+                read_tree_recursive(repo, &commit.tree_hash)?
+            }
+            None => BTreeMap::new(),
+        };
+        let head_files: BTreeMap<_, _> = head_files
+            .into_iter()
+            .map(|(path, entry)| {
+                let mode = u32::from_str_radix(&entry.mode, 8).unwrap_or(0);
+                (
+                    path,
+                    Version {
+                        mode,
+                        hash: entry.hash,
+                    },
+                )
+            })
+            .collect();
+
+        let mut entries = BTreeMap::new();
+        let mut conflicts: BTreeMap<&str, Conflict> = BTreeMap::new();
+        for entry in index.entries().iter().filter(|e| e.stage != 0) {
+            let conflict = conflicts.entry(&entry.path).or_insert(Conflict {
+                stages: [None, None, None],
+            });
+            conflict.stages[entry.stage as usize - 1] = Some(Version {
+                mode: entry.mode,
+                hash: entry.hash.clone(),
+            });
+        }
+        let staged: BTreeMap<&str, &IndexEntry> = index
+            .entries()
+            .iter()
+            .filter(|e| e.stage == 0)
+            .map(|e| (e.path.as_str(), e))
+            .collect();
+
+        // HEAD against the index
+        let mut added = Vec::new();
+        let mut deleted = Vec::new();
+        let paths: BTreeSet<&str> = head_files
+            .keys()
+            .map(String::as_str)
+            .chain(staged.keys().copied())
+            .collect();
+        for path in paths {
+            if conflicts.contains_key(path) {
+                continue;
+            }
+            let head = head_files.get(path);
+            let index = staged
+                .get(path)
+                .filter(|e| !e.intent_to_add)
+                .map(|e| Version {
+                    mode: e.mode,
+                    hash: e.hash.clone(),
+                });
+            let change = match (head, index.as_ref()) {
+                (None, None) => None,
+                (None, Some(index)) => {
+                    added.push((path.to_owned(), index.clone()));
+                    Some(Change::Added)
+                }
+                (Some(head), None) => {
+                    deleted.push((path.to_owned(), head.clone()));
+                    Some(Change::Deleted)
+                }
+                (Some(head), Some(index)) => compare_versions(head, index),
+            };
+            if change.is_some() {
+                entries.insert(
+                    path.to_owned(),
+                    new_entry(path, head.cloned(), index, change),
+                );
+            }
+        }
+
+        for (path, (from, score)) in detect_renames(repo, &deleted, &added)? {
+            let source = entries.remove(&from).unwrap();
+            let entry = entries.get_mut(&path).unwrap();
+            entry.staged = Some(Change::Renamed);
+            entry.head = source.head;
+            entry.renamed_from = Some((from, (score * 100 / MAX_SCORE) as u32));
+        }
+
+        // The index against the working tree
+        let work_tree = repo.work_tree()?;
+        let index_mtime = index::index_mtime(&repo.index_path());
+        let modes = FileModes::load(repo)?;
+        let mut refreshed = Vec::new();
+        for (path, conflict) in conflicts {
+            let worktree_mode = match fs::symlink_metadata(work_tree.join(path)) {
+                Ok(metadata) => Some(mode_from_metadata(&metadata)),
+                Err(err) if is_missing(&err) => None,
+                Err(err) => return Err(err.into()),
+            };
+            let mut entry = new_entry(path, head_files.get(path).cloned(), None, None);
+            entry.worktree_mode = worktree_mode;
+            entry.conflict = Some(conflict);
+            entries.insert(path.to_owned(), entry);
+        }
+        for (path, index_entry) in staged {
+            let metadata = match fs::symlink_metadata(work_tree.join(path)) {
+                Ok(metadata) => Some(metadata),
+                Err(err) if is_missing(&err) => None,
+                Err(err) => return Err(err.into()),
+            };
+            // A directory where a file was is only a change for gitlinks, which are directories
+            let metadata = metadata.filter(|m| !m.is_dir() || index_entry.mode == 0o160000);
+            let worktree_mode = metadata
+                .as_ref()
+                .map(|m| modes.mode(m, Some(index_entry.mode)));
+
+            let change = match (metadata, index_entry.intent_to_add) {
+                (None, _) => Some(Change::Deleted),
+                (Some(_), true) => Some(Change::Added),
+                (Some(metadata), false) => {
+                    let mode = modes.mode(&metadata, Some(index_entry.mode));
+                    if mode & 0o170000 != index_entry.mode & 0o170000 {
+                        Some(Change::TypeChanged)
+                    } else if mode == 0o160000 {
+                        None
+                    } else if worktree::is_modified(work_tree, index_entry, index_mtime, &modes)? {
+                        Some(Change::Modified)
+                    } else {
+                        if index_entry.stat_changed(&metadata) {
+                            refreshed.push((path.to_owned(), metadata));
+                        }
+                        None
+                    }
+                }
+            };
+            if change.is_none() {
+                continue;
+            }
+
+            let entry =
+                entries.entry(path.to_owned()).or_insert_with(|| {
+                    // Files added with `add -N` only count as in the index once they're deleted
+                    let index = (!index_entry.intent_to_add || change == Some(Change::Deleted))
+                        .then(|| Version {
+                            mode: index_entry.mode,
+                            hash: index_entry.hash.clone(),
+                        });
+                    new_entry(path, head_files.get(path).cloned(), index, None)
+                });
+            entry.unstaged = change;
+            entry.worktree_mode = worktree_mode;
+        }
+        for (path, metadata) in refreshed {
+            index.refresh(&path, &metadata);
+        }
+
+        let entries = entries
+            .into_values()
+            .filter(|entry| {
+                pathspec.matches(&entry.path).is_some()
+                    || entry
+                        .renamed_from
+                        .as_ref()
+                        .is_some_and(|(from, _)| pathspec.matches(from).is_some())
+            })
+            .map(|mut entry| {
+                // Paths without staged changes are the same in HEAD as in the index
+                if entry.staged.is_none() && entry.conflict.is_none() {
+                    entry.head = entry.index.clone();
+                }
+                entry
+            })
+            .collect();
+
+        let untracked = match untracked_files {
+            UntrackedFiles::No => Vec::new(),
+            _ => {
+                let ignore = Ignore::new(repo)?;
+                find_untracked(work_tree, index, &ignore, pathspec, untracked_files)?
+            }
+        };
+
+        Ok(Status { entries, untracked })
+    }
+
+    /// Whether anything has been staged to commit.
+    pub fn has_staged(&self) -> bool {
+        self.entries
+            .iter()
+            .any(|e| e.staged.is_some() && e.conflict.is_none())
+    }
+
+    /// Whether anything in the working tree is different from the index, including unresolved
+    /// conflicts but not untracked files.
+    pub fn has_unstaged(&self) -> bool {
+        self.entries
+            .iter()
+            .any(|e| e.unstaged.is_some() || e.conflict.is_some())
+    }
+}
+
+/// The current branch and how it compares with its upstream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BranchStatus {
+    /// The branch's full ref name, or `None` if `HEAD` is detached
+    pub branch: Option<String>,
+    /// The commit `HEAD` points to, unless there are no commits yet
+    pub head: Option<String>,
+    /// The upstream's full ref name, with how many commits the branch is ahead and behind it, or
+    /// `None` if the upstream is gone
+    pub upstream: Option<(String, Option<(usize, usize)>)>,
+    /// Whether a merge is in progress, waiting to be committed
+    pub merging: bool,
+}
+
+impl BranchStatus {
+    /// Read the current branch, its upstream, and whether a merge is in progress.
+    pub fn read(repo: &Repository) -> Result<Self> {
+        let branch = repo.symbolic_ref("HEAD")?;
+        let head = repo.read_ref("HEAD")?;
+        let upstream = match branch.as_deref() {
+            Some(branch) => revision::upstream(repo, branch)?,
+            None => None,
+        };
+        let upstream = match upstream {
+            Some(upstream) => {
+                let counts = match (head.as_deref(), repo.read_ref(&upstream)?) {
+                    (Some(head), Some(upstream_hash)) => Some((
+                        count_commits(repo, head, &upstream_hash)?,
+                        count_commits(repo, &upstream_hash, head)?,
+                    )),
+                    _ => None,
+                };
+                Some((upstream, counts))
+            }
+            None => None,
+        };
+        Ok(BranchStatus {
+            branch,
+            head,
+            upstream,
+            merging: repo.git_dir().join("MERGE_HEAD").exists(),
+        })
+    }
+}
+
+impl Status {
+    /// Print the status as `status` does by default, with the branch, hints about what to do
+    /// next, and paths relative to `prefix`, the current directory's path in the working tree.
+    pub fn print_long(
+        &self,
+        output: &mut impl Write,
+        branch: &BranchStatus,
+        prefix: &str,
+        untracked_files: UntrackedFiles,
+    ) -> Result<()> {
+        match branch.branch.as_deref() {
+            Some(name) => writeln!(output, "On branch {}", refs::shorten(name))?,
+            None => {
+                let head = branch.head.as_deref().unwrap_or_default();
+                writeln!(output, "HEAD detached at {}", head.get(..7).unwrap_or(head))?;
+            }
+        }
+        if let Some((upstream, counts)) = branch.upstream.as_ref() {
+            let upstream = refs::shorten(upstream);
+            match counts {
+                None => {
+                    writeln!(
+                        output,
+                        "Your branch is based on '{}', but the upstream is gone.",
+                        upstream
+                    )?;
+                    writeln!(output, "  (use \"git branch --unset-upstream\" to fixup)")?;
+                }
+                Some((0, 0)) => writeln!(output, "Your branch is up to date with '{}'.", upstream)?,
+                Some((ahead, 0)) => {
+                    writeln!(
+                        output,
+                        "Your branch is ahead of '{}' by {}.",
+                        upstream,
+                        plural(*ahead, "commit")
+                    )?;
+                    writeln!(output, "  (use \"git push\" to publish your local commits)")?;
+                }
+                Some((0, behind)) => {
+                    writeln!(
+                        output,
+                        "Your branch is behind '{}' by {}, and can be fast-forwarded.",
+                        upstream,
+                        plural(*behind, "commit")
+                    )?;
+                    writeln!(output, "  (use \"git pull\" to update your local branch)")?;
+                }
+                Some((ahead, behind)) => {
+                    writeln!(output, "Your branch and '{}' have diverged,", upstream)?;
+                    writeln!(
+                        output,
+                        "and have {} and {} different commits each, respectively.",
+                        ahead, behind
+                    )?;
+                    writeln!(
+                        output,
+                        "  (use \"git pull\" if you want to integrate the remote branch with yours)"
+                    )?;
+                }
+            }
+            writeln!(output)?;
+        }
+        if branch.head.is_none() {
+            writeln!(output)?;
+            writeln!(output, "No commits yet")?;
+            writeln!(output)?;
+        }
+
+        let has_conflicts = self.entries.iter().any(|e| e.conflict.is_some());
+        if branch.merging {
+            if has_conflicts {
+                writeln!(output, "You have unmerged paths.")?;
+                writeln!(output, "  (fix conflicts and run \"git commit\")")?;
+                writeln!(output, "  (use \"git merge --abort\" to abort the merge)")?;
+            } else {
+                writeln!(output, "All conflicts fixed but you are still merging.")?;
+                writeln!(output, "  (use \"git commit\" to conclude merge)")?;
+            }
+            writeln!(output)?;
+        }
+
+        let unstage_hint = match branch.head {
+            Some(_) => "  (use \"git restore --staged <file>...\" to unstage)",
+            None => "  (use \"git rm --cached <file>...\" to unstage)",
+        };
+        let staged = self
+            .entries
+            .iter()
+            .filter(|e| e.staged.is_some() && e.conflict.is_none())
+            .collect::<Vec<_>>();
+        if !staged.is_empty() {
+            writeln!(output, "Changes to be committed:")?;
+            // Unstaging isn't how to get out of a merge
+            if !branch.merging {
+                writeln!(output, "{}", unstage_hint)?;
+            }
+            for entry in staged {
+                let change = entry.staged.unwrap();
+                let path = relative_path(&entry.path, prefix);
+                match entry.renamed_from.as_ref() {
+                    Some((from, _)) => writeln!(
+                        output,
+                        "\t{:<12}{} -> {}",
+                        "renamed:",
+                        relative_path(from, prefix),
+                        path
+                    )?,
+                    None => writeln!(
+                        output,
+                        "\t{:<12}{}",
+                        format!("{}:", change_label(change)),
+                        path
+                    )?,
+                }
+            }
+            writeln!(output)?;
+        }
+
+        let conflicts = self
+            .entries
+            .iter()
+            .filter_map(|e| Some((e, e.conflict.as_ref()?)))
+            .collect::<Vec<_>>();
+        if !conflicts.is_empty() {
+            writeln!(output, "Unmerged paths:")?;
+            if !branch.merging {
+                writeln!(output, "{}", unstage_hint)?;
+            }
+            match conflicts.iter().any(|(_, c)| c.code().contains('D')) {
+                true => writeln!(
+                    output,
+                    "  (use \"git add/rm <file>...\" as appropriate to mark resolution)"
+                )?,
+                false => writeln!(output, "  (use \"git add <file>...\" to mark resolution)")?,
+            }
+            for (entry, conflict) in conflicts {
+                let label = match conflict.code() {
+                    "DD" => "both deleted:",
+                    "AU" => "added by us:",
+                    "UD" => "deleted by them:",
+                    "UA" => "added by them:",
+                    "DU" => "deleted by us:",
+                    "AA" => "both added:",
+                    _ => "both modified:",
+                };
+                writeln!(
+                    output,
+                    "\t{:<17}{}",
+                    label,
+                    relative_path(&entry.path, prefix)
+                )?;
+            }
+            writeln!(output)?;
+        }
+
+        let unstaged = self
+            .entries
+            .iter()
+            .filter(|e| e.unstaged.is_some())
+            .collect::<Vec<_>>();
+        if !unstaged.is_empty() {
+            writeln!(output, "Changes not staged for commit:")?;
+            match unstaged.iter().any(|e| e.unstaged == Some(Change::Deleted)) {
+                true => writeln!(
+                    output,
+                    "  (use \"git add/rm <file>...\" to update what will be committed)"
+                )?,
+                false => writeln!(
+                    output,
+                    "  (use \"git add <file>...\" to update what will be committed)"
+                )?,
+            }
+            writeln!(
+                output,
+                "  (use \"git restore <file>...\" to discard changes in working directory)"
+            )?;
+            for entry in unstaged {
+                let label = format!("{}:", change_label(entry.unstaged.unwrap()));
+                writeln!(
+                    output,
+                    "\t{:<12}{}",
+                    label,
+                    relative_path(&entry.path, prefix)
+                )?;
+            }
+            writeln!(output)?;
+        }
+
+        if !self.untracked.is_empty() {
+            writeln!(output, "Untracked files:")?;
+            writeln!(
+                output,
+                "  (use \"git add <file>...\" to include in what will be committed)"
+            )?;
+            for path in self.untracked.iter() {
+                writeln!(output, "\t{}", relative_path(path, prefix))?;
+            }
+            writeln!(output)?;
+        }
+
+        let untracked_shown = untracked_files != UntrackedFiles::No;
+        if self.has_staged() {
+            if !untracked_shown {
+                writeln!(
+                    output,
+                    "Untracked files not listed (use -u option to show untracked files)"
+                )?;
+            }
+        } else if self.has_unstaged() {
+            writeln!(
+                output,
+                "no changes added to commit (use \"git add\" and/or \"git commit -a\")"
+            )?;
+        } else if !self.untracked.is_empty() {
+            writeln!(
+                output,
+                "nothing added to commit but untracked files present (use \"git add\" to track)"
+            )?;
+        } else if branch.head.is_none() {
+            writeln!(
+                output,
+                "nothing to commit (create/copy files and use \"git add\" to track)"
+            )?;
+        } else if !untracked_shown {
+            writeln!(output, "nothing to commit (use -u to show untracked files)")?;
+        } else {
+            writeln!(output, "nothing to commit, working tree clean")?;
+        }
+        Ok(())
+    }
+
+    /// Print the status as `status --short` does, with the branch first if it's given, and paths
+    /// relative to `prefix`.
+    pub fn print_short(
+        &self,
+        output: &mut impl Write,
+        branch: Option<&BranchStatus>,
+        prefix: &str,
+    ) -> Result<()> {
+        if let Some(branch) = branch {
+            let name = branch.branch.as_deref().map(refs::shorten);
+            match (name, branch.head.as_ref()) {
+                (Some(name), None) => writeln!(output, "## No commits yet on {}", name)?,
+                (None, _) => writeln!(output, "## HEAD (no branch)")?,
+                (Some(name), Some(_)) => match branch.upstream.as_ref() {
+                    None => writeln!(output, "## {}", name)?,
+                    Some((upstream, counts)) => {
+                        let tracking = match counts {
+                            None => " [gone]".to_owned(),
+                            Some((0, 0)) => String::new(),
+                            Some((ahead, 0)) => format!(" [ahead {}]", ahead),
+                            Some((0, behind)) => format!(" [behind {}]", behind),
+                            Some((ahead, behind)) => {
+                                format!(" [ahead {}, behind {}]", ahead, behind)
+                            }
+                        };
+                        writeln!(
+                            output,
+                            "## {}...{}{}",
+                            name,
+                            refs::shorten(upstream),
+                            tracking
+                        )?;
+                    }
+                },
+            }
+        }
+
+        for entry in self.entries.iter() {
+            let code = match entry.conflict.as_ref() {
+                Some(conflict) => conflict.code().to_owned(),
+                None => [entry.staged, entry.unstaged]
+                    .iter()
+                    .map(|change| change.map_or(' ', Change::letter))
+                    .collect(),
+            };
+            let path = relative_path(&entry.path, prefix);
+            match entry.renamed_from.as_ref() {
+                Some((from, _)) => writeln!(
+                    output,
+                    "{} {} -> {}",
+                    code,
+                    relative_path(from, prefix),
+                    path
+                )?,
+                None => writeln!(output, "{} {}", code, path)?,
+            }
+        }
+        for path in self.untracked.iter() {
+            writeln!(output, "?? {}", relative_path(path, prefix))?;
+        }
+        Ok(())
+    }
+
+    /// Print the status as `status --porcelain=v2` does, with the branch headers first if it's
+    /// given.
+    pub fn print_porcelain_v2(
+        &self,
+        output: &mut impl Write,
+        branch: Option<&BranchStatus>,
+    ) -> Result<()> {
+        if let Some(branch) = branch {
+            writeln!(
+                output,
+                "# branch.oid {}",
+                branch.head.as_deref().unwrap_or("(initial)")
+            )?;
+            writeln!(
+                output,
+                "# branch.head {}",
+                branch.branch.as_deref().map_or("(detached)", refs::shorten)
+            )?;
+            if let Some((upstream, counts)) = branch.upstream.as_ref() {
+                writeln!(output, "# branch.upstream {}", refs::shorten(upstream))?;
+                if let Some((ahead, behind)) = counts {
+                    writeln!(output, "# branch.ab +{} -{}", ahead, behind)?;
+                }
+            }
+        }
+
+        let mode = |version: Option<&Version>| version.map_or(0, |v| v.mode);
+        let hash =
+            |version: Option<&Version>| version.map_or(NULL_HASH.to_owned(), |v| v.hash.clone());
+        let submodule = |entry: &StatusEntry| {
+            let modes = [entry.head.as_ref(), entry.index.as_ref()].map(mode);
+            match modes.contains(&0o160000) {
+                true => "S...",
+                false => "N...",
+            }
+        };
+
+        // Unmerged paths come after the other changes
+        for entry in self.entries.iter().filter(|e| e.conflict.is_none()) {
+            let code: String = [entry.staged, entry.unstaged]
+                .iter()
+                .map(|change| change.map_or('.', Change::letter))
+                .collect();
+            // The file in the working tree is the same as the index if it hasn't changed
+            let worktree_mode = match entry.unstaged {
+                Some(_) => entry.worktree_mode.unwrap_or(0),
+                None => mode(entry.index.as_ref()),
+            };
+            let fields = format!(
+                "{} {} {:06o} {:06o} {:06o} {} {}",
+                code,
+                submodule(entry),
+                mode(entry.head.as_ref()),
+                mode(entry.index.as_ref()),
+                worktree_mode,
+                hash(entry.head.as_ref()),
+                hash(entry.index.as_ref()),
+            );
+            match entry.renamed_from.as_ref() {
+                Some((from, score)) => {
+                    writeln!(output, "2 {} R{} {}\t{}", fields, score, entry.path, from)?
+                }
+                None => writeln!(output, "1 {} {}", fields, entry.path)?,
+            }
+        }
+        for entry in self.entries.iter() {
+            let conflict = match entry.conflict.as_ref() {
+                Some(conflict) => conflict,
+                None => continue,
+            };
+            let [base, ours, theirs] = &conflict.stages;
+            let (base, ours, theirs) = (base.as_ref(), ours.as_ref(), theirs.as_ref());
+            writeln!(
+                output,
+                "u {} {} {:06o} {:06o} {:06o} {:06o} {} {} {} {}",
+                conflict.code(),
+                submodule(entry),
+                mode(base),
+                mode(ours),
+                mode(theirs),
+                entry.worktree_mode.unwrap_or(0),
+                hash(base),
+                hash(ours),
+                hash(theirs),
+                entry.path
+            )?;
+        }
+        for path in self.untracked.iter() {
+            writeln!(output, "? {}", path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Count the commits reachable from one commit but not from another.
+fn count_commits(repo: &Repository, from: &str, hidden: &str) -> Result<usize> {
+    let mut walk = RevWalk::new(repo);
+    walk.push(from)?;
+    walk.hide(hidden)?;
+    let mut count = 0;
+    for commit in walk {
+        commit?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Make a path from the root of the working tree relative to the current directory, given its
+/// path from the root.
+fn relative_path(path: &str, prefix: &str) -> String {
+    if prefix.is_empty() {
+        return path.to_owned();
+    }
+    let prefix = format!("{}/", prefix);
+    let common = prefix
+        .match_indices('/')
+        .map(|(i, _)| i + 1)
+        .take_while(|&end| path.starts_with(&prefix[..end]))
+        .last()
+        .unwrap_or(0);
+    let ups = prefix[common..].matches('/').count();
+    let relative = format!("{}{}", "../".repeat(ups), &path[common..]);
+    if relative.is_empty() {
+        "./".to_owned()
+    } else {
+        relative
+    }
+}
+
+/// How a change is described in `status`'s long output.
+fn change_label(change: Change) -> &'static str {
+    match change {
+        Change::Added => "new file",
+        Change::Deleted => "deleted",
+        Change::Modified => "modified",
+        Change::TypeChanged => "typechange",
+        Change::Renamed => "renamed",
+    }
+}
+
+/// A count followed by a noun, made plural if there isn't just one, e.g., `2 commits`.
+fn plural(count: usize, noun: &str) -> String {
+    match count {
+        1 => format!("1 {}", noun),
+        _ => format!("{} {}s", count, noun),
+    }
+}
+
+fn new_entry(
+    path: &str,
+    head: Option<Version>,
+    index: Option<Version>,
+    staged: Option<Change>,
+) -> StatusEntry {
+    StatusEntry {
+        path: path.to_owned(),
+        renamed_from: None,
+        staged,
+        unstaged: None,
+        head,
+        index,
+        worktree_mode: None,
+        conflict: None,
+    }
+}
+
+/// How a file changed between two versions that both exist.
+fn compare_versions(old: &Version, new: &Version) -> Option<Change> {
+    if old.mode & 0o170000 != new.mode & 0o170000 {
+        Some(Change::TypeChanged)
+    } else if old != new {
+        Some(Change::Modified)
+    } else {
+        None
+    }
+}
+
+/// List the files in the working tree that aren't in the index or ignored, collapsing
+/// directories with no tracked files in them unless every file is wanted.
+fn find_untracked(
+    work_tree: &std::path::Path,
+    index: &Index,
+    ignore: &Ignore,
+    pathspec: &Pathspec,
+    untracked_files: UntrackedFiles,
+) -> Result<Vec<String>> {
+    let tracked: HashSet<&str> = index.entries().iter().map(|e| e.path.as_str()).collect();
+    let tracked_dirs: HashSet<&str> = index
+        .entries()
+        .iter()
+        .flat_map(|e| e.path.match_indices('/').map(|(i, _)| &e.path[..i]))
+        .collect();
+
+    let mut untracked = Vec::new();
+    for path in worktree::walk(work_tree, Some(ignore))? {
+        if tracked.contains(path.as_str()) || pathspec.matches(&path).is_none() {
+            continue;
+        }
+        let collapsed = match untracked_files {
+            UntrackedFiles::Normal => path
+                .match_indices('/')
+                .map(|(i, _)| &path[..i])
+                .find(|dir| !tracked_dirs.contains(dir))
+                .map(|dir| format!("{}/", dir)),
+            _ => None,
+        };
+        let path = collapsed.unwrap_or(path);
+        if untracked.last() != Some(&path) {
+            untracked.push(path);
+        }
+    }
+    Ok(untracked)
+}
+
+/// Pair files deleted since `HEAD` with added ones that are (at least half) the same, as git
+/// does, returning the path each added file was renamed from and their similarity score.
+///
+/// Identical files are paired first, then ones with the same name, then the rest in order of how
+/// similar they are.
+fn detect_renames(
+    repo: &Repository,
+    deleted: &[(String, Version)],
+    added: &[(String, Version)],
+) -> Result<BTreeMap<String, (String, u64)>> {
+    // Gitlinks aren't files, so they can't be renamed
+    let deleted: Vec<_> = deleted.iter().filter(|(_, v)| v.mode != 0o160000).collect();
+    let added: Vec<_> = added.iter().filter(|(_, v)| v.mode != 0o160000).collect();
+    let mut renames = BTreeMap::new();
+    let mut used = vec![false; deleted.len()];
+
+    for (path, version) in added.iter() {
+        // Prefer a source with the same name, if there's a choice
+        let source = (0..deleted.len())
+            .filter(|&i| !used[i] && deleted[i].1.hash == version.hash)
+            .max_by_key(|&i| {
+                (
+                    basename(&deleted[i].0) == basename(path),
+                    std::cmp::Reverse(i),
+                )
+            });
+        if let Some(i) = source {
+            used[i] = true;
+            renames.insert(path.clone(), (deleted[i].0.clone(), MAX_SCORE));
+        }
+    }
+
+    // Read the files that could still be renamed
+    let mut contents = HashMap::new();
+    for (_, version) in deleted.iter().chain(added.iter()) {
+        if !contents.contains_key(&version.hash) {
+            let content = match Object::parse_from_hash(repo, &version.hash)? {
+                Object::Blob(content) => content,
+                _ => Vec::new(),
+            };
+            contents.insert(version.hash.clone(), content);
+        }
+    }
+    let score = |source: &Version, destination: &Version| {
+        // Symlinks can only be renamed to symlinks, and regular files to regular files
+        if source.mode & 0o170000 != destination.mode & 0o170000 {
+            return 0;
+        }
+        similarity(&contents[&source.hash], &contents[&destination.hash])
+    };
+
+    // Files with a name that only one deleted and one added file have are likely to be the same
+    let source_names = unique_names(&deleted, |i| used[i]);
+    let destination_names = unique_names(&added, |i| renames.contains_key(&added[i].0));
+    let mut pairs: Vec<_> = destination_names
+        .iter()
+        .filter_map(|(name, destination)| Some(((*destination)?, (*source_names.get(name)?)?)))
+        .collect();
+    pairs.sort();
+    for (destination, source) in pairs {
+        let score = score(&deleted[source].1, &added[destination].1);
+        if score >= MIN_BASENAME_SCORE {
+            used[source] = true;
+            let from = deleted[source].0.clone();
+            renames.insert(added[destination].0.clone(), (from, score));
+        }
+    }
+
+    let mut candidates = Vec::new();
+    for (destination, (path, version)) in added.iter().enumerate() {
+        if renames.contains_key(path) {
+            continue;
+        }
+        let mut best = Vec::new();
+        for (source, (source_path, source_version)) in deleted.iter().enumerate() {
+            if used[source] {
+                continue;
+            }
+            let score = score(source_version, version);
+            let same_name = basename(source_path) == basename(path);
+            best.push((score, same_name, destination, source));
+        }
+        best.sort_by_key(|&(score, same_name, _, _)| std::cmp::Reverse((score, same_name)));
+        best.truncate(CANDIDATES_PER_FILE);
+        candidates.extend(best);
+    }
+    candidates.sort_by_key(|&(score, same_name, _, _)| std::cmp::Reverse((score, same_name)));
+    for (score, _, destination, source) in candidates {
+        if score < MIN_SCORE {
+            break;
+        }
+        let path = &added[destination].0;
+        if used[source] || renames.contains_key(path) {
+            continue;
+        }
+        used[source] = true;
+        renames.insert(path.clone(), (deleted[source].0.clone(), score));
+    }
+
+    Ok(renames)
+}
+
+/// Map the names (without directories) of files to their positions in `files`, or to `None` if
+/// more than one has the name. Files that are `taken` are skipped.
+fn unique_names<'a>(
+    files: &[&'a (String, Version)],
+    taken: impl Fn(usize) -> bool,
+) -> HashMap<&'a str, Option<usize>> {
+    let mut names: HashMap<&str, Option<usize>> = HashMap::new();
+    for (i, (path, _)) in files.iter().enumerate().filter(|(i, _)| !taken(*i)) {
+        names
+            .entry(basename(path))
+            .and_modify(|found| *found = None)
+            .or_insert(Some(i));
+    }
+    names
+}
+
+fn basename(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Estimate how similar two files are, out of [`MAX_SCORE`], the way git does: by splitting them
+/// into lines (or 64 byte chunks of long lines) and counting how many bytes of the first are
+/// still in the second.
+fn similarity(source: &[u8], destination: &[u8]) -> u64 {
+    let max_size = source.len().max(destination.len()) as u64;
+    let min_size = source.len().min(destination.len()) as u64;
+    // Files that are too different in size can't be similar enough
+    if max_size * (MAX_SCORE - MIN_SCORE) < (max_size - min_size) * MAX_SCORE {
+        return 0;
+    }
+    if max_size == 0 {
+        return MAX_SCORE;
+    }
+
+    let source_spans = count_spans(source);
+    let destination_spans = count_spans(destination);
+    let copied: u64 = source_spans
+        .iter()
+        .map(|(hash, &count)| count.min(destination_spans.get(hash).copied().unwrap_or(0)))
+        .sum();
+    copied * MAX_SCORE / max_size
+}
+
+/// Hash each line (or 64 byte chunk of a longer one) in a file, counting the bytes that have each
+/// hash. Carriage returns before newlines are ignored in text files.
+fn count_spans(data: &[u8]) -> HashMap<u32, u64> {
+    const HASH_BASE: u32 = 107927;
+    let is_text = !data[..data.len().min(8000)].contains(&0);
+    let mut spans = HashMap::new();
+    let (mut accum1, mut accum2, mut len) = (0u32, 0u32, 0u64);
+    for (i, &byte) in data.iter().enumerate() {
+        if is_text && byte == b'\r' && data.get(i + 1) == Some(&b'\n') {
+            continue;
+        }
+        let old_accum1 = accum1;
+        accum1 = (accum1 << 7) ^ (accum2 >> 25);
+        accum2 = (accum2 << 7) ^ (old_accum1 >> 25);
+        accum1 = accum1.wrapping_add(byte as u32);
+        len += 1;
+        if len < 64 && byte != b'\n' {
+            continue;
+        }
+        let hash = accum1.wrapping_add(accum2.wrapping_mul(0x61)) % HASH_BASE;
+        *spans.entry(hash).or_insert(0) += len;
+        (accum1, accum2, len) = (0, 0, 0);
+    }
+    if len > 0 {
+        let hash = accum1.wrapping_add(accum2.wrapping_mul(0x61)) % HASH_BASE;
+        *spans.entry(hash).or_insert(0) += len;
+    }
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        new_entry, relative_path, similarity, BranchStatus, Change, Conflict, Status,
+        UntrackedFiles, Version, MAX_SCORE,
+    };
+
+    fn sample_status() -> Status {
+        let version = |hash: &str| Version {
+            mode: 0o100644,
+            hash: hash.repeat(20),
+        };
+        let mut modified = new_entry("src/lib.rs", Some(version("aa")), Some(version("bb")), None);
+        modified.staged = Some(Change::Modified);
+        modified.unstaged = Some(Change::Modified);
+        modified.worktree_mode = Some(0o100644);
+        let mut renamed = new_entry("new.txt", Some(version("cc")), Some(version("cc")), None);
+        renamed.staged = Some(Change::Renamed);
+        renamed.renamed_from = Some(("old.txt".to_owned(), 100));
+        let mut conflict = new_entry("merge.txt", None, None, None);
+        conflict.worktree_mode = Some(0o100644);
+        conflict.conflict = Some(Conflict {
+            stages: [
+                Some(version("11")),
+                Some(version("22")),
+                Some(version("33")),
+            ],
+        });
+        Status {
+            entries: vec![conflict, renamed, modified],
+            untracked: vec!["notes/".to_owned()],
+        }
+    }
+
+    fn empty_status() -> Status {
+        Status {
+            entries: Vec::new(),
+            untracked: Vec::new(),
+        }
+    }
+
+    fn branch(upstream: Option<(usize, usize)>) -> BranchStatus {
+        BranchStatus {
+            branch: Some("refs/heads/main".to_owned()),
+            head: Some("ab".repeat(20)),
+            upstream: Some(("refs/remotes/origin/main".to_owned(), upstream)),
+            merging: false,
+        }
+    }
+
+    fn output(print: impl FnOnce(&mut Vec<u8>) -> crate::error::Result<()>) -> String {
+        let mut output = Vec::new();
+        print(&mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_relative_path() {
+        assert_eq!(relative_path("src/lib.rs", ""), "src/lib.rs");
+        assert_eq!(relative_path("src/lib.rs", "src"), "lib.rs");
+        assert_eq!(relative_path("README", "src/bin"), "../../README");
+        assert_eq!(relative_path("src/bin/", "src/bin"), "./");
+    }
+
+    #[test]
+    fn test_print_short() {
+        let status = sample_status();
+        let branch = branch(Some((2, 1)));
+        assert_eq!(
+            output(|o| status.print_short(o, Some(&branch), "src")),
+            "## main...origin/main [ahead 2, behind 1]\n\
+             UU ../merge.txt\n\
+             R  ../old.txt -> ../new.txt\n\
+             MM lib.rs\n\
+             ?? ../notes/\n"
+        );
+        let gone = BranchStatus {
+            upstream: Some(("refs/remotes/origin/main".to_owned(), None)),
+            ..branch
+        };
+        assert_eq!(
+            output(|o| empty_status().print_short(o, Some(&gone), "")),
+            "## main...origin/main [gone]\n"
+        );
+    }
+
+    #[test]
+    fn test_print_porcelain_v2() {
+        let status = sample_status();
+        let (aa, bb, cc) = ("aa".repeat(20), "bb".repeat(20), "cc".repeat(20));
+        let (one, two, three) = ("11".repeat(20), "22".repeat(20), "33".repeat(20));
+        let expected = format!(
+            "# branch.oid {head}\n\
+             # branch.head main\n\
+             # branch.upstream origin/main\n\
+             # branch.ab +0 -3\n\
+             2 R. N... 100644 100644 100644 {cc} {cc} R100 new.txt\told.txt\n\
+             1 MM N... 100644 100644 100644 {aa} {bb} src/lib.rs\n\
+             u UU N... 100644 100644 100644 100644 {one} {two} {three} merge.txt\n\
+             ? notes/\n",
+            head = "ab".repeat(20),
+        );
+        let branch = branch(Some((0, 3)));
+        assert_eq!(
+            output(|o| status.print_porcelain_v2(o, Some(&branch))),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_print_long() {
+        let status = sample_status();
+        let branch = branch(Some((0, 0)));
+        let long = output(|o| status.print_long(o, &branch, "", UntrackedFiles::Normal));
+        assert!(long.starts_with(
+            "On branch main\n\
+             Your branch is up to date with 'origin/main'.\n\n\
+             Changes to be committed:\n"
+        ));
+        assert!(long.contains("\trenamed:    old.txt -> new.txt\n"));
+        assert!(long.contains("\tboth modified:   merge.txt\n"));
+        assert!(long.contains("\tmodified:   src/lib.rs\n"));
+        assert!(long.ends_with("\tnotes/\n\n"));
+
+        let clean = output(|o| empty_status().print_long(o, &branch, "", UntrackedFiles::No));
+        assert!(clean.ends_with("nothing to commit (use -u to show untracked files)\n"));
+    }
+
+    #[test]
+    fn test_similarity() {
+        let lines = |range: std::ops::RangeInclusive<u32>| {
+            range.map(|i| format!("{}\n", i)).collect::<String>()
+        };
+        assert_eq!(similarity(b"", b""), MAX_SCORE);
+        assert_eq!(similarity(b"same\n", b"same\n"), MAX_SCORE);
+        assert_eq!(similarity(b"same\n", b"same\r\n"), MAX_SCORE * 5 / 6);
+        // Appending a line keeps everything that was there
+        let score = similarity(lines(1..=50).as_bytes(), lines(1..=51).as_bytes());
+        assert_eq!(score, MAX_SCORE * 141 / 144);
+        // Too different in size to compare
+        assert_eq!(
+            similarity(lines(1..=10).as_bytes(), lines(1..=30).as_bytes()),
+            0
+        );
+        assert_eq!(similarity(b"abc\n", b"xyz\n"), 0);
+    }
+
+    #[test]
+    fn test_conflict_code() {
+        let version = Some(Version {
+            mode: 0o100644,
+            hash: "0".repeat(40),
+        });
+        let code = |base: bool, ours: bool, theirs: bool| {
+            let stages = [base, ours, theirs].map(|present| version.clone().filter(|_| present));
+            Conflict { stages }.code()
+        };
+        assert_eq!(code(true, true, true), "UU");
+        assert_eq!(code(false, true, true), "AA");
+        assert_eq!(code(true, false, false), "DD");
+        assert_eq!(code(true, true, false), "UD");
+        assert_eq!(code(true, false, true), "DU");
+        assert_eq!(code(false, true, false), "AU");
+        assert_eq!(code(false, false, true), "UA");
+    }
+}
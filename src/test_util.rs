@@ -0,0 +1,69 @@
+//! Fixtures shared by the unit tests.
+
+use std::{
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+use crate::{object::Signature, repository::Repository};
+
+/// A directory under the system's temporary directory, which is removed when it's dropped, even
+/// if the test using it fails.
+pub(crate) struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Create an empty directory named after `name` and this process, clearing out anything an
+    /// earlier run left there.
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+/// A new repository in a temporary directory, which is removed when it's dropped.
+pub(crate) struct TempRepo {
+    repo: Repository,
+    /// Kept to remove the directory, after the repository is dropped
+    _dir: TempDir,
+}
+
+impl TempRepo {
+    pub fn new(name: &str) -> Self {
+        let dir = TempDir::new(name);
+        let repo = Repository::init(dir.path()).unwrap();
+        TempRepo { repo, _dir: dir }
+    }
+}
+
+impl Deref for TempRepo {
+    type Target = Repository;
+
+    fn deref(&self) -> &Repository {
+        &self.repo
+    }
+}
+
+/// An author or committer for test commits, at the given time in UTC.
+pub(crate) fn signature(time: i64) -> Signature {
+    Signature {
+        name: b"A".to_vec(),
+        email: b"a@example.com".to_vec(),
+        time,
+        tz_offset: 0,
+        negative_utc: false,
+    }
+}
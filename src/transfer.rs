@@ -1,61 +1,81 @@
-use std::{fs, io::Write, path::PathBuf};
+//! Talking to remotes over git's smart HTTP protocol.
 
-use anyhow::Result;
-use flate2::Compression;
-use reqwest::StatusCode;
+use std::{fs, path::PathBuf};
 
-use crate::{object::Object, pack::parse_pack_file, util::parse_packet_lines};
+use reqwest::StatusCode;
 
+use crate::{
+    checkout::Checkout,
+    error::{Error, Result},
+    identity,
+    index::Index,
+    object::Object,
+    pack::parse_pack_file,
+    pack_index::PackIndex,
+    refs::{RefTransaction, NULL_HASH},
+    repository::Repository,
+    util::parse_packet_lines,
+};
+
+/// A ref advertised by a remote.
 #[derive(Debug)]
 pub struct Ref {
-    name: String,
-    hash: String,
+    /// Full name of the ref, e.g., `refs/heads/master`
+    pub name: String,
+    /// Hash of the object the ref points to
+    pub hash: String,
 }
 
 impl Ref {
+    /// Print the ref in the same format as `ls-remote`.
     pub fn print(&self) {
         println!("{} {}", self.hash, self.name);
     }
 }
 
+/// List the refs of a remote repository, along with the server's capabilities.
 pub fn get_refs(repo_url: &reqwest::Url) -> Result<(Vec<Ref>, Vec<String>)> {
     let client = reqwest::blocking::Client::new();
 
     let request = client.get(format!("{}/info/refs?service=git-upload-pack", repo_url));
     let resp = request.send()?;
-    let resp_headers = resp.headers();
-
-    assert!(resp.status() == StatusCode::OK || resp.status() == StatusCode::NOT_MODIFIED);
-    assert!(resp_headers.get("content-type").is_some());
-    assert_eq!(
-        resp_headers.get("content-type").unwrap().to_str().unwrap(),
-        "application/x-git-upload-pack-advertisement"
-    );
+    if resp.status() != StatusCode::OK && resp.status() != StatusCode::NOT_MODIFIED {
+        return Err(Error::HttpStatus(resp.status()));
+    }
+    check_content_type(&resp, "application/x-git-upload-pack-advertisement")?;
 
     let content = resp.bytes()?;
     let (rest, lines) = parse_packet_lines(&content)?;
-    assert_eq!(lines.len(), 1);
-    assert_eq!(lines[0], b"# service=git-upload-pack");
+    if lines != [b"# service=git-upload-pack"] {
+        return Err(Error::Protocol("missing service announcement".into()));
+    }
 
     let (rest, ref_lines) = parse_packet_lines(rest)?;
-    assert!(rest.is_empty());
+    if !rest.is_empty() {
+        return Err(Error::Protocol("unexpected data after refs".into()));
+    }
 
     let mut refs = Vec::new();
     let mut capabilities = Vec::new();
     for (i, ref_line) in ref_lines.iter().enumerate() {
-        let ref_line_str = std::str::from_utf8(ref_line)?;
+        let ref_line_str = std::str::from_utf8(ref_line)
+            .map_err(|_| Error::Protocol("invalid ref advertisement".into()))?;
         let ref_info = if let Some((ref_info, capabilities_str)) = ref_line_str.split_once('\0') {
             if i == 0 {
                 capabilities.extend(capabilities_str.split_whitespace().map(|s| s.to_owned()));
             } else {
-                anyhow::bail!("capabilities should only accompany first ref");
+                return Err(Error::Protocol(
+                    "capabilities should only accompany first ref".into(),
+                ));
             }
 
             ref_info
         } else {
             ref_line_str
         };
-        let (hash, name) = ref_info.split_once(' ').unwrap();
+        let (hash, name) = ref_info
+            .split_once(' ')
+            .ok_or_else(|| Error::Protocol(format!("invalid ref line '{}'", ref_info)))?;
         refs.push(Ref {
             hash: hash.to_owned(),
             name: name.to_owned(),
@@ -65,6 +85,18 @@ pub fn get_refs(repo_url: &reqwest::Url) -> Result<(Vec<Ref>, Vec<String>)> {
     Ok((refs, capabilities))
 }
 
+/// Check that a response has the expected content type.
+fn check_content_type(resp: &reqwest::blocking::Response, content_type: &str) -> Result<()> {
+    match resp.headers().get("content-type") {
+        Some(value) if value == content_type => Ok(()),
+        _ => Err(Error::Protocol(format!(
+            "expected content type {}",
+            content_type
+        ))),
+    }
+}
+
+/// Clone a remote repository into `directory`, and check out its `HEAD`.
 pub fn clone(repo_url: reqwest::Url, directory: PathBuf) -> Result<()> {
     let (refs, _capabilities) = get_refs(&repo_url)?;
     let wanted_refs = refs
@@ -84,7 +116,6 @@ pub fn clone(repo_url: reqwest::Url, directory: PathBuf) -> Result<()> {
             line.push_str(&MY_CAPABILITIES.join(" "));
         }
         let line_length = line.len() + 4 + 1; // add 4 for length string and 1 for trailing newline
-        assert!(line_length <= u16::MAX as usize);
         request_body.push_str(&format!("{:04x}{}\n", line_length, line));
     }
     request_body.push_str("0000");
@@ -97,102 +128,70 @@ pub fn clone(repo_url: reqwest::Url, directory: PathBuf) -> Result<()> {
         .header("content-type", "application/x-git-upload-pack-request")
         .body(request_body);
     let resp = request.send()?;
-    let resp_headers = resp.headers();
-
-    assert!(resp.status().is_success());
-    assert!(resp_headers.get("content-type").is_some());
-    assert_eq!(
-        resp_headers.get("content-type").unwrap().to_str().unwrap(),
-        "application/x-git-upload-pack-result"
-    );
+    if !resp.status().is_success() {
+        return Err(Error::HttpStatus(resp.status()));
+    }
+    check_content_type(&resp, "application/x-git-upload-pack-result")?;
 
     let data = &resp.bytes()?[..];
     let (rest, lines) = parse_packet_lines(data)?;
-    assert!(rest.is_empty());
-    assert_eq!(lines[0], b"NAK");
-    let pack_file = lines[1];
+    let pack_file = match (rest.is_empty(), lines.as_slice()) {
+        (true, [b"NAK", pack_file]) => *pack_file,
+        _ => return Err(Error::Protocol("expected NAK followed by a pack".into())),
+    };
     let objects = parse_pack_file(pack_file)?;
 
-    fs::create_dir_all(&directory)?;
+    let repo = Repository::init(&directory)?;
 
-    for (hash, object) in objects.iter() {
-        let mut path = PathBuf::from(&directory);
-        path.push(".git/objects");
-        path.push(&hash[0..2]);
-        fs::create_dir_all(&path)?;
-        path.push(&hash[2..40]);
+    let pack_checksum = hex::encode(&pack_file[pack_file.len() - 20..]);
+    let index = PackIndex::new(&objects, &pack_checksum);
 
-        let file = fs::File::create(path)?;
-        let mut encoder = flate2::write::ZlibEncoder::new(file, Compression::default());
-        encoder.write_all(&object.encode())?;
-    }
-
-    let mut path = PathBuf::from(&directory);
-    path.push(".git/refs");
-    fs::create_dir_all(path)?;
-
-    for ref_ in refs.iter() {
-        if ref_.name == "HEAD" {
-            continue;
-        }
-
-        let mut ref_path = PathBuf::from(&directory);
-        ref_path.push(".git");
-        ref_path.push(&ref_.name);
-
-        let ref_dir = ref_path.parent().unwrap();
-        fs::create_dir_all(ref_dir)?;
-
-        fs::write(ref_path, &ref_.hash)?;
-    }
+    let mut pack_path = repo.objects_dir();
+    pack_path.push("pack");
+    fs::create_dir_all(&pack_path)?;
+    pack_path.push(format!("pack-{}.pack", pack_checksum));
+    fs::write(&pack_path, pack_file)?;
+    fs::write(pack_path.with_extension("idx"), index.encode())?;
 
     let head_hash = refs
         .iter()
         .find(|r| r.name == "HEAD")
         .map(|r| &r.hash)
-        .unwrap();
+        .ok_or_else(|| Error::InvalidRef("HEAD".into()))?;
     let head_ref = refs
         .iter()
         .find(|r| r.hash == *head_hash && r.name != "HEAD")
-        .map(|r| &r.name)
-        .unwrap();
-
-    let mut path = PathBuf::from(&directory);
-    path.push(".git/HEAD");
-    fs::write(path, format!("ref: {}\n", head_ref))?;
-
-    let head_commit = Object::parse_from_hash(&directory, head_hash)?;
-    let head_tree_hash = match head_commit {
-        Object::Commit(commit) => commit.tree_hash,
-        _ => anyhow::bail!("HEAD points to non-commit object"),
-    };
-    let tree = match Object::parse_from_hash(&directory, &head_tree_hash)? {
-        Object::Tree(tree) => tree,
-        _ => unreachable!(),
-    };
-    let mut tree_entries = tree
+        .map(|r| &r.name);
+
+    // HEAD is pointed at its branch first, so the branch's creation goes in HEAD's reflog too
+    let mut transaction = RefTransaction::new(&repo);
+    transaction.no_deref(true);
+    match head_ref {
+        Some(head_ref) => repo.write_symbolic_ref("HEAD", head_ref)?,
+        // Detached HEAD
+        None => transaction.update("HEAD", head_hash, Some(NULL_HASH)),
+    }
+    // Peeled tags are advertised as `<tag>^{}`, but they aren't refs in their own right
+    for ref_ in refs
         .iter()
-        .map(|te| (PathBuf::from(&directory), te.clone()))
-        .collect::<Vec<_>>();
-    while let Some((parent_dir, tree_entry)) = tree_entries.pop() {
-        let object = Object::parse_from_hash(&directory, &tree_entry.hash)?;
-        match object {
-            Object::Blob(content) => {
-                let mut object_path = parent_dir;
-                object_path.push(&tree_entry.name);
-                fs::write(object_path, &content)?;
-            }
-            Object::Tree(tree) => {
-                let mut tree_path = parent_dir;
-                tree_path.push(&tree_entry.name);
-                fs::create_dir(&tree_path)?;
-                tree_entries.extend(tree.iter().map(|te| (tree_path.clone(), te.to_owned())));
-            }
-            _ => unreachable!(),
+        .filter(|r| r.name != "HEAD" && !r.name.ends_with("^{}"))
+    {
+        // Only branches are fetched, so other refs (like tags) might point to objects we don't
+        // have, and would be broken
+        if repo.has_object(&ref_.hash)? {
+            transaction.update(&ref_.name, &ref_.hash, Some(NULL_HASH));
         }
     }
+    // Cloning shouldn't fail just because there's nobody to record in the reflogs
+    if let Ok(committer) = identity::committer(&repo) {
+        transaction.reflog(committer, &format!("clone: from {}", repo_url));
+    }
+    transaction.commit()?;
 
-    // TODO: Write index
+    let head_commit = Object::read_commit(&repo, head_hash)?;
+    let mut index = Index::new();
+    Checkout::new(&repo, &index, None, &head_commit.tree_hash, true)?.apply(&repo, &mut index)?;
+    index.write(&repo)?;
 
     Ok(())
 }
@@ -1,4 +1,11 @@
-use anyhow::Result;
+use std::io::ErrorKind;
+
+use crate::error::{Error, Result};
+
+/// The `ENOTDIR` and `EISDIR` error numbers, which are the same on Linux, macOS and the BSDs.
+/// `ErrorKind` only has variants for them from Rust 1.83.
+const ENOTDIR: i32 = 20;
+const EISDIR: i32 = 21;
 
 /// Parse packet line data until a flush packet ("0000") or a pack file is found
 pub fn parse_packet_lines(input: &[u8]) -> Result<(&[u8], Vec<&[u8]>)> {
@@ -6,6 +13,10 @@ pub fn parse_packet_lines(input: &[u8]) -> Result<(&[u8], Vec<&[u8]>)> {
 
     let mut lines = Vec::new();
     loop {
+        if rest.len() < 4 {
+            return Err(Error::Protocol("unexpected end of packet lines".into()));
+        }
+
         if &rest[0..4] == b"PACK" {
             // Found a pack file, assume rest of response is that file
             lines.push(rest);
@@ -13,20 +24,27 @@ pub fn parse_packet_lines(input: &[u8]) -> Result<(&[u8], Vec<&[u8]>)> {
             break;
         }
 
-        let line_length = u16::from_str_radix(std::str::from_utf8(&rest[0..4])?, 16)? as usize;
+        let line_length = std::str::from_utf8(&rest[0..4])
+            .ok()
+            .and_then(|s| u16::from_str_radix(s, 16).ok())
+            .ok_or_else(|| Error::Protocol("invalid packet line length".into()))?
+            as usize;
         rest = &rest[4..];
 
         if line_length == 0 {
             // Flush packet
             break;
         }
+        if line_length < 4 || line_length - 4 > rest.len() {
+            return Err(Error::Protocol(format!(
+                "invalid packet line length {}",
+                line_length
+            )));
+        }
 
-        let line = if rest[line_length - 5] == b'\n' {
-            // Ignore trailing newlines but don't require them
-            &rest[0..line_length - 5]
-        } else {
-            &rest[0..line_length - 4]
-        };
+        let line = &rest[0..line_length - 4];
+        // Ignore trailing newlines but don't require them
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
         lines.push(line);
         rest = &rest[line_length - 4..];
     }
@@ -37,22 +55,142 @@ pub fn parse_packet_lines(input: &[u8]) -> Result<(&[u8], Vec<&[u8]>)> {
 /// Parse a variable-length encoded integer.
 pub fn parse_size(input: &[u8]) -> Result<(&[u8], usize)> {
     let mut i = 0;
-    let mut value: usize = (input[i] as usize) & 0x0f;
+    let mut value: usize = (byte_at(input, i)? as usize) & 0x7f;
     while high_bit(input[i]) {
         i += 1;
-        value |= ((input[i] as usize) & 0x7f) << 7;
+        let shift = 7 * i as u32;
+        if shift >= usize::BITS {
+            return Err(Error::CorruptPack("size is too large".into()));
+        }
+        value |= ((byte_at(input, i)? as usize) & 0x7f) << shift;
     }
     let input = &input[i + 1..];
     Ok((input, value))
 }
 
+/// Parse the negative offset of an offset delta's base object.
+///
+/// Unlike sizes, each continuation byte adds one before shifting, so that there are no
+/// redundant encodings of the same offset.
+pub fn parse_offset(input: &[u8]) -> Result<(&[u8], usize)> {
+    let mut i = 0;
+    let mut value: usize = (byte_at(input, i)? as usize) & 0x7f;
+    while high_bit(input[i]) {
+        i += 1;
+        value = value
+            .checked_add(1)
+            .and_then(|v| v.checked_mul(1 << 7))
+            .ok_or_else(|| Error::CorruptPack("offset is too large".into()))?
+            | ((byte_at(input, i)? as usize) & 0x7f);
+    }
+    let input = &input[i + 1..];
+    Ok((input, value))
+}
+
+/// Encode an offset as [`parse_offset`] reads it, which is also how version 4 index files
+/// compress paths.
+pub fn encode_offset(output: &mut Vec<u8>, mut value: usize) {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    value >>= 7;
+    while value != 0 {
+        value -= 1;
+        bytes.push(0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+    output.extend(bytes.iter().rev());
+}
+
+/// Read a big-endian 32-bit integer from the start of some data, which must be long enough.
+pub fn read_u32(input: &[u8]) -> u32 {
+    u32::from_be_bytes([input[0], input[1], input[2], input[3]])
+}
+
+/// Get the byte at index `i` of some pack data, which might have been truncated.
+pub fn byte_at(input: &[u8], i: usize) -> Result<u8> {
+    input
+        .get(i)
+        .copied()
+        .ok_or_else(|| Error::CorruptPack("unexpected end of data".into()))
+}
+
+/// Calculate the CRC-32 checksum of some data, as stored in pack index files.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data.iter() {
+        crc = CRC32_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut value = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            value = if value & 1 != 0 {
+                0xedb88320 ^ (value >> 1)
+            } else {
+                value >> 1
+            };
+            bit += 1;
+        }
+        table[i] = value;
+        i += 1;
+    }
+    table
+}
+
+/// Whether a string is a full, lowercase hex SHA-1 hash.
+pub fn is_hash(input: &str) -> bool {
+    input.len() == 40
+        && input
+            .bytes()
+            .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// Whether a string is a well-formed full ref name, following the rules of
+/// `git check-ref-format`.
+pub fn is_valid_ref_name(name: &str) -> bool {
+    name.starts_with("refs/")
+        && !name.ends_with('/')
+        && !name.ends_with('.')
+        && !name.contains("..")
+        && !name.contains("@{")
+        && !name.contains("//")
+        && !name
+            .bytes()
+            .any(|b| b < 0x20 || b == 0x7f || b" ~^:?*[\\".contains(&b))
+        && name
+            .split('/')
+            .all(|c| !c.starts_with('.') && !c.ends_with(".lock"))
+}
+
 pub fn high_bit(byte: u8) -> bool {
     (byte & 0x80) >> 7 != 0
 }
 
+/// Whether reading a file failed because it isn't there, including if part of its path is now a
+/// file rather than a directory.
+pub fn is_missing(err: &std::io::Error) -> bool {
+    err.kind() == ErrorKind::NotFound || err.raw_os_error() == Some(ENOTDIR)
+}
+
+/// Whether reading a file failed because it's a directory.
+pub fn is_directory(err: &std::io::Error) -> bool {
+    err.raw_os_error() == Some(EISDIR)
+}
+
 #[cfg(test)]
 mod tests {
-    use super::parse_packet_lines;
+    use super::{
+        crc32, encode_offset, is_directory, is_missing, is_valid_ref_name, parse_offset,
+        parse_packet_lines, parse_size,
+    };
+    use crate::{error::Error, test_util::TempDir};
 
     #[test]
     fn test_parse_packet_lines() {
@@ -65,4 +203,84 @@ mod tests {
             "# service=git-upload-pack"
         );
     }
+
+    #[test]
+    fn test_parse_truncated_packet_lines() {
+        assert!(matches!(
+            parse_packet_lines(b"001e# service=git"),
+            Err(Error::Protocol(_))
+        ));
+        assert!(matches!(
+            parse_packet_lines(b"0002"),
+            Err(Error::Protocol(_))
+        ));
+        assert!(matches!(parse_packet_lines(b"00"), Err(Error::Protocol(_))));
+    }
+
+    #[test]
+    fn test_parse_size() {
+        let (rest, size) = parse_size(&[0x0e]).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(size, 14);
+
+        let (rest, size) = parse_size(&[0xe5, 0x8e, 0x26, 0x01]).unwrap();
+        assert_eq!(rest, &[0x01]);
+        assert_eq!(size, 624485);
+
+        assert!(matches!(parse_size(&[0x80]), Err(Error::CorruptPack(_))));
+    }
+
+    #[test]
+    fn test_parse_offset() {
+        let (rest, offset) = parse_offset(&[0x4a]).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(offset, 74);
+
+        let (rest, offset) = parse_offset(&[0x81, 0x00, 0xff]).unwrap();
+        assert_eq!(rest, &[0xff]);
+        assert_eq!(offset, 256);
+    }
+
+    #[test]
+    fn test_encode_offset() {
+        for value in [0, 1, 127, 128, 16511, 16512, 1 << 30] {
+            let mut encoded = Vec::new();
+            encode_offset(&mut encoded, value);
+            let (rest, offset) = parse_offset(&encoded).unwrap();
+            assert!(rest.is_empty());
+            assert_eq!(offset, value);
+        }
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+    }
+
+    #[test]
+    fn test_is_valid_ref_name() {
+        assert!(is_valid_ref_name("refs/heads/master"));
+        assert!(is_valid_ref_name("refs/tags/v1.0"));
+        assert!(!is_valid_ref_name("HEAD"));
+        assert!(!is_valid_ref_name("refs/heads/../../config"));
+        assert!(!is_valid_ref_name("refs/heads/.hidden"));
+        assert!(!is_valid_ref_name("refs/heads/a.lock"));
+        assert!(!is_valid_ref_name("refs/heads/a b"));
+        assert!(!is_valid_ref_name("refs/heads/a@{1}"));
+        assert!(!is_valid_ref_name("refs/heads/"));
+    }
+
+    #[test]
+    fn test_is_missing() {
+        let dir = TempDir::new("util-missing");
+        std::fs::write(dir.path().join("file"), "").unwrap();
+        let read = |path: &str| std::fs::read(dir.path().join(path)).unwrap_err();
+
+        assert!(is_missing(&read("missing")));
+        assert!(is_missing(&read("file/inside")));
+        assert!(!is_directory(&read("missing")));
+        assert!(is_directory(&read(".")));
+        assert!(!is_missing(&read(".")));
+    }
 }
@@ -0,0 +1,277 @@
+//! Matching paths against shell-style glob patterns, with git's rules for `**`.
+
+/// The result of matching part of a pattern, which lets a `*` give up early when trying later
+/// positions can't possibly help.
+#[derive(Debug, PartialEq)]
+enum Match {
+    Matched,
+    NotMatched,
+    /// Nothing after this point can match
+    AbortAll,
+    /// Only a `**` earlier in the pattern could still make this match
+    AbortToStarStar,
+}
+
+#[derive(Clone, Copy)]
+struct Options {
+    ignore_case: bool,
+    /// Whether wildcards stop at slashes
+    pathname: bool,
+}
+
+/// Whether `text` matches the glob `pattern`, where `*`, `?` and `[...]` don't match `/`, and
+/// `**` between slashes (or at the start or end) matches any number of directories.
+pub(crate) fn wildmatch(pattern: &str, text: &str, ignore_case: bool) -> bool {
+    let options = Options {
+        ignore_case,
+        pathname: true,
+    };
+    do_match(pattern.as_bytes(), text.as_bytes(), options) == Match::Matched
+}
+
+/// Whether `text` matches the glob `pattern`, where wildcards match `/` like any other
+/// character, as in pathspecs.
+pub(crate) fn fnmatch(pattern: &str, text: &str) -> bool {
+    let options = Options {
+        ignore_case: false,
+        pathname: false,
+    };
+    do_match(pattern.as_bytes(), text.as_bytes(), options) == Match::Matched
+}
+
+fn do_match(pattern: &[u8], text: &[u8], options: Options) -> Match {
+    let ignore_case = options.ignore_case;
+    let eq = |a: u8, b: u8| a == b || (ignore_case && a.eq_ignore_ascii_case(&b));
+
+    let (mut p, mut t) = (0, 0);
+    while p < pattern.len() {
+        let pattern_char = pattern[p];
+        if t == text.len() && pattern_char != b'*' {
+            return Match::AbortAll;
+        }
+        let text_char = text.get(t).copied().unwrap_or(0);
+
+        match pattern_char {
+            b'\\' => {
+                // Escaped literal
+                p += 1;
+                match pattern.get(p) {
+                    Some(c) if eq(*c, text_char) => (),
+                    _ => return Match::NotMatched,
+                }
+            }
+            b'?' => {
+                if options.pathname && text_char == b'/' {
+                    return Match::NotMatched;
+                }
+            }
+            b'*' => {
+                let star_start = p;
+                p += 1;
+                let mut match_slash = !options.pathname;
+                if options.pathname && pattern.get(p) == Some(&b'*') {
+                    while pattern.get(p) == Some(&b'*') {
+                        p += 1;
+                    }
+                    let at_start = star_start == 0 || pattern[star_start - 1] == b'/';
+                    let at_end = p == pattern.len() || pattern[p] == b'/';
+                    if at_start && at_end {
+                        // `**/` can also match no directories at all
+                        if p < pattern.len()
+                            && do_match(&pattern[p + 1..], &text[t..], options) == Match::Matched
+                        {
+                            return Match::Matched;
+                        }
+                        match_slash = true;
+                    }
+                }
+
+                if p == pattern.len() {
+                    // A trailing star matches the rest of the text, unless it crosses a slash
+                    if !match_slash && text[t..].contains(&b'/') {
+                        return Match::AbortToStarStar;
+                    }
+                    return Match::Matched;
+                } else if !match_slash && pattern[p] == b'/' {
+                    // `*/` can only match up to the next slash
+                    match text[t..].iter().position(|c| *c == b'/') {
+                        Some(slash) => {
+                            t += slash;
+                            continue;
+                        }
+                        None => return Match::AbortToStarStar,
+                    }
+                }
+
+                while t < text.len() {
+                    match do_match(&pattern[p..], &text[t..], options) {
+                        Match::NotMatched => {
+                            if !match_slash && text[t] == b'/' {
+                                return Match::AbortToStarStar;
+                            }
+                        }
+                        Match::AbortToStarStar if match_slash => (),
+                        result => return result,
+                    }
+                    t += 1;
+                }
+                return Match::AbortAll;
+            }
+            b'[' => {
+                let (end, matched) = match match_class(pattern, p + 1, text_char, ignore_case) {
+                    Some(result) => result,
+                    None => return Match::AbortAll,
+                };
+                if !matched || (options.pathname && text_char == b'/') {
+                    return Match::NotMatched;
+                }
+                p = end;
+            }
+            c => {
+                if !eq(c, text_char) {
+                    return Match::NotMatched;
+                }
+            }
+        }
+
+        p += 1;
+        t += 1;
+    }
+
+    if t == text.len() {
+        Match::Matched
+    } else {
+        Match::NotMatched
+    }
+}
+
+/// Match a character against the bracket expression starting at `p` (just after the `[`),
+/// returning the index of its closing `]` and whether it matched, or `None` if it isn't closed.
+fn match_class(pattern: &[u8], mut p: usize, c: u8, ignore_case: bool) -> Option<(usize, bool)> {
+    let negated = matches!(pattern.get(p), Some(b'!' | b'^'));
+    if negated {
+        p += 1;
+    }
+
+    let mut matched = false;
+    let mut previous = None;
+    let mut first = true;
+    loop {
+        let mut class_char = *pattern.get(p)?;
+        if class_char == b']' && !first {
+            break;
+        }
+        first = false;
+
+        if class_char == b'\\' {
+            p += 1;
+            class_char = *pattern.get(p)?;
+        } else if class_char == b'-'
+            && previous.is_some()
+            && !matches!(pattern.get(p + 1), None | Some(b']'))
+        {
+            p += 1;
+            let mut high = pattern[p];
+            if high == b'\\' {
+                p += 1;
+                high = *pattern.get(p)?;
+            }
+            let low = previous.unwrap();
+            let in_range = |c: u8| low <= c && c <= high;
+            if in_range(c)
+                || (ignore_case
+                    && (in_range(c.to_ascii_lowercase()) || in_range(c.to_ascii_uppercase())))
+            {
+                matched = true;
+            }
+            previous = None;
+            p += 1;
+            continue;
+        } else if class_char == b'[' && pattern.get(p + 1) == Some(&b':') {
+            let name_start = p + 2;
+            let name_len = pattern[name_start..].windows(2).position(|w| w == b":]")?;
+            let name = &pattern[name_start..name_start + name_len];
+            if match_named_class(name, c, ignore_case)? {
+                matched = true;
+            }
+            previous = None;
+            p = name_start + name_len + 2;
+            continue;
+        }
+
+        if class_char == c || (ignore_case && class_char.eq_ignore_ascii_case(&c)) {
+            matched = true;
+        }
+        previous = Some(class_char);
+        p += 1;
+    }
+
+    Some((p, matched != negated))
+}
+
+/// Match a character against a class like `[:alpha:]`, or `None` if the class is unknown.
+fn match_named_class(name: &[u8], c: u8, ignore_case: bool) -> Option<bool> {
+    Some(match name {
+        b"alnum" => c.is_ascii_alphanumeric(),
+        b"alpha" => c.is_ascii_alphabetic(),
+        b"blank" => c == b' ' || c == b'\t',
+        b"cntrl" => c.is_ascii_control(),
+        b"digit" => c.is_ascii_digit(),
+        b"graph" => c.is_ascii_graphic(),
+        b"lower" => c.is_ascii_lowercase() || (ignore_case && c.is_ascii_uppercase()),
+        b"print" => c.is_ascii_graphic() || c == b' ',
+        b"punct" => c.is_ascii_punctuation(),
+        b"space" => c.is_ascii_whitespace() || c == 0x0b,
+        b"upper" => c.is_ascii_uppercase() || (ignore_case && c.is_ascii_lowercase()),
+        b"xdigit" => c.is_ascii_hexdigit(),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{fnmatch, wildmatch};
+
+    #[test]
+    fn test_wildmatch() {
+        let cases = [
+            ("foo", "foo", true),
+            ("foo", "bar", false),
+            ("*.txt", "a.txt", true),
+            ("*.txt", "dir/a.txt", false),
+            ("?", "/", false),
+            ("a/*/c", "a/b/c", true),
+            ("a/*/c", "a/b/b/c", false),
+            ("**/foo", "foo", true),
+            ("**/foo", "a/b/foo", true),
+            ("a/**/b", "a/b", true),
+            ("a/**/b", "a/x/y/b", true),
+            ("a/**", "a/x/y", true),
+            ("a**b", "a/b", false),
+            ("[a-c]x", "bx", true),
+            ("[!a-c]x", "bx", false),
+            ("[[:digit:]]", "5", true),
+            ("[]]", "]", true),
+            ("\\*", "*", true),
+            ("\\*", "a", false),
+            ("[a", "a", false),
+        ];
+        for (pattern, text, expected) in cases {
+            assert_eq!(
+                wildmatch(pattern, text, false),
+                expected,
+                "{} {}",
+                pattern,
+                text
+            );
+        }
+
+        assert!(wildmatch("*.TXT", "a.txt", true));
+        assert!(wildmatch("[A-C]", "b", true));
+        assert!(!wildmatch("*.TXT", "a.txt", false));
+
+        assert!(fnmatch("*.txt", "dir/a.txt"));
+        assert!(fnmatch("d?r/*", "d/r/a"));
+        assert!(!fnmatch("*.txt", "a.md"));
+    }
+}
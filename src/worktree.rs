@@ -0,0 +1,279 @@
+//! Walking the working tree, and comparing its files with the index.
+
+use std::{
+    fs::{self, Metadata},
+    path::Path,
+};
+
+use crate::{
+    config::Config,
+    error::Result,
+    ignore::Ignore,
+    index::{self, mode_from_metadata, Index, IndexEntry},
+    object::Object,
+    pathspec::Pathspec,
+    repository::Repository,
+    util::is_missing,
+};
+
+/// Which file modes the filesystem can record, from `core.fileMode` and `core.symlinks`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileModes {
+    /// Whether files' executable bits can be trusted
+    pub executable_bit: bool,
+    /// Whether symlinks can be created, rather than checking them out as plain files containing
+    /// their targets
+    pub symlinks: bool,
+}
+
+impl Default for FileModes {
+    fn default() -> Self {
+        FileModes {
+            executable_bit: true,
+            symlinks: true,
+        }
+    }
+}
+
+impl FileModes {
+    /// Read `core.fileMode` and `core.symlinks`, which are both true by default.
+    pub fn load(repo: &Repository) -> Result<Self> {
+        let config = Config::load(Some(repo))?;
+        Ok(FileModes {
+            executable_bit: config.get_bool("core.fileMode")?.unwrap_or(true),
+            symlinks: config.get_bool("core.symlinks")?.unwrap_or(true),
+        })
+    }
+
+    /// The mode to stage for a file, given its metadata and the mode it's staged with already, if
+    /// any. Where the filesystem can't be trusted, the staged executable bit is kept, and so is a
+    /// symlink that had to be checked out as a plain file.
+    pub fn mode(&self, metadata: &Metadata, staged: Option<u32>) -> u32 {
+        let mode = mode_from_metadata(metadata);
+        match staged {
+            Some(0o120000) if !self.symlinks && mode & 0o170000 == 0o100000 => 0o120000,
+            Some(staged @ (0o100644 | 0o100755))
+                if !self.executable_bit && mode & 0o170000 == 0o100000 =>
+            {
+                staged
+            }
+            None if !self.executable_bit && mode & 0o170000 == 0o100000 => 0o100644,
+            _ => mode,
+        }
+    }
+}
+
+/// List the files in the working tree, as paths from its root with `/` separators, sorted in the
+/// same order as the index.
+///
+/// The `.git` directory, and any nested repositories, are skipped, as are ignored files and
+/// directories if `ignore` is given.
+pub fn walk(work_tree: &Path, ignore: Option<&Ignore>) -> Result<Vec<String>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![String::new()];
+    while let Some(dir) = dirs.pop() {
+        for entry in fs::read_dir(work_tree.join(&dir))? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let name = match name.to_str() {
+                Some(".git") | None => continue,
+                Some(name) => name,
+            };
+            let path = if dir.is_empty() {
+                name.to_owned()
+            } else {
+                format!("{}/{}", dir, name)
+            };
+
+            let is_dir = entry.file_type()?.is_dir();
+            if let Some(ignore) = ignore {
+                if ignore.is_ignored(&path, is_dir)? {
+                    continue;
+                }
+            }
+            if is_dir {
+                if !entry.path().join(".git").exists() {
+                    dirs.push(path);
+                }
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Whether the file for an index entry differs from what is staged, including if it has been
+/// deleted. Files whose stat data is unchanged are assumed not to have changed, unless they
+/// were modified in the same instant as the index was written.
+pub fn is_modified(
+    work_tree: &Path,
+    entry: &IndexEntry,
+    index_mtime: (u32, u32),
+    modes: &FileModes,
+) -> Result<bool> {
+    let path = work_tree.join(&entry.path);
+    let metadata = match fs::symlink_metadata(&path) {
+        Ok(metadata) => metadata,
+        Err(err) if is_missing(&err) => return Ok(true),
+        Err(err) => return Err(err.into()),
+    };
+    if modes.mode(&metadata, Some(entry.mode)) != entry.mode {
+        return Ok(true);
+    }
+    // Nested repositories aren't looked inside
+    if entry.mode == 0o160000 {
+        return Ok(false);
+    }
+    if !entry.stat_changed(&metadata) && !entry.is_racily_clean(index_mtime) {
+        return Ok(false);
+    }
+    Ok(Object::new_from_path(&path)?.hash() != entry.hash)
+}
+
+/// The changes `add` makes to the index, for the files a pathspec matches.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Staging {
+    /// New or changed files to hash and stage
+    pub additions: Vec<String>,
+    /// Tracked files that have been deleted
+    pub removals: Vec<String>,
+    /// Whether each of the pathspec's patterns matched anything
+    pub matched: Vec<bool>,
+}
+
+/// Work out what `add` has to stage to bring the index in line with the files a pathspec
+/// matches. With `tracked_only` (as in `add -u`), new files are left out. Ignored files are left
+/// out if `ignore` is given, unless they're already tracked. Files with merge conflicts are
+/// always added, to resolve the conflict.
+pub fn changes_to_stage(
+    repo: &Repository,
+    index: &Index,
+    pathspec: &Pathspec,
+    ignore: Option<&Ignore>,
+    tracked_only: bool,
+    modes: &FileModes,
+) -> Result<Staging> {
+    let work_tree = repo.work_tree()?;
+    let index_mtime = index::index_mtime(&repo.index_path());
+    let mut staging = Staging {
+        matched: vec![false; pathspec.patterns().len()],
+        ..Default::default()
+    };
+
+    let walked = walk(work_tree, ignore)?;
+    for path in walked.iter() {
+        let pathspec_match = match pathspec.matches(path) {
+            Some(pathspec_match) => pathspec_match,
+            None => continue,
+        };
+        staging.matched[pathspec_match.pattern] = true;
+
+        let changed = match index.find(path, 0) {
+            Some(entry) => is_modified(work_tree, entry, index_mtime, modes)?,
+            None => !tracked_only || index.entries().iter().any(|e| &e.path == path),
+        };
+        if changed {
+            staging.additions.push(path.clone());
+        }
+    }
+
+    // Tracked files the walk skipped, because they've been deleted or are ignored
+    for (entry, pathspec_match) in index.matching(pathspec) {
+        staging.matched[pathspec_match.pattern] = true;
+        let deleted = fs::symlink_metadata(work_tree.join(&entry.path)).is_err();
+        if deleted {
+            staging.removals.push(entry.path.clone());
+        } else if entry.stage == 0
+            && walked.binary_search(&entry.path).is_err()
+            && is_modified(work_tree, entry, index_mtime, modes)?
+        {
+            staging.additions.push(entry.path.clone());
+        }
+    }
+    Ok(staging)
+}
+
+/// Hash a file in the working tree into the object store, and stage it.
+pub fn add_file(repo: &Repository, index: &mut Index, path: &str, modes: &FileModes) -> Result<()> {
+    let file_path = repo.work_tree()?.join(path);
+    let metadata = fs::symlink_metadata(&file_path)?;
+    let object = Object::new_from_path(&file_path)?;
+    object.add(repo)?;
+    let mode = modes.mode(&metadata, index.find(path, 0).map(|e| e.mode));
+    index.add(IndexEntry::new(path, &object.hash(), mode, &metadata));
+    Ok(())
+}
+
+/// Remove a file, and then any directories it leaves empty, up to the root of the working tree.
+/// Gitlinks are checked out as directories, which are only removed if they're empty.
+pub fn remove_file(work_tree: &Path, path: &str) -> Result<()> {
+    let file_path = work_tree.join(path);
+    if fs::symlink_metadata(&file_path).is_ok_and(|m| m.is_dir()) {
+        let _ = fs::remove_dir(&file_path);
+    } else {
+        match fs::remove_file(&file_path) {
+            Ok(()) => (),
+            Err(err) if is_missing(&err) => (),
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    let mut dir = Path::new(path).parent();
+    while let Some(parent) = dir.filter(|d| !d.as_os_str().is_empty()) {
+        if fs::remove_dir(work_tree.join(parent)).is_err() {
+            break;
+        }
+        dir = parent.parent();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{add_file, changes_to_stage, FileModes, Staging};
+    use crate::{ignore::Ignore, index::Index, pathspec::Pathspec, test_util::TempRepo};
+
+    #[test]
+    fn test_changes_to_stage() {
+        let repo = TempRepo::new("worktree-staging");
+        let work_tree = repo.work_tree().unwrap();
+        let modes = FileModes::default();
+        let mut index = Index::new();
+        for path in ["changed", "deleted", "same", "tracked.log"] {
+            std::fs::write(work_tree.join(path), path).unwrap();
+            add_file(&repo, &mut index, path, &modes).unwrap();
+        }
+        std::fs::write(work_tree.join("changed"), "new content").unwrap();
+        std::fs::remove_file(work_tree.join("deleted")).unwrap();
+        std::fs::write(work_tree.join("new"), "new").unwrap();
+        std::fs::write(work_tree.join("tracked.log"), "new content").unwrap();
+        std::fs::write(work_tree.join("untracked.log"), "new").unwrap();
+        std::fs::write(work_tree.join(".gitignore"), "*.log\n").unwrap();
+
+        let pathspec = Pathspec::from_patterns(&["", "missing"]);
+        let ignore = Ignore::new(&repo).unwrap();
+        let staging =
+            changes_to_stage(&repo, &index, &pathspec, Some(&ignore), false, &modes).unwrap();
+        // Tracked files are updated even if they're ignored
+        assert_eq!(
+            staging,
+            Staging {
+                additions: vec![
+                    ".gitignore".into(),
+                    "changed".into(),
+                    "new".into(),
+                    "tracked.log".into()
+                ],
+                removals: vec!["deleted".into()],
+                matched: vec![true, false],
+            }
+        );
+
+        // New files are left out with `add -u`
+        let staging = changes_to_stage(&repo, &index, &pathspec, None, true, &modes).unwrap();
+        assert_eq!(staging.additions, ["changed", "tracked.log"]);
+        assert_eq!(staging.removals, ["deleted"]);
+    }
+}